@@ -0,0 +1,557 @@
+use std::collections::HashMap;
+use std::path::Path;
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+#[cfg(target_os = "macos")]
+use std::process::Command;
+
+use chrono::{DateTime, Utc};
+use rdev::Key;
+use sysinfo::{ProcessRefreshKind, System, UpdateKind};
+
+#[cfg(target_os = "linux")]
+use crate::clipboard_linux::LinuxClipboard;
+#[cfg(target_os = "linux")]
+use crate::x11_window::X11Windows;
+use crate::keyboard::RdevKeyboard;
+use crate::models::{MonitoringConfig, Result};
+#[cfg(target_os = "macos")]
+use crate::models::PromptHistError;
+
+/// Which selection captured text came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Selection {
+    /// Text that was copied
+    Clipboard,
+    /// Text that is merely selected, pasted with the middle mouse button (X11 and Wayland)
+    Primary,
+}
+
+/// An open browser tab
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrowserTab {
+    pub browser: String,
+    pub title: String,
+    pub url: String,
+}
+
+/// A running desktop application
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunningApp {
+    pub name: String, // Application name as used in `monitored_applications`
+    pub process_name: String,
+    pub pid: u32,
+    pub started_at: Option<DateTime<Utc>>,
+}
+
+/// The window that has keyboard focus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActiveWindow {
+    pub process_name: String, // Application or window class owning the window
+    pub title: String,
+    pub pid: Option<u32>,
+}
+
+/// A key going down or up
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keystroke {
+    pub key: Key,
+    pub pressed: bool,
+    pub text: Option<String>, // What the press types in the current layout, if anything
+}
+
+/// Executable names of AI tools and the application each belongs to. A name also
+/// matches with a suffix after `-`, `_`, `.` or a space, like `code-insiders`.
+const KNOWN_PROCESSES: &[(&str, &str)] = &[
+    ("cursor", "Cursor"),
+    ("ollama", "Ollama"),
+    ("lm-studio", "LM Studio"),
+    ("lm studio", "LM Studio"),
+    ("lmstudio", "LM Studio"),
+    ("code", "VS Code"),
+    ("claude", "Claude"),
+    ("chatgpt", "ChatGPT"),
+    ("perplexity", "Perplexity"),
+    ("grok", "Grok"),
+];
+
+// Launchers whose first argument names the program actually run, like `node /usr/bin/claude`
+const LAUNCHERS: &[&str] = &["node", "electron", "python", "python3"];
+
+/// Where the monitor reads copied text from
+pub trait ClipboardSource: Send {
+    /// Text of every selection that changed since the last call
+    fn changes(&mut self) -> Result<Vec<(Selection, String)>>;
+
+    /// Text on the clipboard right now, used to fill in pastes
+    fn current(&mut self) -> Result<String>;
+}
+
+/// Open windows, used to spot LLM web apps and the conversation a prompt belongs to
+pub trait WindowSource: Send {
+    fn browser_tabs(&mut self) -> Result<Vec<BrowserTab>>;
+
+    /// The focused window, None when nothing has focus or it can't be told
+    fn active_window(&mut self) -> Result<Option<ActiveWindow>>;
+}
+
+/// Running processes, used to spot LLM desktop apps
+pub trait ProcessSource: Send {
+    /// AI tools that are running, one entry per application
+    fn running_applications(&mut self) -> Result<Vec<RunningApp>>;
+}
+
+/// Keys typed anywhere on the system, used to rebuild prompts as they are submitted
+pub trait KeyboardSource: Send {
+    /// Keystrokes since the last call. Nothing is recorded before the first call.
+    fn keystrokes(&mut self) -> Result<Vec<Keystroke>>;
+}
+
+/// Everything a `SystemMonitor` captures from
+pub struct CaptureSources {
+    pub clipboard: Box<dyn ClipboardSource>,
+    pub windows: Box<dyn WindowSource>,
+    pub processes: Box<dyn ProcessSource>,
+    pub keyboard: Box<dyn KeyboardSource>,
+}
+
+/// Sources for the platform the app runs on
+pub fn platform_sources(config: &MonitoringConfig) -> CaptureSources {
+    #[cfg(target_os = "macos")]
+    let sources = {
+        let _ = config;
+        CaptureSources {
+            clipboard: Box::new(Pasteboard::default()),
+            windows: Box::new(AppleScriptWindows),
+            processes: Box::new(SysinfoProcesses::default()),
+            keyboard: Box::new(RdevKeyboard),
+        }
+    };
+
+    #[cfg(target_os = "linux")]
+    let sources = {
+        let clipboard: Box<dyn ClipboardSource> = match LinuxClipboard::detect(config.capture_primary_selection) {
+            Some(clipboard) => {
+                println!("[MONITOR] Clipboard backend: {:?}", clipboard.backend());
+                Box::new(clipboard)
+            }
+            None => {
+                println!("[MONITOR] ⚠️  No clipboard tool found; install wl-clipboard (Wayland) or xclip (X11)");
+                Box::new(Unsupported)
+            }
+        };
+        let windows: Box<dyn WindowSource> = match X11Windows::detect() {
+            Some(windows) => Box::new(windows),
+            None => {
+                println!("[MONITOR] ⚠️  Focused window unknown without X11 and xprop; captures are saved as clipboard");
+                Box::new(Unsupported)
+            }
+        };
+        CaptureSources {
+            clipboard,
+            windows,
+            processes: Box::new(SysinfoProcesses::default()),
+            keyboard: Box::new(RdevKeyboard),
+        }
+    };
+
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    let sources = {
+        let _ = config;
+        CaptureSources {
+            clipboard: Box::new(Unsupported),
+            windows: Box::new(Unsupported),
+            processes: Box::new(SysinfoProcesses::default()),
+            keyboard: Box::new(RdevKeyboard),
+        }
+    };
+
+    sources
+}
+
+/// Stand-in for sources the platform doesn't have; never reports anything
+pub struct Unsupported;
+
+impl ClipboardSource for Unsupported {
+    fn changes(&mut self) -> Result<Vec<(Selection, String)>> {
+        Ok(vec![])
+    }
+
+    fn current(&mut self) -> Result<String> {
+        Ok(String::new())
+    }
+}
+
+impl WindowSource for Unsupported {
+    fn browser_tabs(&mut self) -> Result<Vec<BrowserTab>> {
+        Ok(vec![])
+    }
+
+    fn active_window(&mut self) -> Result<Option<ActiveWindow>> {
+        Ok(None)
+    }
+}
+
+impl ProcessSource for Unsupported {
+    fn running_applications(&mut self) -> Result<Vec<RunningApp>> {
+        Ok(vec![])
+    }
+}
+
+impl KeyboardSource for Unsupported {
+    fn keystrokes(&mut self) -> Result<Vec<Keystroke>> {
+        Ok(vec![])
+    }
+}
+
+/// Process scanner built on sysinfo, for every platform
+#[derive(Default)]
+pub struct SysinfoProcesses {
+    system: System,
+}
+
+impl ProcessSource for SysinfoProcesses {
+    fn running_applications(&mut self) -> Result<Vec<RunningApp>> {
+        self.system.refresh_processes_specifics(
+            ProcessRefreshKind::new()
+                .with_cmd(UpdateKind::OnlyIfNotSet)
+                .with_exe(UpdateKind::OnlyIfNotSet),
+        );
+
+        let mut found: HashMap<&'static str, RunningApp> = HashMap::new();
+        for (pid, process) in self.system.processes() {
+            let Some(name) = application_for_process(process.name(), process.exe(), process.cmd()) else {
+                continue;
+            };
+            let app = RunningApp {
+                name: name.to_string(),
+                process_name: process.name().to_string(),
+                pid: pid.as_u32(),
+                started_at: DateTime::from_timestamp(process.start_time() as i64, 0),
+            };
+
+            // Electron apps run many helper processes; the oldest one is the app itself
+            let replace = match found.get(name) {
+                Some(existing) => (app.started_at, app.pid) < (existing.started_at, existing.pid),
+                None => true,
+            };
+            if replace {
+                found.insert(name, app);
+            }
+        }
+
+        let mut apps: Vec<RunningApp> = found.into_values().collect();
+        apps.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(apps)
+    }
+}
+
+/// Application a process belongs to, judged by its name, executable and command line
+pub fn application_for_process(name: &str, exe: Option<&Path>, cmd: &[String]) -> Option<&'static str> {
+    let mut candidates = vec![program_name(name)];
+    if let Some(exe) = exe.and_then(|exe| exe.file_name()) {
+        candidates.push(program_name(&exe.to_string_lossy()));
+    }
+    if let Some(first) = cmd.first() {
+        let first = program_name(first);
+        if LAUNCHERS.contains(&first.as_str()) {
+            if let Some(script) = cmd.get(1) {
+                candidates.push(program_name(script));
+            }
+        }
+        candidates.push(first);
+    }
+
+    candidates.iter().find_map(|candidate| {
+        KNOWN_PROCESSES.iter().find_map(|(pattern, application)| {
+            let matches = candidate == pattern
+                || candidate.strip_prefix(pattern).is_some_and(|rest| rest.starts_with(['-', '_', '.', ' ']));
+            matches.then_some(*application)
+        })
+    })
+}
+
+/// Lowercase file name of a program path without a Windows `.exe`
+fn program_name(path: &str) -> String {
+    let file_name = path.rsplit(['/', '\\']).next().unwrap_or(path).to_lowercase();
+    file_name.strip_suffix(".exe").map(str::to_string).unwrap_or(file_name)
+}
+
+/// macOS pasteboard read through `pbpaste`
+#[cfg(target_os = "macos")]
+#[derive(Default)]
+pub struct Pasteboard {
+    last: Option<String>,
+}
+
+#[cfg(target_os = "macos")]
+impl ClipboardSource for Pasteboard {
+    fn changes(&mut self) -> Result<Vec<(Selection, String)>> {
+        let content = self.current()?;
+        if self.last.as_ref() == Some(&content) {
+            return Ok(vec![]);
+        }
+        self.last = Some(content.clone());
+        Ok(vec![(Selection::Clipboard, content)])
+    }
+
+    fn current(&mut self) -> Result<String> {
+        let output = Command::new("pbpaste")
+            .output()
+            .map_err(|e| PromptHistError::SystemError(format!("Failed to read clipboard: {}", e)))?;
+
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        } else {
+            Ok(String::new())
+        }
+    }
+}
+
+/// Safari and Chrome tabs read through AppleScript
+#[cfg(target_os = "macos")]
+pub struct AppleScriptWindows;
+
+#[cfg(target_os = "macos")]
+impl WindowSource for AppleScriptWindows {
+    fn browser_tabs(&mut self) -> Result<Vec<BrowserTab>> {
+        let mut tabs = Vec::new();
+
+        for (browser, script) in [
+            ("Safari", r#"
+                tell application "Safari"
+                    set tabList to {}
+                    repeat with w in windows
+                        repeat with t in tabs of w
+                            set end of tabList to (name of t) & "|" & (URL of t)
+                        end repeat
+                    end repeat
+                    return tabList
+                end tell
+            "#),
+            ("Google Chrome", r#"
+                tell application "Google Chrome"
+                    set tabList to {}
+                    repeat with w in windows
+                        repeat with t in tabs of w
+                            set end of tabList to (title of t) & "|" & (URL of t)
+                        end repeat
+                    end repeat
+                    return tabList
+                end tell
+            "#),
+        ] {
+            // A browser that isn't running or refuses scripting has no tabs to report
+            let Ok(result) = run_applescript(script) else {
+                continue;
+            };
+            tabs.extend(result.lines().filter_map(|line| {
+                let parts: Vec<&str> = line.split('|').collect();
+                if parts.len() == 2 {
+                    Some(BrowserTab {
+                        browser: browser.to_string(),
+                        title: parts[0].to_string(),
+                        url: parts[1].to_string(),
+                    })
+                } else {
+                    None
+                }
+            }));
+        }
+
+        Ok(tabs)
+    }
+
+    fn active_window(&mut self) -> Result<Option<ActiveWindow>> {
+        let result = run_applescript(r#"
+            tell application "System Events"
+                set frontApp to first application process whose frontmost is true
+                set windowTitle to ""
+                try
+                    set windowTitle to name of front window of frontApp
+                end try
+                return (name of frontApp) & "|" & (unix id of frontApp) & "|" & windowTitle
+            end tell
+        "#)?;
+
+        // The title comes last since it may contain the separator itself
+        let mut parts = result.trim_end_matches('\n').splitn(3, '|');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(name), Some(pid), Some(title)) if !name.is_empty() => Ok(Some(ActiveWindow {
+                process_name: name.to_string(),
+                title: title.to_string(),
+                pid: pid.parse().ok(),
+            })),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Output of an AppleScript, empty when the script failed
+#[cfg(target_os = "macos")]
+fn run_applescript(script: &str) -> Result<String> {
+    let output = Command::new("osascript")
+        .arg("-e")
+        .arg(script)
+        .output()
+        .map_err(|e| PromptHistError::SystemError(format!("Failed to execute AppleScript: {}", e)))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Ok(String::new())
+    }
+}
+
+/// One moment of a scripted capture session
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub struct CaptureFrame {
+    pub copied: Option<String>, // Text copied at this moment
+    pub tabs: Vec<BrowserTab>,
+    pub applications: Vec<RunningApp>,
+    pub active: Option<ActiveWindow>,
+    pub typed: Vec<Keystroke>,
+}
+
+/// Replays frames through scripted sources; `advance` moves every source to the next frame
+#[cfg(test)]
+#[derive(Clone)]
+pub struct Timeline {
+    state: Arc<Mutex<TimelineState>>,
+}
+
+#[cfg(test)]
+struct TimelineState {
+    frames: Vec<CaptureFrame>,
+    position: Option<usize>,
+    copy_reported: bool,
+    typing_reported: bool,
+}
+
+#[cfg(test)]
+impl Timeline {
+    pub fn new(frames: Vec<CaptureFrame>) -> Self {
+        Self { state: Arc::new(Mutex::new(TimelineState {
+            frames,
+            position: None,
+            copy_reported: false,
+            typing_reported: false,
+        })) }
+    }
+
+    /// Moves to the next frame; false once the frames ran out
+    pub fn advance(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let next = state.position.map_or(0, |position| position + 1);
+        if next >= state.frames.len() {
+            return false;
+        }
+        state.position = Some(next);
+        state.copy_reported = false;
+        state.typing_reported = false;
+        true
+    }
+
+    pub fn sources(&self) -> CaptureSources {
+        CaptureSources {
+            clipboard: Box::new(self.clone()),
+            windows: Box::new(self.clone()),
+            processes: Box::new(self.clone()),
+            keyboard: Box::new(self.clone()),
+        }
+    }
+
+    fn frame(&self) -> CaptureFrame {
+        let state = self.state.lock().unwrap();
+        state.position.map(|position| state.frames[position].clone()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+impl ClipboardSource for Timeline {
+    fn changes(&mut self) -> Result<Vec<(Selection, String)>> {
+        let mut state = self.state.lock().unwrap();
+        let Some(position) = state.position else {
+            return Ok(vec![]);
+        };
+        // A copy is reported once, however often the frame is polled
+        if state.copy_reported {
+            return Ok(vec![]);
+        }
+        state.copy_reported = true;
+        Ok(state.frames[position].copied.clone().map(|text| (Selection::Clipboard, text)).into_iter().collect())
+    }
+
+    fn current(&mut self) -> Result<String> {
+        let state = self.state.lock().unwrap();
+        let Some(position) = state.position else {
+            return Ok(String::new());
+        };
+        Ok(state.frames[..=position].iter().rev().find_map(|frame| frame.copied.clone()).unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+impl WindowSource for Timeline {
+    fn browser_tabs(&mut self) -> Result<Vec<BrowserTab>> {
+        Ok(self.frame().tabs)
+    }
+
+    fn active_window(&mut self) -> Result<Option<ActiveWindow>> {
+        Ok(self.frame().active)
+    }
+}
+
+#[cfg(test)]
+impl ProcessSource for Timeline {
+    fn running_applications(&mut self) -> Result<Vec<RunningApp>> {
+        Ok(self.frame().applications)
+    }
+}
+
+#[cfg(test)]
+impl KeyboardSource for Timeline {
+    fn keystrokes(&mut self) -> Result<Vec<Keystroke>> {
+        let mut state = self.state.lock().unwrap();
+        let Some(position) = state.position else {
+            return Ok(vec![]);
+        };
+        if state.typing_reported {
+            return Ok(vec![]);
+        }
+        state.typing_reported = true;
+        Ok(state.frames[position].typed.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_processes_map_to_applications() {
+        let cmd = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(application_for_process("ollama", None, &cmd(&["/usr/local/bin/ollama", "serve"])), Some("Ollama"));
+        assert_eq!(application_for_process("Cursor.exe", None, &[]), Some("Cursor"));
+        assert_eq!(application_for_process("code-insiders", None, &[]), Some("VS Code"));
+        assert_eq!(application_for_process("LM Studio", None, &[]), Some("LM Studio"));
+        assert_eq!(
+            application_for_process("MainThread", Some(Path::new("/opt/lm-studio/lm-studio")), &[]),
+            Some("LM Studio")
+        );
+        assert_eq!(application_for_process("node", None, &cmd(&["node", "/usr/lib/node_modules/.bin/claude"])), Some("Claude"));
+
+        assert_eq!(application_for_process("codex", None, &[]), None);
+        assert_eq!(application_for_process("vim", None, &cmd(&["vim", "code"])), None);
+    }
+
+    #[test]
+    fn test_live_scan_skips_unrelated_processes() {
+        // The test binary is no AI tool, so a live scan must not claim it
+        let own = std::process::id();
+        let apps = SysinfoProcesses::default().running_applications().unwrap();
+        assert!(apps.iter().all(|app| app.pid != own));
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::capture::{ClipboardSource, Selection};
+use crate::models::{PromptHistError, Result};
+
+// Answers come through the X server or compositor; a hung selection owner or client must not stall the monitor
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Command-line tool used to talk to the display server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    WlPaste,
+    Xclip,
+    Xsel,
+}
+
+/// Linux clipboard reader using wl-paste on Wayland and xclip or xsel on X11.
+///
+/// A selection's text is only fetched after it changed: wl-paste reports changes through
+/// `--watch` and xclip through the selection's TIMESTAMP. Where neither works, such as on
+/// compositors without the data-control protocol, the text is read and compared by hash.
+pub struct LinuxClipboard {
+    backend: Backend,
+    selections: Vec<Selection>,
+    // Running `wl-paste --watch` processes and how many changes each has reported
+    watchers: HashMap<Selection, (Child, Arc<AtomicU64>)>,
+    watch_unsupported: bool,
+    // Last change marker per selection: watcher count, X11 timestamp or text hash
+    markers: HashMap<Selection, String>,
+}
+
+impl LinuxClipboard {
+    /// Picks a backend for the current session, or None when no display or tool is available
+    pub fn detect(capture_primary: bool) -> Option<Self> {
+        let wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
+        let x11 = std::env::var_os("DISPLAY").is_some();
+
+        let backend = if wayland && on_path("wl-paste") {
+            Backend::WlPaste
+        } else if x11 && on_path("xclip") {
+            Backend::Xclip
+        } else if x11 && on_path("xsel") {
+            Backend::Xsel
+        } else {
+            return None;
+        };
+
+        let mut selections = vec![Selection::Clipboard];
+        if capture_primary {
+            selections.push(Selection::Primary);
+        }
+
+        Some(Self {
+            backend,
+            selections,
+            watchers: HashMap::new(),
+            watch_unsupported: false,
+            markers: HashMap::new(),
+        })
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    fn read(&self, selection: Selection) -> Result<String> {
+        let (program, args) = read_command(self.backend, selection);
+        // An empty selection makes the tools fail, which is not an error here
+        let output = run(program, &args)?.unwrap_or_default();
+        Ok(String::from_utf8_lossy(&output).to_string())
+    }
+
+    /// Ownership timestamp of an X11 selection, which changes whenever new text is copied
+    fn x11_timestamp(&self, selection: Selection) -> Result<Option<String>> {
+        let output = run("xclip", &["-o", "-selection", x11_name(selection), "-t", "TIMESTAMP"])?;
+        Ok(output.filter(|output| !output.is_empty()).map(|output| timestamp_marker(&output)))
+    }
+
+    /// Changes reported by `wl-paste --watch` for the selection, starting the watcher if needed.
+    /// None once watching turned out not to work in this session.
+    fn watch_count(&mut self, selection: Selection) -> Option<u64> {
+        if self.watch_unsupported {
+            return None;
+        }
+
+        if let Some((child, _)) = self.watchers.get_mut(&selection) {
+            if !matches!(child.try_wait(), Ok(None)) {
+                println!("[MONITOR] wl-paste --watch exited; comparing clipboard contents instead");
+                self.stop_watchers();
+                self.watch_unsupported = true;
+                return None;
+            }
+        } else {
+            let mut command = Command::new("wl-paste");
+            if selection == Selection::Primary {
+                command.arg("--primary");
+            }
+            let spawned = command.args(["--watch", "echo"])
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn();
+
+            let mut child = match spawned {
+                Ok(child) => child,
+                Err(e) => {
+                    println!("[MONITOR] Failed to start wl-paste --watch: {}", e);
+                    self.watch_unsupported = true;
+                    return None;
+                }
+            };
+
+            let changes = Arc::new(AtomicU64::new(0));
+            if let Some(stdout) = child.stdout.take() {
+                let changes = Arc::clone(&changes);
+                std::thread::spawn(move || {
+                    for _ in BufReader::new(stdout).lines().map_while(std::result::Result::ok) {
+                        changes.fetch_add(1, Ordering::SeqCst);
+                    }
+                });
+            }
+            // A new watcher counts from zero again
+            self.markers.remove(&selection);
+            self.watchers.insert(selection, (child, changes));
+        }
+
+        self.watchers.get(&selection).map(|(_, changes)| changes.load(Ordering::SeqCst))
+    }
+
+    fn stop_watchers(&mut self) {
+        for (_, (mut child, _)) in self.watchers.drain() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl ClipboardSource for LinuxClipboard {
+    fn changes(&mut self) -> Result<Vec<(Selection, String)>> {
+        let mut changed = Vec::new();
+
+        for selection in self.selections.clone() {
+            let marker = match self.backend {
+                Backend::WlPaste => self.watch_count(selection).map(|count| count.to_string()),
+                Backend::Xclip => self.x11_timestamp(selection)?,
+                Backend::Xsel => None,
+            };
+
+            let (marker, content) = match marker {
+                Some(marker) => {
+                    if self.markers.get(&selection) == Some(&marker) {
+                        continue;
+                    }
+                    let content = self.read(selection)?;
+                    (marker, content)
+                }
+                None => {
+                    let content = self.read(selection)?;
+                    (text_hash(&content), content)
+                }
+            };
+
+            if self.markers.insert(selection, marker.clone()) == Some(marker) {
+                continue;
+            }
+            changed.push((selection, content));
+        }
+
+        Ok(changed)
+    }
+
+    fn current(&mut self) -> Result<String> {
+        self.read(Selection::Clipboard)
+    }
+}
+
+impl Drop for LinuxClipboard {
+    fn drop(&mut self) {
+        self.stop_watchers();
+    }
+}
+
+/// Program and arguments printing the selection's text
+fn read_command(backend: Backend, selection: Selection) -> (&'static str, Vec<&'static str>) {
+    match (backend, selection) {
+        (Backend::WlPaste, Selection::Clipboard) => ("wl-paste", vec!["--no-newline", "--type", "text"]),
+        (Backend::WlPaste, Selection::Primary) => ("wl-paste", vec!["--no-newline", "--type", "text", "--primary"]),
+        (Backend::Xclip, selection) => ("xclip", vec!["-o", "-selection", x11_name(selection), "-t", "UTF8_STRING"]),
+        (Backend::Xsel, Selection::Clipboard) => ("xsel", vec!["--output", "--clipboard"]),
+        (Backend::Xsel, Selection::Primary) => ("xsel", vec!["--output", "--primary"]),
+    }
+}
+
+fn x11_name(selection: Selection) -> &'static str {
+    match selection {
+        Selection::Clipboard => "clipboard",
+        Selection::Primary => "primary",
+    }
+}
+
+/// Output of a successful run, None when the program failed
+pub(crate) fn run(program: &str, args: &[&str]) -> Result<Option<Vec<u8>>> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| PromptHistError::SystemError(format!("Failed to run {}: {}", program, e)))?;
+
+    // Read on another thread so a large selection can't fill the pipe while we wait
+    let mut stdout = child.stdout.take();
+    let reader = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        if let Some(stdout) = stdout.as_mut() {
+            let _ = stdout.read_to_end(&mut output);
+        }
+        output
+    });
+
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if started.elapsed() < READ_TIMEOUT => std::thread::sleep(Duration::from_millis(10)),
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(PromptHistError::SystemError(format!("{} timed out", program)));
+            }
+            Err(e) => return Err(PromptHistError::SystemError(format!("Failed to run {}: {}", program, e))),
+        }
+    };
+
+    let output = reader.join().unwrap_or_default();
+    Ok(status.success().then_some(output))
+}
+
+pub(crate) fn on_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+// TIMESTAMP is the raw INTEGER the owner set, not text, so compare its bytes
+fn timestamp_marker(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn text_hash(text: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_commands_target_the_selection() {
+        assert_eq!(read_command(Backend::WlPaste, Selection::Primary).1.last(), Some(&"--primary"));
+        assert_eq!(
+            read_command(Backend::Xclip, Selection::Primary),
+            ("xclip", vec!["-o", "-selection", "primary", "-t", "UTF8_STRING"])
+        );
+        assert_eq!(read_command(Backend::Xsel, Selection::Clipboard).1, vec!["--output", "--clipboard"]);
+    }
+
+    #[test]
+    fn test_run_reports_failures_without_erroring() {
+        assert_eq!(run("sh", &["-c", "printf copied"]).unwrap(), Some(b"copied".to_vec()));
+        assert_eq!(run("sh", &["-c", "exit 1"]).unwrap(), None);
+        assert!(run("prompthist-no-such-program", &[]).is_err());
+    }
+
+    #[test]
+    fn test_timestamp_marker_keeps_high_bytes() {
+        let first = timestamp_marker(&[0x10, 0x80, 0x3c, 0x01]);
+        let second = timestamp_marker(&[0x10, 0x9f, 0x3c, 0x01]);
+        assert_eq!(first, "10803c01");
+        assert_ne!(first, second);
+    }
+}
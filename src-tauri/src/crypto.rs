@@ -2,68 +2,350 @@ use aes_gcm::{
     aead::{Aead, KeyInit, OsRng},
     Aes256Gcm, Nonce, Key,
 };
-use argon2::{Argon2, PasswordHasher, password_hash::{rand_core::RngCore, PasswordHash, PasswordVerifier, SaltString}};
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version, password_hash::{rand_core::RngCore, PasswordHash, PasswordVerifier, SaltString}};
 use base64::{Engine as _, engine::general_purpose};
-use keyring::Entry;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+use uuid::Uuid;
+use crate::keystore::{KeyStore, KeyringStore};
 use crate::models::{PromptHistError, Result};
 
-pub struct CryptoManager {
+type HmacSha256 = Hmac<Sha256>;
+
+/// Key id given to the single key stored by versions that predate rotation
+pub const LEGACY_KEY_ID: &str = "legacy";
+
+/// Serialized form of all data keys, as stored in the keyring
+#[derive(Serialize, Deserialize)]
+struct StoredKeySet {
+    active: String,
+    keys: BTreeMap<String, String>, // key id -> base64 key
+}
+
+struct DataKey {
     cipher: Aes256Gcm,
-    key_entry: Entry,
+    search_key: [u8; 32],
+    encoded: String,
+}
+
+struct KeySet {
+    active: String,
+    keys: HashMap<String, DataKey>,
+}
+
+/// Salt, Argon2id parameters and wrapped data keys for passphrase mode.
+///
+/// Stored in the database `encryption_header` table, or as a key file by `PassphraseFileStore`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PassphraseHeader {
+    pub kdf_memory_kib: u32,
+    pub kdf_iterations: u32,
+    pub kdf_parallelism: u32,
+    pub salt: String,
+    pub wrapped_keys: String,
+}
+
+impl PassphraseHeader {
+    fn new_salted() -> Self {
+        let params = Params::default();
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+
+        Self {
+            kdf_memory_kib: params.m_cost(),
+            kdf_iterations: params.t_cost(),
+            kdf_parallelism: params.p_cost(),
+            salt: general_purpose::STANDARD.encode(salt),
+            wrapped_keys: String::new(),
+        }
+    }
+
+    /// Wraps serialized keys under `passphrase` with a fresh salt
+    pub fn wrap(passphrase: &str, serialized: &str) -> Result<Self> {
+        let mut header = Self::new_salted();
+        let wrapping_cipher = CryptoManager::derive_wrapping_cipher(passphrase, &header)?;
+        header.wrapped_keys = CryptoManager::seal_bytes(&wrapping_cipher, serialized.as_bytes())?;
+        Ok(header)
+    }
+
+    /// Recovers the serialized keys, failing if `passphrase` is wrong
+    pub fn unwrap(&self, passphrase: &str) -> Result<String> {
+        let wrapping_cipher = CryptoManager::derive_wrapping_cipher(passphrase, self)?;
+        Self::unwrap_with(&wrapping_cipher, &self.wrapped_keys)
+    }
+
+    fn unwrap_with(wrapping_cipher: &Aes256Gcm, wrapped_keys: &str) -> Result<String> {
+        let serialized = CryptoManager::open_bytes(wrapping_cipher, wrapped_keys)
+            .map_err(|_| PromptHistError::Encryption("Incorrect passphrase".to_string()))?;
+        String::from_utf8(serialized)
+            .map_err(|e| PromptHistError::Encryption(format!("Invalid UTF-8 in wrapped keys: {}", e)))
+    }
+}
+
+/// Where the data keys are persisted
+enum KeyBackend {
+    Store(Box<dyn KeyStore>),
+    /// Keys are wrapped by a passphrase-derived key; the caller persists the header
+    Passphrase { wrapping_cipher: Box<Aes256Gcm>, header: PassphraseHeader },
+}
+
+pub struct CryptoManager {
+    keys: RwLock<KeySet>,
+    backend: KeyBackend,
 }
 
 impl CryptoManager {
+    /// Uses keys from the OS keyring
     pub fn new() -> Result<Self> {
-        let key_entry = Entry::new("prompthist", "encryption_key")
-            .map_err(|e| PromptHistError::Encryption(format!("Failed to create keyring entry: {}", e)))?;
+        Self::with_store(Box::new(KeyringStore::new()?))
+    }
+
+    /// Loads keys from `store`, generating and storing a key on first use
+    pub fn with_store(store: Box<dyn KeyStore>) -> Result<Self> {
+        let keys = Self::get_or_create_keys(store.as_ref())?;
+
+        Ok(Self {
+            keys: RwLock::new(keys),
+            backend: KeyBackend::Store(store),
+        })
+    }
 
-        let key = Self::get_or_create_key(&key_entry)?;
-        let cipher = Aes256Gcm::new(&key);
+    /// Creates fresh data keys protected by `passphrase`. The returned header must be
+    /// stored for the keys to be recoverable.
+    pub fn create_with_passphrase(passphrase: &str) -> Result<(Self, PassphraseHeader)> {
+        let mut header = PassphraseHeader::new_salted();
+        let wrapping_cipher = Self::derive_wrapping_cipher(passphrase, &header)?;
+
+        let id = Uuid::new_v4().to_string();
+        let mut keys = HashMap::new();
+        keys.insert(id.clone(), Self::generate_key()?);
+        let key_set = KeySet { active: id, keys };
+
+        header.wrapped_keys = Self::seal_bytes(&wrapping_cipher, Self::serialize_keys(&key_set)?.as_bytes())?;
+
+        let crypto = Self {
+            keys: RwLock::new(key_set),
+            backend: KeyBackend::Passphrase { wrapping_cipher: Box::new(wrapping_cipher), header: header.clone() },
+        };
+        Ok((crypto, header))
+    }
+
+    /// Unwraps the data keys in `header` with `passphrase`
+    pub fn unlock_with_passphrase(passphrase: &str, header: &PassphraseHeader) -> Result<Self> {
+        let wrapping_cipher = Self::derive_wrapping_cipher(passphrase, header)?;
+        let serialized = PassphraseHeader::unwrap_with(&wrapping_cipher, &header.wrapped_keys)?;
 
         Ok(Self {
-            cipher,
-            key_entry,
+            keys: RwLock::new(Self::deserialize_keys(&serialized)?),
+            backend: KeyBackend::Passphrase { wrapping_cipher: Box::new(wrapping_cipher), header: header.clone() },
         })
     }
 
-    fn get_or_create_key(key_entry: &Entry) -> Result<Key<Aes256Gcm>> {
-        // Try to get existing key from keyring
-        match key_entry.get_password() {
-            Ok(key_str) => {
-                // Decode the base64 key
-                let key_bytes = general_purpose::STANDARD
-                    .decode(key_str)
-                    .map_err(|e| PromptHistError::Encryption(format!("Failed to decode key: {}", e)))?;
+    fn derive_wrapping_cipher(passphrase: &str, header: &PassphraseHeader) -> Result<Aes256Gcm> {
+        let salt = general_purpose::STANDARD
+            .decode(&header.salt)
+            .map_err(|e| PromptHistError::Encryption(format!("Failed to decode salt: {}", e)))?;
+        let params = Params::new(header.kdf_memory_kib, header.kdf_iterations, header.kdf_parallelism, Some(32))
+            .map_err(|e| PromptHistError::Encryption(format!("Invalid KDF parameters: {}", e)))?;
+
+        let mut key_bytes = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key_bytes)
+            .map_err(|e| PromptHistError::Encryption(format!("Key derivation failed: {}", e)))?;
+
+        Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_bytes)))
+    }
+
+    /// Current passphrase header, re-wrapped to include any rotated keys.
+    /// `None` when keys live in a `KeyStore`.
+    pub fn passphrase_header(&self) -> Result<Option<PassphraseHeader>> {
+        match &self.backend {
+            KeyBackend::Store(_) => Ok(None),
+            KeyBackend::Passphrase { wrapping_cipher, header } => {
+                let serialized = Self::serialize_keys(&self.read_keys())?;
+                Ok(Some(PassphraseHeader {
+                    wrapped_keys: Self::seal_bytes(wrapping_cipher, serialized.as_bytes())?,
+                    ..header.clone()
+                }))
+            }
+        }
+    }
+
+    /// Derives a separate key for blind search tokens so the data key is never used for two purposes
+    fn derive_search_key(key: &Key<Aes256Gcm>) -> Result<[u8; 32]> {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(key.as_slice())
+            .map_err(|e| PromptHistError::Encryption(format!("Failed to derive search key: {}", e)))?;
+        mac.update(b"prompthist-search-index-v1");
+        Ok(mac.finalize().into_bytes().into())
+    }
+
+    fn decode_key(encoded: &str) -> Result<DataKey> {
+        let key_bytes = general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|e| PromptHistError::Encryption(format!("Failed to decode key: {}", e)))?;
 
-                if key_bytes.len() != 32 {
-                    return Err(PromptHistError::Encryption("Invalid key length".to_string()));
-                }
+        if key_bytes.len() != 32 {
+            return Err(PromptHistError::Encryption("Invalid key length".to_string()));
+        }
+
+        let key = *Key::<Aes256Gcm>::from_slice(&key_bytes);
+        Ok(DataKey {
+            cipher: Aes256Gcm::new(&key),
+            search_key: Self::derive_search_key(&key)?,
+            encoded: encoded.to_string(),
+        })
+    }
 
-                Ok(*Key::<Aes256Gcm>::from_slice(&key_bytes))
+    fn generate_key() -> Result<DataKey> {
+        let mut key_bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut key_bytes);
+        Self::decode_key(&general_purpose::STANDARD.encode(key_bytes))
+    }
+
+    fn get_or_create_keys(store: &dyn KeyStore) -> Result<KeySet> {
+        // Try to get existing keys from the store
+        match store.load()? {
+            Some(stored) if stored.trim_start().starts_with('{') => Self::deserialize_keys(&stored),
+            Some(legacy) => {
+                // A bare base64 key written before rotation support
+                let mut keys = HashMap::new();
+                keys.insert(LEGACY_KEY_ID.to_string(), Self::decode_key(legacy.trim())?);
+                Ok(KeySet { active: LEGACY_KEY_ID.to_string(), keys })
             }
-            Err(_) => {
-                // Generate new key
-                let mut key_bytes = [0u8; 32];
-                OsRng.fill_bytes(&mut key_bytes);
+            None => {
+                let id = Uuid::new_v4().to_string();
+                let mut keys = HashMap::new();
+                keys.insert(id.clone(), Self::generate_key()?);
+
+                let key_set = KeySet { active: id, keys };
+                store.store(&Self::serialize_keys(&key_set)?)?;
+                Ok(key_set)
+            }
+        }
+    }
+
+    fn serialize_keys(key_set: &KeySet) -> Result<String> {
+        let stored = StoredKeySet {
+            active: key_set.active.clone(),
+            keys: key_set
+                .keys
+                .iter()
+                .map(|(id, key)| (id.clone(), key.encoded.clone()))
+                .collect(),
+        };
+        Ok(serde_json::to_string(&stored)?)
+    }
+
+    fn deserialize_keys(serialized: &str) -> Result<KeySet> {
+        let stored: StoredKeySet = serde_json::from_str(serialized)
+            .map_err(|e| PromptHistError::Encryption(format!("Failed to parse stored keys: {}", e)))?;
+
+        let mut keys = HashMap::new();
+        for (id, encoded) in &stored.keys {
+            keys.insert(id.clone(), Self::decode_key(encoded)?);
+        }
+        if !keys.contains_key(&stored.active) {
+            return Err(PromptHistError::Encryption("Active key is missing from stored keys".to_string()));
+        }
 
-                let key_str = general_purpose::STANDARD.encode(&key_bytes);
-                key_entry.set_password(&key_str)
-                    .map_err(|e| PromptHistError::Encryption(format!("Failed to store key: {}", e)))?;
+        Ok(KeySet { active: stored.active, keys })
+    }
+
+    fn store_keys(&self, key_set: &KeySet) -> Result<()> {
+        match &self.backend {
+            KeyBackend::Store(store) => store.store(&Self::serialize_keys(key_set)?),
+            // Persisted by the database through `passphrase_header`
+            KeyBackend::Passphrase { .. } => Ok(()),
+        }
+    }
+
+    fn read_keys(&self) -> std::sync::RwLockReadGuard<'_, KeySet> {
+        self.keys.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write_keys(&self) -> std::sync::RwLockWriteGuard<'_, KeySet> {
+        self.keys.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Id of the key new data is encrypted with
+    pub fn active_key_id(&self) -> String {
+        self.read_keys().active.clone()
+    }
+
+    /// Ids of every key still available for decryption
+    pub fn key_ids(&self) -> Vec<String> {
+        self.read_keys().keys.keys().cloned().collect()
+    }
+
+    /// Generates a new active key and persists it alongside the old ones, which stay
+    /// available for decryption until they are retired. Returns the new key id.
+    pub fn rotate_key(&self) -> Result<String> {
+        let id = Uuid::new_v4().to_string();
+        let mut key_set = self.write_keys();
+
+        key_set.keys.insert(id.clone(), Self::generate_key()?);
+        let previous = std::mem::replace(&mut key_set.active, id.clone());
+
+        if let Err(e) = self.store_keys(&key_set) {
+            // Keep memory consistent with what's persisted
+            key_set.active = previous;
+            key_set.keys.remove(&id);
+            return Err(e);
+        }
+
+        Ok(id)
+    }
+
+    /// Undoes a rotation whose keys could not be persisted, making `previous_key_id` active again
+    pub fn discard_key(&self, key_id: &str, previous_key_id: &str) {
+        let mut key_set = self.write_keys();
+        if key_set.keys.contains_key(previous_key_id) {
+            key_set.active = previous_key_id.to_string();
+            key_set.keys.remove(key_id);
+        }
+    }
+
+    /// Forgets a key that no longer protects any data
+    pub fn retire_key(&self, key_id: &str) -> Result<()> {
+        let mut key_set = self.write_keys();
+        if key_set.active == key_id {
+            return Err(PromptHistError::Encryption("Cannot retire the active key".to_string()));
+        }
 
-                Ok(*Key::<Aes256Gcm>::from_slice(&key_bytes))
+        if let Some(removed) = key_set.keys.remove(key_id) {
+            if let Err(e) = self.store_keys(&key_set) {
+                key_set.keys.insert(key_id.to_string(), removed);
+                return Err(e);
             }
         }
+        Ok(())
     }
 
     pub fn encrypt(&self, plaintext: &str) -> Result<String> {
+        let active = self.active_key_id();
+        self.encrypt_with(&active, plaintext)
+    }
+
+    pub fn encrypt_with(&self, key_id: &str, plaintext: &str) -> Result<String> {
+        let key_set = self.read_keys();
+        let key = key_set.keys.get(key_id)
+            .ok_or_else(|| PromptHistError::Encryption(format!("Unknown key id: {}", key_id)))?;
+
+        Self::seal_bytes(&key.cipher, plaintext.as_bytes())
+    }
+
+    fn seal_bytes(cipher: &Aes256Gcm, plaintext: &[u8]) -> Result<String> {
         // Generate random nonce
         let mut nonce_bytes = [0u8; 12];
         OsRng.fill_bytes(&mut nonce_bytes);
         let nonce = Nonce::from_slice(&nonce_bytes);
 
         // Encrypt the data
-        let ciphertext = self.cipher
-            .encrypt(nonce, plaintext.as_bytes())
+        let ciphertext = cipher
+            .encrypt(nonce, plaintext)
             .map_err(|e| PromptHistError::Encryption(format!("Encryption failed: {}", e)))?;
 
         // Combine nonce and ciphertext
@@ -76,6 +358,22 @@ impl CryptoManager {
     }
 
     pub fn decrypt(&self, encrypted_data: &str) -> Result<String> {
+        let active = self.active_key_id();
+        self.decrypt_with(&active, encrypted_data)
+    }
+
+    pub fn decrypt_with(&self, key_id: &str, encrypted_data: &str) -> Result<String> {
+        let key_set = self.read_keys();
+        let key = key_set.keys.get(key_id)
+            .ok_or_else(|| PromptHistError::Encryption(format!("Unknown key id: {}", key_id)))?;
+
+        let plaintext = Self::open_bytes(&key.cipher, encrypted_data)?;
+
+        String::from_utf8(plaintext)
+            .map_err(|e| PromptHistError::Encryption(format!("Invalid UTF-8 in decrypted data: {}", e)))
+    }
+
+    fn open_bytes(cipher: &Aes256Gcm, encrypted_data: &str) -> Result<Vec<u8>> {
         // Decode from base64
         let encrypted_bytes = general_purpose::STANDARD
             .decode(encrypted_data)
@@ -90,12 +388,45 @@ impl CryptoManager {
         let nonce = Nonce::from_slice(nonce_bytes);
 
         // Decrypt the data
-        let plaintext = self.cipher
+        cipher
             .decrypt(nonce, ciphertext)
-            .map_err(|e| PromptHistError::Encryption(format!("Decryption failed: {}", e)))?;
+            .map_err(|e| PromptHistError::Encryption(format!("Decryption failed: {}", e)))
+    }
 
-        String::from_utf8(plaintext)
-            .map_err(|e| PromptHistError::Encryption(format!("Invalid UTF-8 in decrypted data: {}", e)))
+    /// Keyed, deterministic token used to index encrypted content without revealing it
+    pub fn blind_token(&self, key_id: &str, token: &str) -> Result<String> {
+        let key_set = self.read_keys();
+        let key = key_set.keys.get(key_id)
+            .ok_or_else(|| PromptHistError::Encryption(format!("Unknown key id: {}", key_id)))?;
+
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&key.search_key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(token.as_bytes());
+        Ok(general_purpose::STANDARD_NO_PAD.encode(mac.finalize().into_bytes()))
+    }
+
+    /// Keyed digest of prompt content, so duplicates can be found without storing a plain hash
+    pub fn content_hash(&self, key_id: &str, content: &str) -> Result<String> {
+        self.keyed_hash(key_id, "content", content)
+    }
+
+    /// Keyed digest of a tag name, used to look tags up without storing the name in the clear
+    pub fn tag_hash(&self, key_id: &str, name: &str) -> Result<String> {
+        self.keyed_hash(key_id, "tag", name)
+    }
+
+    fn keyed_hash(&self, key_id: &str, domain: &str, value: &str) -> Result<String> {
+        let key_set = self.read_keys();
+        let key = key_set.keys.get(key_id)
+            .ok_or_else(|| PromptHistError::Encryption(format!("Unknown key id: {}", key_id)))?;
+
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&key.search_key)
+            .expect("HMAC accepts keys of any length");
+        // Tokens never contain ':', so these can't collide with blind tokens
+        mac.update(domain.as_bytes());
+        mac.update(b":");
+        mac.update(value.as_bytes());
+        Ok(general_purpose::STANDARD_NO_PAD.encode(mac.finalize().into_bytes()))
     }
 
     pub fn hash_password(&self, password: &str) -> Result<String> {
@@ -126,21 +457,20 @@ impl CryptoManager {
         OsRng.fill_bytes(&mut token_bytes);
         general_purpose::STANDARD.encode(&token_bytes)
     }
-
-    pub fn secure_delete_key(&self) -> Result<()> {
-        self.key_entry.delete_password()
-            .map_err(|e| PromptHistError::Encryption(format!("Failed to delete key: {}", e)))?;
-        Ok(())
-    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::keystore::MemoryKeyStore;
+
+    fn memory_crypto() -> CryptoManager {
+        CryptoManager::with_store(Box::new(MemoryKeyStore::default())).unwrap()
+    }
 
     #[test]
     fn test_encrypt_decrypt() {
-        let crypto = CryptoManager::new().unwrap();
+        let crypto = memory_crypto();
         let plaintext = "This is a test message for encryption";
 
         let encrypted = crypto.encrypt(plaintext).unwrap();
@@ -151,7 +481,7 @@ mod tests {
 
     #[test]
     fn test_password_hashing() {
-        let crypto = CryptoManager::new().unwrap();
+        let crypto = memory_crypto();
         let password = "test_password_123";
 
         let hash = crypto.hash_password(password).unwrap();
@@ -162,7 +492,7 @@ mod tests {
 
     #[test]
     fn test_secure_token_generation() {
-        let crypto = CryptoManager::new().unwrap();
+        let crypto = memory_crypto();
 
         let token1 = crypto.generate_secure_token();
         let token2 = crypto.generate_secure_token();
@@ -170,4 +500,33 @@ mod tests {
         assert_ne!(token1, token2);
         assert_eq!(token1.len(), 44); // Base64 encoded 32 bytes
     }
+
+    #[test]
+    fn test_passphrase_wrapped_keys() {
+        let (crypto, header) = CryptoManager::create_with_passphrase("correct horse").unwrap();
+        let encrypted = crypto.encrypt("secret prompt").unwrap();
+
+        let unlocked = CryptoManager::unlock_with_passphrase("correct horse", &header).unwrap();
+        assert_eq!(unlocked.decrypt(&encrypted).unwrap(), "secret prompt");
+
+        assert!(CryptoManager::unlock_with_passphrase("wrong horse", &header).is_err());
+    }
+
+    #[test]
+    fn test_legacy_key_and_rotation() {
+        let store = MemoryKeyStore::default();
+        store.store(&general_purpose::STANDARD.encode([3u8; 32])).unwrap();
+        let crypto = CryptoManager::with_store(Box::new(store)).unwrap();
+        assert_eq!(crypto.active_key_id(), LEGACY_KEY_ID);
+
+        let old = crypto.encrypt("before rotation").unwrap();
+        let new_id = crypto.rotate_key().unwrap();
+        assert_eq!(crypto.active_key_id(), new_id);
+        assert_eq!(crypto.decrypt_with(LEGACY_KEY_ID, &old).unwrap(), "before rotation");
+        assert!(crypto.decrypt(&old).is_err());
+
+        assert!(crypto.retire_key(&new_id).is_err());
+        crypto.retire_key(LEGACY_KEY_ID).unwrap();
+        assert!(crypto.decrypt_with(LEGACY_KEY_ID, &old).is_err());
+    }
 }
@@ -0,0 +1,63 @@
+use crate::models::{DiffLine, LineChange};
+
+/// Line-by-line diff based on the longest common subsequence.
+///
+/// Removed lines come before the lines that replaced them.
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // common[i][j] = LCS length of old[i..] and new[j..]
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::with_capacity(old.len().max(new.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(line(LineChange::Unchanged, old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(line(LineChange::Removed, old[i]));
+            i += 1;
+        } else {
+            lines.push(line(LineChange::Added, new[j]));
+            j += 1;
+        }
+    }
+
+    lines
+}
+
+fn line(change: LineChange, text: &str) -> DiffLine {
+    DiffLine { change, text: text.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_lines() {
+        let diff = diff_lines("You are a reviewer.\nBe brief.\nUse bullets.", "You are a reviewer.\nBe thorough.\nUse bullets.\nCite lines.");
+        let changes: Vec<(LineChange, &str)> = diff.iter().map(|line| (line.change, line.text.as_str())).collect();
+
+        assert_eq!(changes, vec![
+            (LineChange::Unchanged, "You are a reviewer."),
+            (LineChange::Removed, "Be brief."),
+            (LineChange::Added, "Be thorough."),
+            (LineChange::Unchanged, "Use bullets."),
+            (LineChange::Added, "Cite lines."),
+        ]);
+        assert!(diff_lines("same", "same").iter().all(|line| line.change == LineChange::Unchanged));
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::search_index;
+
+/// Word similarity at or above which a query word counts as found
+pub const DEFAULT_FUZZY_THRESHOLD: f64 = 0.4;
+
+// A query word that begins a longer word, like "refac" in "refactor", is at least this similar
+const PREFIX_SIMILARITY: f64 = 0.8;
+const MIN_PREFIX_LENGTH: usize = 3;
+
+/// Character trigrams of a word, padded like pg_trgm so the start of the word weighs more
+pub fn trigrams(word: &str) -> HashSet<String> {
+    let padded: Vec<char> = format!("  {} ", word.to_lowercase()).chars().collect();
+    padded.windows(3).map(|window| window.iter().collect()).collect()
+}
+
+/// How alike two lowercase words are, from 0 to 1
+pub fn word_similarity(query_word: &str, word: &str) -> f64 {
+    if query_word == word {
+        return 1.0;
+    }
+
+    let (a, b) = (trigrams(query_word), trigrams(word));
+    let shared = a.intersection(&b).count();
+    let similarity = shared as f64 / (a.len() + b.len() - shared) as f64;
+
+    if query_word.chars().count() >= MIN_PREFIX_LENGTH && word.starts_with(query_word) {
+        similarity.max(PREFIX_SIMILARITY)
+    } else {
+        similarity
+    }
+}
+
+/// Trigram index over the distinct words of a set of documents
+pub struct FuzzyIndex {
+    // Each distinct word with the documents containing it
+    words: Vec<(String, Vec<usize>)>,
+    // Trigram -> positions in `words`
+    postings: HashMap<String, Vec<usize>>,
+    document_count: usize,
+}
+
+impl FuzzyIndex {
+    pub fn new<'a>(documents: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut word_ids: HashMap<String, usize> = HashMap::new();
+        let mut words: Vec<(String, Vec<usize>)> = Vec::new();
+        let mut document_count = 0;
+
+        for (document, text) in documents.into_iter().enumerate() {
+            document_count += 1;
+            for token in search_index::tokenize(text) {
+                let id = *word_ids.entry(token.clone()).or_insert_with(|| {
+                    words.push((token, Vec::new()));
+                    words.len() - 1
+                });
+                let containing = &mut words[id].1;
+                if containing.last() != Some(&document) {
+                    containing.push(document);
+                }
+            }
+        }
+
+        let mut postings: HashMap<String, Vec<usize>> = HashMap::new();
+        for (id, (word, _)) in words.iter().enumerate() {
+            for trigram in trigrams(word) {
+                postings.entry(trigram).or_default().push(id);
+            }
+        }
+
+        Self { words, postings, document_count }
+    }
+
+    /// Documents with a word at least `threshold` similar to every query word, scored by the
+    /// average best similarity, best first. Without query words every document scores 1.
+    pub fn search(&self, query_words: &[String], threshold: f64) -> Vec<(usize, f64)> {
+        // document -> (query words found, summed best similarity)
+        let mut totals = vec![(0usize, 0.0f64); self.document_count];
+
+        for query_word in query_words {
+            let candidates: HashSet<usize> = trigrams(query_word)
+                .iter()
+                .filter_map(|trigram| self.postings.get(trigram))
+                .flatten()
+                .copied()
+                .collect();
+
+            let mut best: HashMap<usize, f64> = HashMap::new();
+            for id in candidates {
+                let (word, documents) = &self.words[id];
+                let similarity = word_similarity(query_word, word);
+                if similarity < threshold {
+                    continue;
+                }
+                for &document in documents {
+                    let entry = best.entry(document).or_insert(0.0);
+                    *entry = entry.max(similarity);
+                }
+            }
+
+            for (document, similarity) in best {
+                totals[document].0 += 1;
+                totals[document].1 += similarity;
+            }
+        }
+
+        let mut results: Vec<(usize, f64)> = totals
+            .into_iter()
+            .enumerate()
+            .filter(|(_, (found, _))| *found == query_words.len())
+            .map(|(document, (_, total))| {
+                let similarity = if query_words.is_empty() { 1.0 } else { total / query_words.len() as f64 };
+                (document, similarity)
+            })
+            .collect();
+        results.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_similarity_tolerates_typos_and_prefixes() {
+        assert_eq!(word_similarity("parser", "parser"), 1.0);
+        assert!(word_similarity("lifetmes", "lifetimes") > DEFAULT_FUZZY_THRESHOLD);
+        assert_eq!(word_similarity("refac", "refactor"), PREFIX_SIMILARITY);
+        assert!(word_similarity("rust", "trust") < PREFIX_SIMILARITY);
+        assert!(word_similarity("docker", "lifetimes") < DEFAULT_FUZZY_THRESHOLD);
+    }
+
+    #[test]
+    fn test_search_needs_every_word_and_ranks_closer_matches_first() {
+        let index = FuzzyIndex::new([
+            "Explain Rust lifetimes",
+            "Explain Rust lifetime elision",
+            "Refactor the parser",
+            "Write a Dockerfile",
+        ]);
+        let words = |query: &str| search_index::tokenize(query);
+
+        let results = index.search(&words("rust lifetimes"), DEFAULT_FUZZY_THRESHOLD);
+        assert_eq!(results.iter().map(|(document, _)| *document).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(results[0].1, 1.0);
+        assert!(results[1].1 < 1.0);
+
+        assert_eq!(index.search(&words("refactr parsr"), DEFAULT_FUZZY_THRESHOLD)[0].0, 2);
+        assert!(index.search(&words("refactor docker"), DEFAULT_FUZZY_THRESHOLD).is_empty());
+        assert_eq!(index.search(&[], DEFAULT_FUZZY_THRESHOLD).len(), 4);
+    }
+}
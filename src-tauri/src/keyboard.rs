@@ -0,0 +1,304 @@
+use std::collections::VecDeque;
+use std::sync::{Mutex, Once};
+use std::time::{Duration, Instant};
+
+use rdev::{Event, EventType, Key};
+
+use crate::capture::{KeyboardSource, Keystroke};
+use crate::models::Result;
+
+// Keystrokes are only queued while the monitor keeps polling, so turning capture off stops recording
+const POLL_GRACE: Duration = Duration::from_secs(2);
+const MAX_QUEUED: usize = 10_000;
+
+/// What a keystroke asks of whoever feeds the buffer
+#[derive(Debug, PartialEq)]
+pub enum KeyAction {
+    Nothing,
+    /// Paste shortcut; the caller inserts the clipboard text
+    Paste,
+    /// Enter (Cmd/Ctrl+Enter in editors) was pressed on this non-empty text
+    Submit(String),
+}
+
+/// Rebuilds the text of an input field from the keys typed into it.
+///
+/// Only the caret is tracked, not selections: select-all is the one selection understood,
+/// and mouse clicks that move the caret go unnoticed.
+#[derive(Debug, Default)]
+pub struct KeyBuffer {
+    text: Vec<char>,
+    cursor: usize,
+    all_selected: bool,
+    shift: bool,
+    control: bool,
+    alt: bool,
+    meta: bool,
+    command_submits: bool,
+}
+
+impl KeyBuffer {
+    /// Makes plain Enter type a newline and only Cmd/Ctrl+Enter submit, as in code editors
+    pub fn submit_with_command(&mut self, enabled: bool) {
+        self.command_submits = enabled;
+    }
+
+    pub fn handle(&mut self, keystroke: &Keystroke) -> KeyAction {
+        match keystroke.key {
+            Key::ShiftLeft | Key::ShiftRight => self.shift = keystroke.pressed,
+            Key::ControlLeft | Key::ControlRight => self.control = keystroke.pressed,
+            Key::Alt | Key::AltGr => self.alt = keystroke.pressed,
+            Key::MetaLeft | Key::MetaRight => self.meta = keystroke.pressed,
+            _ if !keystroke.pressed => {}
+            key => return self.press(key, keystroke.text.as_deref()),
+        }
+        KeyAction::Nothing
+    }
+
+    fn press(&mut self, key: Key, text: Option<&str>) -> KeyAction {
+        // Ctrl on Linux and Windows, Cmd on macOS
+        let command = self.control || self.meta;
+
+        match key {
+            Key::Return | Key::KpReturn if self.shift => self.insert("\n"),
+            Key::Return | Key::KpReturn if self.command_submits && !command => self.insert("\n"),
+            Key::Return | Key::KpReturn => {
+                let submitted: String = self.text.iter().collect();
+                self.clear();
+                if !submitted.trim().is_empty() {
+                    return KeyAction::Submit(submitted.trim().to_string());
+                }
+            }
+            Key::KeyV if command => return KeyAction::Paste,
+            Key::Insert if self.shift => return KeyAction::Paste,
+            Key::KeyA if command => self.all_selected = true,
+            Key::KeyX if command && self.all_selected => self.clear(),
+            Key::Backspace | Key::Delete | Key::KpDelete if self.all_selected => self.clear(),
+            Key::Backspace if self.meta => self.delete_back(self.line_start()),
+            Key::Backspace if self.control || self.alt => self.delete_back(self.word_start()),
+            Key::Backspace => self.delete_back(self.cursor.saturating_sub(1)),
+            Key::Delete | Key::KpDelete => {
+                if self.cursor < self.text.len() {
+                    self.text.remove(self.cursor);
+                }
+            }
+            Key::LeftArrow | Key::Home if self.all_selected => self.move_to(0),
+            Key::RightArrow | Key::End if self.all_selected => self.move_to(self.text.len()),
+            Key::Home => self.move_to(self.line_start()),
+            Key::End => self.move_to(self.line_end()),
+            Key::LeftArrow if self.meta => self.move_to(self.line_start()),
+            Key::RightArrow if self.meta => self.move_to(self.line_end()),
+            Key::LeftArrow => self.move_to(self.cursor.saturating_sub(1)),
+            Key::RightArrow => self.move_to((self.cursor + 1).min(self.text.len())),
+            // Other shortcuts don't type anything
+            _ if command => {}
+            _ => {
+                if let Some(text) = text.filter(|text| !text.is_empty() && !text.chars().any(char::is_control)) {
+                    self.insert(text);
+                }
+            }
+        }
+
+        KeyAction::Nothing
+    }
+
+    /// Types `text` at the caret, replacing everything when it is all selected
+    pub fn insert(&mut self, text: &str) {
+        if self.all_selected {
+            self.clear();
+        }
+        let inserted: Vec<char> = text.chars().collect();
+        let count = inserted.len();
+        self.text.splice(self.cursor..self.cursor, inserted);
+        self.cursor += count;
+    }
+
+    /// Forgets the text, keeping which modifiers are held
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+        self.all_selected = false;
+    }
+
+    fn move_to(&mut self, position: usize) {
+        self.cursor = position;
+        self.all_selected = false;
+    }
+
+    fn delete_back(&mut self, start: usize) {
+        self.text.drain(start..self.cursor);
+        self.cursor = start;
+    }
+
+    fn line_start(&self) -> usize {
+        self.text[..self.cursor].iter().rposition(|&c| c == '\n').map_or(0, |newline| newline + 1)
+    }
+
+    fn line_end(&self) -> usize {
+        self.text[self.cursor..].iter().position(|&c| c == '\n').map_or(self.text.len(), |offset| self.cursor + offset)
+    }
+
+    /// Start of the word before the caret, skipping whitespace first like editors do
+    fn word_start(&self) -> usize {
+        let before = &self.text[..self.cursor];
+        let word_end = before.iter().rposition(|c| !c.is_whitespace()).map_or(0, |last| last + 1);
+        before[..word_end].iter().rposition(|c| c.is_whitespace()).map_or(0, |space| space + 1)
+    }
+}
+
+/// Global keyboard hook through rdev, installed the first time keystrokes are polled.
+///
+/// macOS needs the Accessibility permission and Linux an X11 session with the RECORD
+/// extension; without them no keystrokes are reported.
+pub struct RdevKeyboard;
+
+struct Queue {
+    keystrokes: VecDeque<Keystroke>,
+    last_poll: Option<Instant>,
+}
+
+static QUEUE: Mutex<Queue> = Mutex::new(Queue { keystrokes: VecDeque::new(), last_poll: None });
+static LISTENER: Once = Once::new();
+
+impl KeyboardSource for RdevKeyboard {
+    fn keystrokes(&mut self) -> Result<Vec<Keystroke>> {
+        LISTENER.call_once(|| {
+            std::thread::spawn(|| {
+                println!("[MONITOR] Starting keyboard listener");
+                // Blocks for the rest of the process when the hook works
+                if let Err(e) = rdev::listen(record) {
+                    println!("[MONITOR] ⚠️  Keyboard capture unavailable: {:?}", e);
+                }
+            });
+        });
+
+        let mut queue = QUEUE.lock().unwrap();
+        queue.last_poll = Some(Instant::now());
+        Ok(queue.keystrokes.drain(..).collect())
+    }
+}
+
+/// rdev callback; runs on the listener thread
+fn record(event: Event) {
+    let (key, pressed) = match event.event_type {
+        EventType::KeyPress(key) => (key, true),
+        EventType::KeyRelease(key) => (key, false),
+        _ => return,
+    };
+
+    let Ok(mut queue) = QUEUE.lock() else {
+        return;
+    };
+    if queue.last_poll.is_none_or(|last_poll| last_poll.elapsed() >= POLL_GRACE) {
+        queue.keystrokes.clear();
+        return;
+    }
+    if queue.keystrokes.len() >= MAX_QUEUED {
+        queue.keystrokes.pop_front();
+    }
+    queue.keystrokes.push_back(Keystroke { key, pressed, text: if pressed { event.name } else { None } });
+}
+
+/// Press and release of each character of `text`, with Enter for newlines
+#[cfg(test)]
+pub fn typing(text: &str) -> Vec<Keystroke> {
+    text.chars()
+        .flat_map(|c| match c {
+            '\n' => tap(Key::Return),
+            c => vec![
+                Keystroke { key: Key::Unknown(c as u32), pressed: true, text: Some(c.to_string()) },
+                Keystroke { key: Key::Unknown(c as u32), pressed: false, text: None },
+            ],
+        })
+        .collect()
+}
+
+/// Press and release of a key that types nothing
+#[cfg(test)]
+pub fn tap(key: Key) -> Vec<Keystroke> {
+    vec![
+        Keystroke { key, pressed: true, text: None },
+        Keystroke { key, pressed: false, text: None },
+    ]
+}
+
+/// `key` pressed while holding `modifier`
+#[cfg(test)]
+pub fn chord(modifier: Key, key: Key) -> Vec<Keystroke> {
+    let mut keystrokes = vec![Keystroke { key: modifier, pressed: true, text: None }];
+    keystrokes.extend(tap(key));
+    keystrokes.push(Keystroke { key: modifier, pressed: false, text: None });
+    keystrokes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(buffer: &mut KeyBuffer, keystrokes: Vec<Keystroke>) -> Vec<KeyAction> {
+        keystrokes
+            .iter()
+            .map(|keystroke| buffer.handle(keystroke))
+            .filter(|action| *action != KeyAction::Nothing)
+            .collect()
+    }
+
+    #[test]
+    fn test_enter_submits_edited_text() {
+        let mut buffer = KeyBuffer::default();
+        let mut keystrokes = typing("Explain lifetimse");
+        keystrokes.extend(tap(Key::Backspace));
+        keystrokes.extend(tap(Key::Backspace));
+        keystrokes.extend(typing("es in Rust"));
+        // Fix "Explain" to "Please explain" at the start of the line
+        keystrokes.extend(tap(Key::Home));
+        keystrokes.extend(tap(Key::Delete));
+        keystrokes.extend(typing("Please e"));
+        keystrokes.extend(tap(Key::End));
+        keystrokes.extend(chord(Key::ShiftLeft, Key::Return));
+        keystrokes.extend(typing("with an exampel"));
+        keystrokes.extend(chord(Key::ControlLeft, Key::Backspace));
+        keystrokes.extend(typing("example\n"));
+
+        assert_eq!(
+            feed(&mut buffer, keystrokes),
+            vec![KeyAction::Submit("Please explain lifetimes in Rust\nwith an example".to_string())]
+        );
+        // The buffer starts over after a submission and blank input submits nothing
+        assert_eq!(feed(&mut buffer, typing("  \n")), vec![]);
+    }
+
+    #[test]
+    fn test_paste_and_select_all() {
+        let mut buffer = KeyBuffer::default();
+        let mut keystrokes = typing("Summarize: ");
+        keystrokes.extend(chord(Key::MetaLeft, Key::KeyV));
+        assert_eq!(feed(&mut buffer, keystrokes), vec![KeyAction::Paste]);
+        buffer.insert("the release notes");
+        assert_eq!(feed(&mut buffer, typing("\n")), vec![KeyAction::Submit("Summarize: the release notes".to_string())]);
+
+        let mut keystrokes = typing("draft that gets thrown away");
+        keystrokes.extend(chord(Key::ControlLeft, Key::KeyA));
+        keystrokes.extend(typing("Write a haiku about Rust\n"));
+        assert_eq!(feed(&mut buffer, keystrokes), vec![KeyAction::Submit("Write a haiku about Rust".to_string())]);
+
+        // Shortcuts type nothing even though the key has a character
+        let mut keystrokes = vec![Keystroke { key: Key::ControlLeft, pressed: true, text: None }];
+        keystrokes.push(Keystroke { key: Key::KeyS, pressed: true, text: Some("s".to_string()) });
+        keystrokes.push(Keystroke { key: Key::ControlLeft, pressed: false, text: None });
+        keystrokes.extend(typing("ok\n"));
+        assert_eq!(feed(&mut buffer, keystrokes), vec![KeyAction::Submit("ok".to_string())]);
+    }
+
+    #[test]
+    fn test_editors_submit_on_command_enter() {
+        let mut buffer = KeyBuffer::default();
+        buffer.submit_with_command(true);
+        assert_eq!(feed(&mut buffer, typing("fn main() {\n    run();\n}\n")), vec![]);
+
+        buffer.clear();
+        let mut keystrokes = typing("Explain this error");
+        keystrokes.extend(chord(Key::MetaLeft, Key::Return));
+        assert_eq!(feed(&mut buffer, keystrokes), vec![KeyAction::Submit("Explain this error".to_string())]);
+    }
+}
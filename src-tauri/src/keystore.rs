@@ -0,0 +1,189 @@
+use std::path::PathBuf;
+
+use keyring::Entry;
+
+use crate::crypto::PassphraseHeader;
+use crate::models::{KeyStorage, MonitoringConfig, PromptHistError, Result};
+
+/// Environment variable holding a base64 key (or serialized key set) for `KeyStorage::Environment`
+pub const ENCRYPTION_KEY_ENV: &str = "PROMPTHIST_ENCRYPTION_KEY";
+
+/// Environment variable holding the passphrase for `KeyStorage::PassphraseFile`
+pub const KEY_PASSPHRASE_ENV: &str = "PROMPTHIST_KEY_PASSPHRASE";
+
+/// Persistence for the serialized data keys managed by `CryptoManager`
+pub trait KeyStore: Send + Sync {
+    /// Returns the stored keys, or `None` if nothing has been stored yet
+    fn load(&self) -> Result<Option<String>>;
+
+    fn store(&self, serialized: &str) -> Result<()>;
+}
+
+/// Builds the key store selected in the configuration
+pub fn from_config(config: &MonitoringConfig) -> Result<Box<dyn KeyStore>> {
+    match config.key_storage {
+        KeyStorage::Keyring => Ok(Box::new(KeyringStore::new()?)),
+        KeyStorage::PassphraseFile => {
+            let passphrase = std::env::var(KEY_PASSPHRASE_ENV).map_err(|_| {
+                PromptHistError::Encryption(format!("{} must be set to use a passphrase key file", KEY_PASSPHRASE_ENV))
+            })?;
+            let path = match &config.key_file {
+                Some(path) => PathBuf::from(path),
+                None => PassphraseFileStore::default_path()?,
+            };
+            Ok(Box::new(PassphraseFileStore::new(path, passphrase)))
+        }
+        KeyStorage::Environment => Ok(Box::new(EnvKeyStore::new(ENCRYPTION_KEY_ENV))),
+        KeyStorage::Passphrase => Err(PromptHistError::Encryption(
+            "Passphrase keys are stored in the database and unlocked at runtime".to_string(),
+        )),
+    }
+}
+
+/// OS keyring (Keychain, Secret Service, Credential Manager)
+pub struct KeyringStore {
+    entry: Entry,
+}
+
+impl KeyringStore {
+    pub fn new() -> Result<Self> {
+        let entry = Entry::new("prompthist", "encryption_key")
+            .map_err(|e| PromptHistError::Encryption(format!("Failed to create keyring entry: {}", e)))?;
+        Ok(Self { entry })
+    }
+}
+
+impl KeyStore for KeyringStore {
+    fn load(&self) -> Result<Option<String>> {
+        match self.entry.get_password() {
+            Ok(stored) => Ok(Some(stored)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(PromptHistError::Encryption(format!("Failed to read key: {}", e))),
+        }
+    }
+
+    fn store(&self, serialized: &str) -> Result<()> {
+        self.entry.set_password(serialized)
+            .map_err(|e| PromptHistError::Encryption(format!("Failed to store key: {}", e)))
+    }
+}
+
+/// JSON file holding the keys wrapped by an Argon2id-derived key, for machines without a keyring
+pub struct PassphraseFileStore {
+    path: PathBuf,
+    passphrase: String,
+}
+
+impl PassphraseFileStore {
+    pub fn new(path: PathBuf, passphrase: String) -> Self {
+        Self { path, passphrase }
+    }
+
+    pub fn default_path() -> Result<PathBuf> {
+        let mut path = dirs::config_dir()
+            .ok_or_else(|| PromptHistError::InvalidInput("Could not find config directory".to_string()))?;
+
+        path.push("prompthist");
+        path.push("keys.json");
+
+        Ok(path)
+    }
+}
+
+impl KeyStore for PassphraseFileStore {
+    fn load(&self) -> Result<Option<String>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let content = std::fs::read_to_string(&self.path)?;
+        let header: PassphraseHeader = serde_json::from_str(&content)?;
+        header.unwrap(&self.passphrase).map(Some)
+    }
+
+    fn store(&self, serialized: &str) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let header = PassphraseHeader::wrap(&self.passphrase, serialized)?;
+        // Write then rename so a crash never leaves a truncated key file
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(&header)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Read-only key supplied through an environment variable, e.g. by a secrets manager
+pub struct EnvKeyStore {
+    variable: String,
+}
+
+impl EnvKeyStore {
+    pub fn new(variable: &str) -> Self {
+        Self { variable: variable.to_string() }
+    }
+}
+
+impl KeyStore for EnvKeyStore {
+    fn load(&self) -> Result<Option<String>> {
+        // Never generate a key here: it would be lost as soon as the process exits
+        std::env::var(&self.variable)
+            .map(Some)
+            .map_err(|_| PromptHistError::Encryption(format!("{} is not set", self.variable)))
+    }
+
+    fn store(&self, _serialized: &str) -> Result<()> {
+        Err(PromptHistError::Encryption(format!(
+            "Keys provided through {} cannot be changed by the application",
+            self.variable
+        )))
+    }
+}
+
+/// Keeps keys in memory only; for tests
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryKeyStore {
+    value: std::sync::Mutex<Option<String>>,
+}
+
+#[cfg(test)]
+impl KeyStore for MemoryKeyStore {
+    fn load(&self) -> Result<Option<String>> {
+        Ok(self.value.lock().unwrap().clone())
+    }
+
+    fn store(&self, serialized: &str) -> Result<()> {
+        *self.value.lock().unwrap() = Some(serialized.to_string());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passphrase_file_roundtrip() {
+        let path = std::env::temp_dir().join(format!("prompthist-keys-{}.json", uuid::Uuid::new_v4()));
+        let store = PassphraseFileStore::new(path.clone(), "open sesame".to_string());
+
+        assert!(store.load().unwrap().is_none());
+        store.store("{\"active\":\"a\",\"keys\":{}}").unwrap();
+        assert_eq!(store.load().unwrap().as_deref(), Some("{\"active\":\"a\",\"keys\":{}}"));
+
+        let wrong = PassphraseFileStore::new(path.clone(), "wrong".to_string());
+        assert!(wrong.load().is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_env_store_is_read_only() {
+        let store = EnvKeyStore::new("PROMPTHIST_TEST_KEY_UNSET");
+        assert!(store.load().is_err());
+        assert!(store.store("key").is_err());
+    }
+}
@@ -1,18 +1,36 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use reqwest;
 
 mod models;
+mod migrations;
 mod prompt_storage;
+mod capture;
 mod crypto;
+#[cfg(target_os = "linux")]
+mod clipboard_linux;
+#[cfg(target_os = "linux")]
+mod x11_window;
+mod diff;
+mod fuzzy;
+mod keyboard;
+mod keystore;
+mod search_index;
+mod search_query;
+mod similarity;
+mod templates;
 mod monitor;
+mod ollama;
+mod workflows;
 
 use crate::models::*;
+use crate::crypto::CryptoManager;
 use crate::prompt_storage::PromptDatabase;
 use crate::monitor::SystemMonitor;
+use crate::ollama::OllamaClient;
 
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
 #[tauri::command]
@@ -29,9 +47,9 @@ async fn save_prompt(
     entry.starred = false;
     entry.tags = vec![];
     entry.usage_count = 0;
-    entry.is_encrypted = false;
 
     match state.db.save_prompt(&entry).await {
+        Ok(id) if id != entry.id => Ok("Prompt already saved, usage count updated".to_string()),
         Ok(_) => Ok("Prompt saved successfully".to_string()),
         Err(e) => Err(format!("Failed to save prompt: {}", e)),
     }
@@ -40,6 +58,7 @@ async fn save_prompt(
 #[tauri::command]
 async fn get_prompts(
     filter: PromptFilter,
+    sort: Option<PromptSort>,
     limit: Option<i32>,
     offset: Option<i32>,
     state: tauri::State<'_, AppState>,
@@ -49,7 +68,7 @@ async fn get_prompts(
     
     println!("[DB] Fetching prompts: limit={}, offset={}", effective_limit, effective_offset);
     
-    match state.db.get_prompts(Some(filter), Some(effective_limit), Some(effective_offset)).await {
+    match state.db.get_prompts(Some(filter), sort.unwrap_or_default(), Some(effective_limit), Some(effective_offset)).await {
         Ok(prompts) => {
             println!("[DB] ✅ Successfully fetched {} prompts", prompts.len());
             Ok(prompts)
@@ -61,6 +80,20 @@ async fn get_prompts(
     }
 }
 
+#[tauri::command]
+async fn get_prompts_page(
+    filter: PromptFilter,
+    sort: Option<PromptSort>,
+    limit: Option<i32>,
+    cursor: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<PromptPage, String> {
+    match state.db.get_prompts_page(Some(&filter), sort.unwrap_or_default(), limit.unwrap_or(50), cursor.as_deref()).await {
+        Ok(page) => Ok(page),
+        Err(e) => Err(format!("Failed to get prompts: {}", e)),
+    }
+}
+
 #[tauri::command]
 async fn get_prompt_by_id(
     id: String,
@@ -86,6 +119,42 @@ async fn update_prompt(
     }
 }
 
+#[tauri::command]
+async fn get_prompt_revisions(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Vec<PromptRevision>, String> {
+    match state.db.get_prompt_revisions(&id).await {
+        Ok(revisions) => Ok(revisions),
+        Err(e) => Err(format!("Failed to get prompt revisions: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn diff_prompt_revisions(
+    id: String,
+    from_revision: i64,
+    to_revision: Option<i64>,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<RevisionDiff, String> {
+    match state.db.diff_prompt_revisions(&id, from_revision, to_revision).await {
+        Ok(diff) => Ok(diff),
+        Err(e) => Err(format!("Failed to diff prompt revisions: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn restore_prompt_revision(
+    id: String,
+    revision: i64,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<PromptEntry, String> {
+    match state.db.restore_prompt_revision(&id, revision).await {
+        Ok(prompt) => Ok(prompt),
+        Err(e) => Err(format!("Failed to restore prompt revision: {}", e)),
+    }
+}
+
 #[tauri::command]
 async fn delete_prompt(
     id: String,
@@ -97,6 +166,383 @@ async fn delete_prompt(
     }
 }
 
+#[tauri::command]
+async fn list_tags(
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Vec<TagCount>, String> {
+    match state.db.list_tags().await {
+        Ok(tags) => Ok(tags),
+        Err(e) => Err(format!("Failed to list tags: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn rename_tag(
+    old_name: String,
+    new_name: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<i64, String> {
+    match state.db.rename_tag(&old_name, &new_name).await {
+        Ok(changed) => Ok(changed),
+        Err(e) => Err(format!("Failed to rename tag: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn merge_tags(
+    sources: Vec<String>,
+    target: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<i64, String> {
+    match state.db.merge_tags(&sources, &target).await {
+        Ok(changed) => Ok(changed),
+        Err(e) => Err(format!("Failed to merge tags: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn delete_tag(
+    name: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<i64, String> {
+    match state.db.delete_tag(&name).await {
+        Ok(changed) => Ok(changed),
+        Err(e) => Err(format!("Failed to delete tag: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn create_collection(
+    name: String,
+    parent_id: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Collection, String> {
+    match state.db.create_collection(&name, parent_id.as_deref()).await {
+        Ok(collection) => Ok(collection),
+        Err(e) => Err(format!("Failed to create collection: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn rename_collection(
+    id: String,
+    name: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<String, String> {
+    match state.db.rename_collection(&id, &name).await {
+        Ok(_) => Ok("Collection renamed successfully".to_string()),
+        Err(e) => Err(format!("Failed to rename collection: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn move_collection(
+    id: String,
+    parent_id: Option<String>,
+    position: Option<i64>,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<String, String> {
+    match state.db.move_collection(&id, parent_id.as_deref(), position).await {
+        Ok(_) => Ok("Collection moved successfully".to_string()),
+        Err(e) => Err(format!("Failed to move collection: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn delete_collection(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<String, String> {
+    match state.db.delete_collection(&id).await {
+        Ok(_) => Ok("Collection deleted successfully".to_string()),
+        Err(e) => Err(format!("Failed to delete collection: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn get_collection_tree(
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Vec<CollectionNode>, String> {
+    match state.db.get_collection_tree().await {
+        Ok(tree) => Ok(tree),
+        Err(e) => Err(format!("Failed to get collections: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn get_collection_prompts(
+    collection_id: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Vec<PromptEntry>, String> {
+    match state.db.get_collection_prompts(&collection_id).await {
+        Ok(prompts) => Ok(prompts),
+        Err(e) => Err(format!("Failed to get collection prompts: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn add_prompt_to_collection(
+    collection_id: String,
+    prompt_id: String,
+    position: Option<i64>,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<String, String> {
+    match state.db.add_prompt_to_collection(&collection_id, &prompt_id, position).await {
+        Ok(_) => Ok("Prompt added to collection".to_string()),
+        Err(e) => Err(format!("Failed to add prompt to collection: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn remove_prompt_from_collection(
+    collection_id: String,
+    prompt_id: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<String, String> {
+    match state.db.remove_prompt_from_collection(&collection_id, &prompt_id).await {
+        Ok(_) => Ok("Prompt removed from collection".to_string()),
+        Err(e) => Err(format!("Failed to remove prompt from collection: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn reorder_collection_prompts(
+    collection_id: String,
+    prompt_ids: Vec<String>,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<String, String> {
+    match state.db.reorder_collection_prompts(&collection_id, &prompt_ids).await {
+        Ok(_) => Ok("Collection reordered successfully".to_string()),
+        Err(e) => Err(format!("Failed to reorder collection: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn create_template(
+    name: String,
+    description: Option<String>,
+    body: String,
+    variables: Vec<TemplateVariable>,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<PromptTemplate, String> {
+    match state.db.create_template(&name, description.as_deref(), &body, &variables).await {
+        Ok(template) => Ok(template),
+        Err(e) => Err(format!("Failed to create template: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn update_template(
+    id: String,
+    name: String,
+    description: Option<String>,
+    body: String,
+    variables: Vec<TemplateVariable>,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<PromptTemplate, String> {
+    match state.db.update_template(&id, &name, description.as_deref(), &body, &variables).await {
+        Ok(template) => Ok(template),
+        Err(e) => Err(format!("Failed to update template: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn delete_template(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<String, String> {
+    match state.db.delete_template(&id).await {
+        Ok(_) => Ok("Template deleted successfully".to_string()),
+        Err(e) => Err(format!("Failed to delete template: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn get_template(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Option<PromptTemplate>, String> {
+    match state.db.get_template(&id).await {
+        Ok(template) => Ok(template),
+        Err(e) => Err(format!("Failed to get template: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn list_templates(
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Vec<PromptTemplate>, String> {
+    match state.db.list_templates().await {
+        Ok(templates) => Ok(templates),
+        Err(e) => Err(format!("Failed to list templates: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn render_template(
+    id: String,
+    values: HashMap<String, String>,
+    save: Option<bool>,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<RenderedTemplate, String> {
+    match state.db.render_template(&id, &values, save.unwrap_or(false)).await {
+        Ok(rendered) => Ok(rendered),
+        Err(e) => Err(format!("Failed to render template: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn get_template_prompts(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Vec<PromptEntry>, String> {
+    match state.db.get_template_prompts(&id).await {
+        Ok(prompts) => Ok(prompts),
+        Err(e) => Err(format!("Failed to get template prompts: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn create_workflow(
+    name: String,
+    description: Option<String>,
+    steps: Vec<WorkflowStep>,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Workflow, String> {
+    match state.db.create_workflow(&name, description.as_deref(), &steps).await {
+        Ok(workflow) => Ok(workflow),
+        Err(e) => Err(format!("Failed to create workflow: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn update_workflow(
+    id: String,
+    name: String,
+    description: Option<String>,
+    steps: Vec<WorkflowStep>,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Workflow, String> {
+    match state.db.update_workflow(&id, &name, description.as_deref(), &steps).await {
+        Ok(workflow) => Ok(workflow),
+        Err(e) => Err(format!("Failed to update workflow: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn delete_workflow(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<String, String> {
+    match state.db.delete_workflow(&id).await {
+        Ok(_) => Ok("Workflow deleted successfully".to_string()),
+        Err(e) => Err(format!("Failed to delete workflow: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn get_workflow(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Option<Workflow>, String> {
+    match state.db.get_workflow(&id).await {
+        Ok(workflow) => Ok(workflow),
+        Err(e) => Err(format!("Failed to get workflow: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn list_workflows(
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Vec<Workflow>, String> {
+    match state.db.list_workflows().await {
+        Ok(workflows) => Ok(workflows),
+        Err(e) => Err(format!("Failed to list workflows: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn run_workflow(
+    id: String,
+    model: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<WorkflowRun, String> {
+    match workflows::run_workflow(&state.db, &OllamaClient::default(), &id, &model).await {
+        Ok(run) => Ok(run),
+        Err(e) => Err(format!("Failed to run workflow: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn list_workflow_runs(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Vec<WorkflowRun>, String> {
+    match state.db.list_workflow_runs(&id).await {
+        Ok(runs) => Ok(runs),
+        Err(e) => Err(format!("Failed to list workflow runs: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn get_workflow_run(
+    run_id: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Option<WorkflowRun>, String> {
+    match state.db.get_workflow_run(&run_id).await {
+        Ok(run) => Ok(run),
+        Err(e) => Err(format!("Failed to get workflow run: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn list_sessions(
+    application: Option<String>,
+    limit: Option<i32>,
+    offset: Option<i32>,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Vec<Session>, String> {
+    match state.db.list_sessions(application.as_deref(), limit, offset).await {
+        Ok(sessions) => Ok(sessions),
+        Err(e) => Err(format!("Failed to list sessions: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn get_session_prompts(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Vec<PromptEntry>, String> {
+    match state.db.get_session_prompts(&id).await {
+        Ok(prompts) => Ok(prompts),
+        Err(e) => Err(format!("Failed to get session prompts: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn split_session(
+    id: String,
+    prompt_id: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Session, String> {
+    match state.db.split_session(&id, &prompt_id).await {
+        Ok(session) => Ok(session),
+        Err(e) => Err(format!("Failed to split session: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn merge_sessions(
+    target_id: String,
+    source_ids: Vec<String>,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Session, String> {
+    match state.db.merge_sessions(&target_id, &source_ids).await {
+        Ok(session) => Ok(session),
+        Err(e) => Err(format!("Failed to merge sessions: {}", e)),
+    }
+}
+
 #[tauri::command]
 async fn get_prompt_stats(
     state: tauri::State<'_, AppState>,
@@ -118,6 +564,125 @@ async fn search_prompts(
     }
 }
 
+#[tauri::command]
+async fn search_prompts_page(
+    query: String,
+    limit: Option<i32>,
+    cursor: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<PromptPage, String> {
+    match state.db.search_prompts_page(&query, limit.unwrap_or(50), cursor.as_deref()).await {
+        Ok(page) => Ok(page),
+        Err(e) => Err(format!("Failed to search prompts: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn search_prompt_hits(
+    query: String,
+    highlight: Option<HighlightOptions>,
+    limit: Option<i32>,
+    cursor: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<SearchHitPage, String> {
+    let highlight = highlight.unwrap_or_default();
+    match state.db.search_hits_page(&query, &highlight, limit.unwrap_or(50), cursor.as_deref()).await {
+        Ok(page) => Ok(page),
+        Err(e) => Err(format!("Failed to search prompts: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn fuzzy_search_prompts(
+    query: String,
+    threshold: Option<f64>,
+    limit: Option<i32>,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<FuzzySearchResults, String> {
+    let threshold = threshold.unwrap_or(fuzzy::DEFAULT_FUZZY_THRESHOLD);
+    match state.db.fuzzy_search_prompts(&query, threshold, limit).await {
+        Ok(matches) => Ok(matches),
+        Err(e) => Err(format!("Failed to search prompts: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn get_duplicate_clusters(
+    threshold: Option<f64>,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Vec<PromptCluster>, String> {
+    let threshold = threshold.unwrap_or(similarity::DEFAULT_SIMILARITY_THRESHOLD);
+    match state.db.find_near_duplicates(threshold).await {
+        Ok(clusters) => Ok(clusters),
+        Err(e) => Err(format!("Failed to find duplicate prompts: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn merge_prompts(
+    primary_id: String,
+    duplicate_ids: Vec<String>,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<PromptEntry, String> {
+    match state.db.merge_prompts(&primary_id, &duplicate_ids).await {
+        Ok(prompt) => Ok(prompt),
+        Err(e) => Err(format!("Failed to merge prompts: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn rotate_encryption_key(
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<KeyRotationReport, String> {
+    match state.db.rotate_encryption_key().await {
+        Ok(report) => Ok(report),
+        Err(e) => Err(format!("Failed to rotate encryption key: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn unlock_database(
+    passphrase: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<String, String> {
+    match state.db.unlock_with_passphrase(&passphrase).await {
+        Ok(_) => Ok("Database unlocked".to_string()),
+        Err(e) => Err(format!("Failed to unlock database: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn lock_database(
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<String, String> {
+    match state.db.lock().await {
+        Ok(_) => Ok("Database locked".to_string()),
+        Err(e) => Err(format!("Failed to lock database: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn is_database_locked(
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<bool, String> {
+    Ok(state.db.is_locked())
+}
+
+#[tauri::command]
+async fn is_database_encrypted(
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<bool, String> {
+    Ok(state.db.encryption_enabled())
+}
+
+/// Why encryption could not be set up at startup, in which case prompts are stored unencrypted
+#[tauri::command]
+async fn get_encryption_warning(
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Option<String>, String> {
+    Ok(state.encryption_warning.clone())
+}
+
 #[tauri::command]
 async fn get_system_info() -> std::result::Result<SystemInfo, String> {
     let system_info = SystemInfo {
@@ -160,6 +725,14 @@ async fn get_monitoring_status(
     Ok(monitor.is_running())
 }
 
+#[tauri::command]
+async fn get_detected_applications(
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Vec<DetectedApplication>, String> {
+    let monitor = state.monitor.lock().await;
+    Ok(monitor.get_detected_applications())
+}
+
 #[tauri::command]
 async fn get_monitoring_config(
     state: tauri::State<'_, AppState>,
@@ -186,37 +759,94 @@ async fn update_monitoring_config(
 #[tauri::command]
 async fn send_prompt_to_ollama(
     request: OllamaRequest,
+    state: tauri::State<'_, AppState>,
 ) -> std::result::Result<OllamaResponse, String> {
-    let client = reqwest::Client::new();
-    let response = client
-        .post("http://localhost:11434/api/generate")
-        .json(&request)
-        .send()
-        .await
-        .map_err(|e| format!("Request failed: {}", e))?;
+    let started = std::time::Instant::now();
+    let response = match OllamaClient::default().generate(&request).await {
+        Ok(response) => response,
+        Err(e) => return Err(format!("Request failed: {}", e)),
+    };
+    let elapsed_ms = started.elapsed().as_millis() as i64;
 
-    let ollama_response: OllamaResponse = response
-        .json()
-        .await
-        .map_err(|e| format!("Failed to parse response: {}", e))?;
+    // Sent prompts go to history like captured ones; an identical prompt gets the response instead
+    let entry = PromptEntry {
+        id: uuid::Uuid::new_v4().to_string(),
+        content: request.prompt.clone(),
+        application: "ollama".to_string(),
+        timestamp: chrono::Utc::now(),
+        starred: false,
+        tags: vec![],
+        usage_count: 0,
+        is_encrypted: false,
+        last_used: None,
+        template_id: None,
+        session_id: None,
+        window_title: None,
+    };
+    let recorded = match state.db.save_prompt(&entry).await {
+        Ok(prompt_id) => state.db.save_response(&prompt_id, &response, elapsed_ms).await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = recorded {
+        println!("[DB] Failed to store Ollama response: {}", e);
+    }
 
-    Ok(ollama_response)
+    Ok(response)
+}
+
+#[tauri::command]
+async fn get_prompt_responses(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Vec<PromptResponse>, String> {
+    match state.db.get_prompt_responses(&id).await {
+        Ok(responses) => Ok(responses),
+        Err(e) => Err(format!("Failed to get responses: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn get_prompt_with_responses(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<Option<PromptWithResponses>, String> {
+    match state.db.get_prompt_with_responses(&id).await {
+        Ok(prompt) => Ok(prompt),
+        Err(e) => Err(format!("Failed to get prompt: {}", e)),
+    }
+}
+
+#[tauri::command]
+async fn delete_response(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> std::result::Result<String, String> {
+    match state.db.delete_response(&id).await {
+        Ok(_) => Ok("Response deleted successfully".to_string()),
+        Err(e) => Err(format!("Failed to delete response: {}", e)),
+    }
 }
 
 struct AppState {
     db: Arc<PromptDatabase>,
     monitor: Arc<Mutex<SystemMonitor>>,
+    encryption_warning: Option<String>,
 }
 
 #[tokio::main]
 async fn main() {
-    let db = match PromptDatabase::new().await {
-        Ok(database) => Arc::new(database),
-        Err(e) => {
-            eprintln!("Failed to initialize database: {}", e);
-            return;
+    // `prompthist --rollback-schema <version>` downgrades the database for an older build, then exits
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(position) = args.iter().position(|arg| arg == "--rollback-schema") {
+        match args.get(position + 1).and_then(|version| version.parse::<i64>().ok()) {
+            Some(target) => match PromptDatabase::rollback_schema(target).await {
+                Ok(()) => println!("[DB] Schema rolled back to version {}", target),
+                Err(e) => eprintln!("Failed to roll back schema: {}", e),
+            },
+            None => eprintln!("Usage: prompthist --rollback-schema <version>"),
         }
-    };
+        return;
+    }
 
     let config = MonitoringConfig::load_from_file()
         .unwrap_or_else(|e| {
@@ -228,12 +858,42 @@ async fn main() {
     if let Err(e) = config.save_to_file() {
         eprintln!("Failed to save initial config: {}", e);
     }
+
+    // A missing keyring must not keep the app from opening, so fall back to plaintext and say so
+    let mut encryption_warning = None;
+    let database = if !config.encryption_enabled {
+        PromptDatabase::new(None).await
+    } else {
+        match config.key_storage {
+            // Starts locked until the user enters their passphrase
+            KeyStorage::Passphrase => PromptDatabase::new_passphrase_protected().await,
+            _ => match keystore::from_config(&config).and_then(CryptoManager::with_store) {
+                Ok(crypto) => PromptDatabase::new(Some(crypto)).await,
+                Err(e) => {
+                    let warning = format!("Failed to initialize encryption, new prompts are stored unencrypted: {}", e);
+                    eprintln!("{}", warning);
+                    encryption_warning = Some(warning);
+                    PromptDatabase::new(None).await
+                }
+            },
+        }
+    };
+
+    let db = match database {
+        Ok(database) => Arc::new(database),
+        Err(e) => {
+            eprintln!("Failed to initialize database: {}", e);
+            return;
+        }
+    };
     
-    let monitor = Arc::new(Mutex::new(SystemMonitor::new(config, db.clone())));
+    let sources = capture::platform_sources(&config);
+    let monitor = Arc::new(Mutex::new(SystemMonitor::new(config, db.clone(), sources)));
 
     let app_state = AppState {
         db,
         monitor,
+        encryption_warning,
     };
 
     tauri::Builder::default()
@@ -242,18 +902,69 @@ async fn main() {
             greet,
             save_prompt,
             get_prompts,
+            get_prompts_page,
             get_prompt_by_id,
             update_prompt,
+            get_prompt_revisions,
+            diff_prompt_revisions,
+            restore_prompt_revision,
             delete_prompt,
+            list_tags,
+            rename_tag,
+            merge_tags,
+            delete_tag,
+            create_collection,
+            rename_collection,
+            move_collection,
+            delete_collection,
+            get_collection_tree,
+            get_collection_prompts,
+            add_prompt_to_collection,
+            remove_prompt_from_collection,
+            reorder_collection_prompts,
+            create_template,
+            update_template,
+            delete_template,
+            get_template,
+            list_templates,
+            render_template,
+            get_template_prompts,
+            create_workflow,
+            update_workflow,
+            delete_workflow,
+            get_workflow,
+            list_workflows,
+            run_workflow,
+            list_workflow_runs,
+            get_workflow_run,
+            list_sessions,
+            get_session_prompts,
+            split_session,
+            merge_sessions,
             get_prompt_stats,
             search_prompts,
+            search_prompts_page,
+            search_prompt_hits,
+            fuzzy_search_prompts,
+            get_duplicate_clusters,
+            merge_prompts,
+            rotate_encryption_key,
+            unlock_database,
+            lock_database,
+            is_database_locked,
+            is_database_encrypted,
+            get_encryption_warning,
             get_system_info,
             start_monitoring,
             stop_monitoring,
             get_monitoring_status,
+            get_detected_applications,
             get_monitoring_config,
             update_monitoring_config,
-            send_prompt_to_ollama
+            send_prompt_to_ollama,
+            get_prompt_responses,
+            get_prompt_with_responses,
+            delete_response
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
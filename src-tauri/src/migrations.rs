@@ -0,0 +1,649 @@
+use sqlx::{Row, SqlitePool};
+
+use crate::models::{PromptHistError, Result};
+
+/// A single versioned schema change
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub up: &'static [&'static str],
+    pub down: &'static [&'static str],
+}
+
+/// All known migrations, in ascending version order.
+///
+/// Never edit a migration that has shipped; append a new one instead.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial prompts schema with full-text search",
+        // Uses IF NOT EXISTS so installs created before versioning adopt this step cleanly
+        up: &[
+            r#"
+            CREATE TABLE IF NOT EXISTS prompts (
+                id TEXT PRIMARY KEY,
+                content TEXT NOT NULL,
+                application TEXT NOT NULL,
+                timestamp TEXT NOT NULL,
+                starred BOOLEAN NOT NULL DEFAULT FALSE,
+                tags TEXT NOT NULL DEFAULT '[]',
+                usage_count INTEGER NOT NULL DEFAULT 1,
+                is_encrypted BOOLEAN NOT NULL DEFAULT FALSE,
+                created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+            "CREATE INDEX IF NOT EXISTS idx_prompts_application ON prompts(application)",
+            "CREATE INDEX IF NOT EXISTS idx_prompts_timestamp ON prompts(timestamp)",
+            "CREATE INDEX IF NOT EXISTS idx_prompts_starred ON prompts(starred)",
+            r#"
+            CREATE VIRTUAL TABLE IF NOT EXISTS prompts_fts USING fts5(
+                id,
+                content,
+                application,
+                tags,
+                content='prompts',
+                content_rowid='rowid'
+            )
+            "#,
+            r#"
+            CREATE TRIGGER IF NOT EXISTS prompts_ai AFTER INSERT ON prompts BEGIN
+                INSERT INTO prompts_fts(rowid, id, content, application, tags)
+                VALUES (new.rowid, new.id, new.content, new.application, new.tags);
+            END
+            "#,
+            r#"
+            CREATE TRIGGER IF NOT EXISTS prompts_ad AFTER DELETE ON prompts BEGIN
+                INSERT INTO prompts_fts(prompts_fts, rowid, id, content, application, tags)
+                VALUES('delete', old.rowid, old.id, old.content, old.application, old.tags);
+            END
+            "#,
+            r#"
+            CREATE TRIGGER IF NOT EXISTS prompts_au AFTER UPDATE ON prompts BEGIN
+                INSERT INTO prompts_fts(prompts_fts, rowid, id, content, application, tags)
+                VALUES('delete', old.rowid, old.id, old.content, old.application, old.tags);
+                INSERT INTO prompts_fts(rowid, id, content, application, tags)
+                VALUES (new.rowid, new.id, new.content, new.application, new.tags);
+            END
+            "#,
+        ],
+        down: &[
+            "DROP TRIGGER IF EXISTS prompts_au",
+            "DROP TRIGGER IF EXISTS prompts_ad",
+            "DROP TRIGGER IF EXISTS prompts_ai",
+            "DROP TABLE IF EXISTS prompts_fts",
+            "DROP TABLE IF EXISTS prompts",
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "keep ciphertext out of FTS and add blind token index for encrypted prompts",
+        up: &[
+            "DROP TRIGGER IF EXISTS prompts_ai",
+            "DROP TRIGGER IF EXISTS prompts_ad",
+            "DROP TRIGGER IF EXISTS prompts_au",
+            r#"
+            CREATE TRIGGER prompts_ai AFTER INSERT ON prompts WHEN new.is_encrypted = 0 BEGIN
+                INSERT INTO prompts_fts(rowid, id, content, application, tags)
+                VALUES (new.rowid, new.id, new.content, new.application, new.tags);
+            END
+            "#,
+            r#"
+            CREATE TRIGGER prompts_ad AFTER DELETE ON prompts WHEN old.is_encrypted = 0 BEGIN
+                INSERT INTO prompts_fts(prompts_fts, rowid, id, content, application, tags)
+                VALUES('delete', old.rowid, old.id, old.content, old.application, old.tags);
+            END
+            "#,
+            r#"
+            CREATE TRIGGER prompts_au AFTER UPDATE ON prompts BEGIN
+                INSERT INTO prompts_fts(prompts_fts, rowid, id, content, application, tags)
+                SELECT 'delete', old.rowid, old.id, old.content, old.application, old.tags
+                WHERE old.is_encrypted = 0;
+                INSERT INTO prompts_fts(rowid, id, content, application, tags)
+                SELECT new.rowid, new.id, new.content, new.application, new.tags
+                WHERE new.is_encrypted = 0;
+            END
+            "#,
+            // Drop any ciphertext that was indexed before this migration
+            "INSERT INTO prompts_fts(prompts_fts) VALUES('delete-all')",
+            r#"
+            INSERT INTO prompts_fts(rowid, id, content, application, tags)
+            SELECT rowid, id, content, application, tags FROM prompts WHERE is_encrypted = 0
+            "#,
+            r#"
+            CREATE TABLE IF NOT EXISTS prompt_search_docs (
+                prompt_id TEXT PRIMARY KEY REFERENCES prompts(id) ON DELETE CASCADE,
+                token_count INTEGER NOT NULL
+            )
+            "#,
+            r#"
+            CREATE TABLE IF NOT EXISTS prompt_search_tokens (
+                token TEXT NOT NULL,
+                prompt_id TEXT NOT NULL REFERENCES prompts(id) ON DELETE CASCADE,
+                term_frequency INTEGER NOT NULL,
+                PRIMARY KEY (token, prompt_id)
+            )
+            "#,
+            "CREATE INDEX IF NOT EXISTS idx_prompt_search_tokens_prompt ON prompt_search_tokens(prompt_id)",
+        ],
+        down: &[
+            "DROP TABLE IF EXISTS prompt_search_tokens",
+            "DROP TABLE IF EXISTS prompt_search_docs",
+            "DROP TRIGGER IF EXISTS prompts_ai",
+            "DROP TRIGGER IF EXISTS prompts_ad",
+            "DROP TRIGGER IF EXISTS prompts_au",
+            r#"
+            CREATE TRIGGER prompts_ai AFTER INSERT ON prompts BEGIN
+                INSERT INTO prompts_fts(rowid, id, content, application, tags)
+                VALUES (new.rowid, new.id, new.content, new.application, new.tags);
+            END
+            "#,
+            r#"
+            CREATE TRIGGER prompts_ad AFTER DELETE ON prompts BEGIN
+                INSERT INTO prompts_fts(prompts_fts, rowid, id, content, application, tags)
+                VALUES('delete', old.rowid, old.id, old.content, old.application, old.tags);
+            END
+            "#,
+            r#"
+            CREATE TRIGGER prompts_au AFTER UPDATE ON prompts BEGIN
+                INSERT INTO prompts_fts(prompts_fts, rowid, id, content, application, tags)
+                VALUES('delete', old.rowid, old.id, old.content, old.application, old.tags);
+                INSERT INTO prompts_fts(rowid, id, content, application, tags)
+                VALUES (new.rowid, new.id, new.content, new.application, new.tags);
+            END
+            "#,
+            "INSERT INTO prompts_fts(prompts_fts) VALUES('rebuild')",
+        ],
+    },
+    Migration {
+        version: 3,
+        description: "record which data key encrypted each prompt",
+        up: &[
+            "ALTER TABLE prompts ADD COLUMN key_id TEXT",
+            // Everything encrypted so far used the single pre-rotation key
+            "UPDATE prompts SET key_id = 'legacy' WHERE is_encrypted = 1",
+            "CREATE INDEX IF NOT EXISTS idx_prompts_key_id ON prompts(key_id)",
+        ],
+        down: &[
+            "DROP INDEX IF EXISTS idx_prompts_key_id",
+            "ALTER TABLE prompts DROP COLUMN key_id",
+        ],
+    },
+    Migration {
+        version: 4,
+        description: "passphrase-wrapped key header",
+        up: &[
+            r#"
+            CREATE TABLE IF NOT EXISTS encryption_header (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                kdf TEXT NOT NULL,
+                kdf_memory_kib INTEGER NOT NULL,
+                kdf_iterations INTEGER NOT NULL,
+                kdf_parallelism INTEGER NOT NULL,
+                salt TEXT NOT NULL,
+                wrapped_keys TEXT NOT NULL,
+                created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        ],
+        down: &["DROP TABLE IF EXISTS encryption_header"],
+    },
+    Migration {
+        version: 5,
+        description: "content hash for deduplication and last used time",
+        up: &[
+            // Hashes are filled in by PromptDatabase, which folds existing duplicates together
+            "ALTER TABLE prompts ADD COLUMN content_hash TEXT",
+            "ALTER TABLE prompts ADD COLUMN last_used TEXT",
+            "UPDATE prompts SET last_used = timestamp",
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_prompts_content_hash ON prompts(content_hash)",
+        ],
+        down: &[
+            "DROP INDEX IF EXISTS idx_prompts_content_hash",
+            "ALTER TABLE prompts DROP COLUMN last_used",
+            "ALTER TABLE prompts DROP COLUMN content_hash",
+        ],
+    },
+    Migration {
+        version: 6,
+        description: "prompt revision history",
+        up: &[
+            r#"
+            CREATE TABLE IF NOT EXISTS prompt_revisions (
+                prompt_id TEXT NOT NULL REFERENCES prompts(id) ON DELETE CASCADE,
+                revision INTEGER NOT NULL,
+                content TEXT NOT NULL,
+                tags TEXT NOT NULL,
+                starred BOOLEAN NOT NULL,
+                is_encrypted BOOLEAN NOT NULL DEFAULT 0,
+                key_id TEXT,
+                created_at TEXT NOT NULL,
+                PRIMARY KEY (prompt_id, revision)
+            )
+            "#,
+            "CREATE INDEX IF NOT EXISTS idx_prompt_revisions_key_id ON prompt_revisions(key_id)",
+        ],
+        down: &["DROP TABLE IF EXISTS prompt_revisions"],
+    },
+    Migration {
+        version: 7,
+        description: "normalized tags",
+        up: &[
+            // `lookup` is the plain name, or a keyed hash of it when the name is encrypted
+            r#"
+            CREATE TABLE IF NOT EXISTS tags (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                lookup TEXT NOT NULL UNIQUE,
+                is_encrypted BOOLEAN NOT NULL DEFAULT 0,
+                key_id TEXT,
+                created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+            r#"
+            CREATE TABLE IF NOT EXISTS prompt_tags (
+                prompt_id TEXT NOT NULL REFERENCES prompts(id) ON DELETE CASCADE,
+                tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+                PRIMARY KEY (prompt_id, tag_id)
+            )
+            "#,
+            "CREATE INDEX IF NOT EXISTS idx_prompt_tags_tag ON prompt_tags(tag_id)",
+            "CREATE INDEX IF NOT EXISTS idx_tags_key_id ON tags(key_id)",
+            // Tags of encrypted prompts can only be read once keys are available, so
+            // PromptDatabase works through this queue instead of doing it here
+            r#"
+            CREATE TABLE IF NOT EXISTS pending_tag_sync (
+                prompt_id TEXT PRIMARY KEY REFERENCES prompts(id) ON DELETE CASCADE
+            )
+            "#,
+            "INSERT OR IGNORE INTO pending_tag_sync (prompt_id) SELECT id FROM prompts",
+        ],
+        down: &[
+            "DROP TABLE IF EXISTS pending_tag_sync",
+            "DROP TABLE IF EXISTS prompt_tags",
+            "DROP TABLE IF EXISTS tags",
+        ],
+    },
+    Migration {
+        version: 8,
+        description: "nested collections of prompts",
+        up: &[
+            r#"
+            CREATE TABLE IF NOT EXISTS collections (
+                id TEXT PRIMARY KEY,
+                parent_id TEXT REFERENCES collections(id) ON DELETE CASCADE,
+                name TEXT NOT NULL,
+                position INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            )
+            "#,
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_collections_sibling_name ON collections(COALESCE(parent_id, ''), name)",
+            "CREATE INDEX IF NOT EXISTS idx_collections_parent ON collections(parent_id, position)",
+            r#"
+            CREATE TABLE IF NOT EXISTS collection_prompts (
+                collection_id TEXT NOT NULL REFERENCES collections(id) ON DELETE CASCADE,
+                prompt_id TEXT NOT NULL REFERENCES prompts(id) ON DELETE CASCADE,
+                position INTEGER NOT NULL DEFAULT 0,
+                added_at TEXT NOT NULL,
+                PRIMARY KEY (collection_id, prompt_id)
+            )
+            "#,
+            "CREATE INDEX IF NOT EXISTS idx_collection_prompts_prompt ON collection_prompts(prompt_id)",
+        ],
+        down: &[
+            "DROP TABLE IF EXISTS collection_prompts",
+            "DROP TABLE IF EXISTS collections",
+        ],
+    },
+    Migration {
+        version: 9,
+        description: "prompt templates",
+        up: &[
+            r#"
+            CREATE TABLE IF NOT EXISTS prompt_templates (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                description TEXT,
+                body TEXT NOT NULL,
+                variables TEXT NOT NULL,
+                is_encrypted BOOLEAN NOT NULL DEFAULT 0,
+                key_id TEXT,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            )
+            "#,
+            "CREATE INDEX IF NOT EXISTS idx_prompt_templates_key_id ON prompt_templates(key_id)",
+            "ALTER TABLE prompts ADD COLUMN template_id TEXT REFERENCES prompt_templates(id) ON DELETE SET NULL",
+            "CREATE INDEX IF NOT EXISTS idx_prompts_template_id ON prompts(template_id)",
+        ],
+        down: &[
+            "DROP INDEX IF EXISTS idx_prompts_template_id",
+            "ALTER TABLE prompts DROP COLUMN template_id",
+            "DROP TABLE IF EXISTS prompt_templates",
+        ],
+    },
+    Migration {
+        version: 10,
+        description: "workflows and their runs",
+        up: &[
+            r#"
+            CREATE TABLE IF NOT EXISTS workflows (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                description TEXT,
+                steps TEXT NOT NULL,
+                is_encrypted BOOLEAN NOT NULL DEFAULT 0,
+                key_id TEXT,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            )
+            "#,
+            r#"
+            CREATE TABLE IF NOT EXISTS workflow_runs (
+                id TEXT PRIMARY KEY,
+                workflow_id TEXT NOT NULL REFERENCES workflows(id) ON DELETE CASCADE,
+                model TEXT NOT NULL,
+                status TEXT NOT NULL,
+                error TEXT,
+                started_at TEXT NOT NULL,
+                finished_at TEXT
+            )
+            "#,
+            "CREATE INDEX IF NOT EXISTS idx_workflow_runs_workflow ON workflow_runs(workflow_id, started_at)",
+            r#"
+            CREATE TABLE IF NOT EXISTS workflow_run_steps (
+                run_id TEXT NOT NULL REFERENCES workflow_runs(id) ON DELETE CASCADE,
+                step_index INTEGER NOT NULL,
+                prompt_id TEXT REFERENCES prompts(id) ON DELETE SET NULL,
+                model TEXT NOT NULL,
+                output TEXT NOT NULL,
+                duration_ms INTEGER NOT NULL,
+                is_encrypted BOOLEAN NOT NULL DEFAULT 0,
+                key_id TEXT,
+                created_at TEXT NOT NULL,
+                PRIMARY KEY (run_id, step_index)
+            )
+            "#,
+            "CREATE INDEX IF NOT EXISTS idx_workflow_run_steps_prompt ON workflow_run_steps(prompt_id)",
+            "CREATE INDEX IF NOT EXISTS idx_workflows_key_id ON workflows(key_id)",
+            "CREATE INDEX IF NOT EXISTS idx_workflow_run_steps_key_id ON workflow_run_steps(key_id)",
+        ],
+        down: &[
+            "DROP TABLE IF EXISTS workflow_run_steps",
+            "DROP TABLE IF EXISTS workflow_runs",
+            "DROP TABLE IF EXISTS workflows",
+        ],
+    },
+    Migration {
+        version: 11,
+        description: "model responses linked to prompts",
+        up: &[
+            r#"
+            CREATE TABLE IF NOT EXISTS responses (
+                id TEXT PRIMARY KEY,
+                prompt_id TEXT NOT NULL REFERENCES prompts(id) ON DELETE CASCADE,
+                model TEXT NOT NULL,
+                response TEXT NOT NULL,
+                duration_ms INTEGER,
+                prompt_tokens INTEGER,
+                completion_tokens INTEGER,
+                is_encrypted BOOLEAN NOT NULL DEFAULT 0,
+                key_id TEXT,
+                created_at TEXT NOT NULL
+            )
+            "#,
+            "CREATE INDEX IF NOT EXISTS idx_responses_prompt ON responses(prompt_id, created_at)",
+            "CREATE INDEX IF NOT EXISTS idx_responses_key_id ON responses(key_id)",
+        ],
+        down: &[
+            "DROP TABLE IF EXISTS responses",
+        ],
+    },
+    Migration {
+        version: 12,
+        description: "sessions grouping prompts into conversations",
+        up: &[
+            r#"
+            CREATE TABLE IF NOT EXISTS sessions (
+                id TEXT PRIMARY KEY,
+                application TEXT NOT NULL,
+                context TEXT,
+                is_encrypted BOOLEAN NOT NULL DEFAULT 0,
+                key_id TEXT,
+                started_at TEXT NOT NULL,
+                ended_at TEXT NOT NULL
+            )
+            "#,
+            "CREATE INDEX IF NOT EXISTS idx_sessions_application ON sessions(application, ended_at)",
+            "CREATE INDEX IF NOT EXISTS idx_sessions_key_id ON sessions(key_id)",
+            "ALTER TABLE prompts ADD COLUMN session_id TEXT REFERENCES sessions(id) ON DELETE SET NULL",
+            "CREATE INDEX IF NOT EXISTS idx_prompts_session ON prompts(session_id, timestamp)",
+        ],
+        down: &[
+            "DROP INDEX IF EXISTS idx_prompts_session",
+            "ALTER TABLE prompts DROP COLUMN session_id",
+            "DROP TABLE IF EXISTS sessions",
+        ],
+    },
+    Migration {
+        version: 13,
+        description: "index for keyset pagination",
+        up: &[
+            "CREATE INDEX IF NOT EXISTS idx_prompts_timestamp_id ON prompts(timestamp, id)",
+        ],
+        down: &[
+            "DROP INDEX IF EXISTS idx_prompts_timestamp_id",
+        ],
+    },
+    Migration {
+        version: 14,
+        description: "window title of captured prompts",
+        up: &[
+            "ALTER TABLE prompts ADD COLUMN window_title TEXT",
+        ],
+        down: &[
+            "ALTER TABLE prompts DROP COLUMN window_title",
+        ],
+    },
+];
+
+/// Highest schema version this build knows how to handle
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+async fn ensure_version_table(pool: &SqlitePool) -> Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns the schema version recorded in the database, or 0 if none was applied yet
+pub async fn current_version(pool: &SqlitePool) -> Result<i64> {
+    ensure_version_table(pool).await?;
+
+    let row = sqlx::query("SELECT COALESCE(MAX(version), 0) AS version FROM schema_version")
+        .fetch_one(pool)
+        .await?;
+    Ok(row.get("version"))
+}
+
+/// Applies every pending migration, each inside its own transaction.
+///
+/// Refuses to touch a database written by a newer build.
+pub async fn migrate(pool: &SqlitePool) -> Result<()> {
+    migrate_with(pool, MIGRATIONS).await
+}
+
+async fn migrate_with(pool: &SqlitePool, migrations: &[Migration]) -> Result<()> {
+    let current = current_version(pool).await?;
+    let latest = migrations.last().map(|m| m.version).unwrap_or(0);
+
+    if current > latest {
+        return Err(PromptHistError::Migration(format!(
+            "Database schema version {} is newer than this build supports ({}); please upgrade PromptHist",
+            current, latest
+        )));
+    }
+
+    for migration in migrations.iter().filter(|m| m.version > current) {
+        println!("[DB] Applying migration {}: {}", migration.version, migration.description);
+
+        let mut tx = pool.begin().await?;
+        for statement in migration.up {
+            sqlx::query(statement).execute(&mut *tx).await.map_err(|e| {
+                PromptHistError::Migration(format!("Migration {} failed: {}", migration.version, e))
+            })?;
+        }
+        sqlx::query("INSERT INTO schema_version (version, description) VALUES (?, ?)")
+            .bind(migration.version)
+            .bind(migration.description)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
+
+/// Reverts applied migrations down to (but not including) `target` version.
+/// Version 1 is the baseline schema, so reverting it, which drops every prompt, is refused.
+pub async fn rollback_to(pool: &SqlitePool, target: i64) -> Result<()> {
+    if !(1..=latest_version()).contains(&target) {
+        return Err(PromptHistError::Migration(format!(
+            "Cannot roll back to schema version {}; versions range from 1 to {}",
+            target,
+            latest_version()
+        )));
+    }
+    rollback_with(pool, MIGRATIONS, target).await
+}
+
+async fn rollback_with(pool: &SqlitePool, migrations: &[Migration], target: i64) -> Result<()> {
+    let current = current_version(pool).await?;
+
+    for migration in migrations.iter().rev().filter(|m| m.version > target && m.version <= current) {
+        println!("[DB] Reverting migration {}: {}", migration.version, migration.description);
+
+        let mut tx = pool.begin().await?;
+        for statement in migration.down {
+            sqlx::query(statement).execute(&mut *tx).await.map_err(|e| {
+                PromptHistError::Migration(format!("Rollback of {} failed: {}", migration.version, e))
+            })?;
+        }
+        sqlx::query("DELETE FROM schema_version WHERE version = ?")
+            .bind(migration.version)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn memory_pool() -> SqlitePool {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap()
+    }
+
+    async fn table_exists(pool: &SqlitePool, name: &str) -> bool {
+        sqlx::query("SELECT name FROM sqlite_master WHERE name = ?")
+            .bind(name)
+            .fetch_optional(pool)
+            .await
+            .unwrap()
+            .is_some()
+    }
+
+    #[tokio::test]
+    async fn test_migrate_applies_all_and_is_idempotent() {
+        let pool = memory_pool().await;
+
+        migrate(&pool).await.unwrap();
+        migrate(&pool).await.unwrap();
+
+        assert_eq!(current_version(&pool).await.unwrap(), latest_version());
+        assert!(table_exists(&pool, "prompts").await);
+        assert!(table_exists(&pool, "prompts_fts").await);
+    }
+
+    #[tokio::test]
+    async fn test_refuses_newer_database() {
+        let pool = memory_pool().await;
+        migrate(&pool).await.unwrap();
+
+        sqlx::query("INSERT INTO schema_version (version, description) VALUES (?, 'from the future')")
+            .bind(latest_version() + 1)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert!(matches!(migrate(&pool).await, Err(PromptHistError::Migration(_))));
+    }
+
+    #[tokio::test]
+    async fn test_failed_step_rolls_back() {
+        const BROKEN: &[Migration] = &[
+            Migration {
+                version: 1,
+                description: "ok",
+                up: &["CREATE TABLE a (id INTEGER)"],
+                down: &["DROP TABLE a"],
+            },
+            Migration {
+                version: 2,
+                description: "broken",
+                up: &["CREATE TABLE b (id INTEGER)", "NOT VALID SQL"],
+                down: &["DROP TABLE b"],
+            },
+        ];
+
+        let pool = memory_pool().await;
+        assert!(migrate_with(&pool, BROKEN).await.is_err());
+
+        assert_eq!(current_version(&pool).await.unwrap(), 1);
+        assert!(table_exists(&pool, "a").await);
+        assert!(!table_exists(&pool, "b").await);
+
+        rollback_with(&pool, BROKEN, 0).await.unwrap();
+        assert_eq!(current_version(&pool).await.unwrap(), 0);
+        assert!(!table_exists(&pool, "a").await);
+    }
+
+    #[tokio::test]
+    async fn test_rollback_to_reverts_real_migrations() {
+        let pool = memory_pool().await;
+        migrate(&pool).await.unwrap();
+
+        assert!(matches!(rollback_to(&pool, latest_version() + 1).await, Err(PromptHistError::Migration(_))));
+        assert!(matches!(rollback_to(&pool, -1).await, Err(PromptHistError::Migration(_))));
+        assert!(matches!(rollback_to(&pool, 0).await, Err(PromptHistError::Migration(_))));
+
+        rollback_to(&pool, 1).await.unwrap();
+        assert_eq!(current_version(&pool).await.unwrap(), 1);
+        assert!(table_exists(&pool, "prompts").await);
+        assert!(!table_exists(&pool, "prompt_revisions").await);
+
+        migrate(&pool).await.unwrap();
+        assert_eq!(current_version(&pool).await.unwrap(), latest_version());
+    }
+}
@@ -13,6 +13,14 @@ pub struct PromptEntry {
     pub tags: Vec<String>,
     pub usage_count: i32,
     pub is_encrypted: bool,
+    #[serde(default)]
+    pub last_used: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub template_id: Option<String>, // Template this prompt was rendered from
+    #[serde(default)]
+    pub session_id: Option<String>, // Assigned when the prompt is saved
+    #[serde(default)]
+    pub window_title: Option<String>, // Focused window when the prompt was captured
 }
 
 /// Filter criteria for querying prompts
@@ -21,11 +29,97 @@ pub struct PromptFilter {
     pub application: Option<String>,
     pub starred: Option<bool>,
     pub tags: Option<Vec<String>>,
+    #[serde(default)]
+    pub tag_match: TagMatch,
     pub search_text: Option<String>,
     pub start_date: Option<DateTime<Utc>>,
     pub end_date: Option<DateTime<Utc>>,
 }
 
+/// Order of prompt listings
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptSort {
+    /// Newest first
+    #[default]
+    Timestamp,
+    /// Most used first
+    UsageCount,
+    /// Most recently edited first
+    UpdatedAt,
+    /// Best `search_text` match first; falls back to `Timestamp` without search text
+    Relevance,
+}
+
+/// One page of prompts. Pass `next_cursor` back to get the following page.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PromptPage {
+    pub prompts: Vec<PromptEntry>,
+    pub next_cursor: Option<String>, // None on the last page
+    pub total_matching: i64,
+}
+
+/// Markers placed around matched words in search hits
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HighlightOptions {
+    pub open: String,
+    pub close: String,
+    pub ellipsis: String,
+    pub snippet_tokens: i64, // 1 to 64, like FTS5's snippet()
+}
+
+impl Default for HighlightOptions {
+    fn default() -> Self {
+        Self {
+            open: "[".to_string(),
+            close: "]".to_string(),
+            ellipsis: "…".to_string(),
+            snippet_tokens: 16,
+        }
+    }
+}
+
+/// Character range `[start, end)` of a matched word in a prompt's content
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct MatchRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A search result with the text to show for it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub prompt: PromptEntry,
+    pub score: Option<f64>, // bm25, lower is better; None for filter-only searches
+    pub snippet: String,
+    pub highlighted: String,
+    pub matches: Vec<MatchRange>,
+}
+
+/// One page of search hits. Pass `next_cursor` back to get the following page.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchHitPage {
+    pub hits: Vec<SearchHit>,
+    pub next_cursor: Option<String>,
+    pub total_matching: i64,
+}
+
+/// Whether a tag filter needs any or all of its tags
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TagMatch {
+    #[default]
+    Any,
+    All,
+}
+
+/// A tag and how many prompts carry it
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TagCount {
+    pub name: String,
+    pub count: i64,
+}
+
 /// Statistics about prompt usage
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PromptStats {
@@ -36,6 +130,229 @@ pub struct PromptStats {
     pub recent_activity: Vec<PromptEntry>,
 }
 
+/// A past state of a prompt, recorded when an edit replaced it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PromptRevision {
+    pub prompt_id: String,
+    pub revision: i64,
+    pub content: String,
+    pub tags: Vec<String>,
+    pub starred: bool,
+    pub created_at: DateTime<Utc>, // When the edit replacing this state was made
+}
+
+/// How a line changed between two versions of a prompt
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LineChange {
+    Unchanged,
+    Added,
+    Removed,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct DiffLine {
+    pub change: LineChange,
+    pub text: String,
+}
+
+/// Differences between two revisions of a prompt
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RevisionDiff {
+    pub prompt_id: String,
+    pub from_revision: i64,
+    pub to_revision: Option<i64>, // None compares against the current prompt
+    pub lines: Vec<DiffLine>,
+    pub tags_added: Vec<String>,
+    pub tags_removed: Vec<String>,
+    pub starred_before: bool,
+    pub starred_after: bool,
+}
+
+/// A folder of prompts, possibly inside another collection
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Collection {
+    pub id: String,
+    pub parent_id: Option<String>,
+    pub name: String,
+    pub position: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A collection with its sub-collections, as returned by the collection tree
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CollectionNode {
+    pub collection: Collection,
+    pub prompt_count: i64,
+    pub children: Vec<CollectionNode>,
+}
+
+/// Kind of value a template variable accepts
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum VariableType {
+    #[default]
+    Text,
+    Number,
+    Integer,
+    Boolean,
+}
+
+/// A `{{name}}` placeholder declared by a template
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TemplateVariable {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub variable_type: VariableType,
+    #[serde(default)]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub allowed_values: Option<Vec<String>>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// A reusable prompt with placeholders
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PromptTemplate {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub body: String,
+    pub variables: Vec<TemplateVariable>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Result of rendering a template
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RenderedTemplate {
+    pub content: String,
+    pub prompt_id: Option<String>, // Set when the rendered prompt was saved to history
+}
+
+/// Prompts captured from one application in one sitting, like a chat thread
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Session {
+    pub id: String,
+    pub application: String,
+    pub context: Option<String>, // Window title the prompts were captured from, when known
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub prompt_count: i64,
+}
+
+/// What a workflow step sends to the model
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StepSource {
+    /// A stored prompt; `{{name}}` placeholders are filled from earlier step outputs
+    Prompt { prompt_id: String },
+    /// A template; values may reference earlier step outputs as `{{name}}`
+    Template {
+        template_id: String,
+        #[serde(default)]
+        values: HashMap<String, String>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct WorkflowStep {
+    pub source: StepSource,
+    #[serde(default)]
+    pub model: Option<String>, // Overrides the model the run was started with
+    #[serde(default)]
+    pub output_variable: Option<String>, // Makes this step's output available to later steps
+}
+
+/// An ordered chain of prompts
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Workflow {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub steps: Vec<WorkflowStep>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// One execution of a workflow
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WorkflowRun {
+    pub id: String,
+    pub workflow_id: String,
+    pub model: String,
+    pub status: RunStatus,
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub steps: Vec<WorkflowRunStep>,
+}
+
+/// Result of one step of a run. The prompt sent is stored in history as `prompt_id`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WorkflowRunStep {
+    pub step_index: i64,
+    pub prompt_id: Option<String>,
+    pub model: String,
+    pub output: String,
+    pub duration_ms: i64,
+}
+
+/// Prompts similar enough to be merged into one
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PromptCluster {
+    pub primary_id: String, // Suggested survivor: the most used, then the oldest
+    pub similarity: f64,    // Lowest similarity of any member to the primary
+    pub prompts: Vec<PromptEntry>,
+}
+
+/// A fuzzy search result
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FuzzyMatch {
+    pub prompt: PromptEntry,
+    pub similarity: f64,    // Average of each query word's closest match; 1.0 when all are exact
+    pub score: Option<f64>, // bm25 when the prompt also matches exactly
+}
+
+/// Fuzzy search results, most similar first
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FuzzySearchResults {
+    pub matches: Vec<FuzzyMatch>,
+    pub truncated: bool, // Only the newest prompts were searched, so older matches may be missing
+}
+
+/// Outcome of rotating the encryption key
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyRotationReport {
+    pub active_key_id: String,
+    pub reencrypted: i64,
+    pub retired_keys: Vec<String>,
+}
+
+/// Where the encryption keys are kept
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyStorage {
+    /// OS keyring (Keychain, Secret Service, Credential Manager)
+    #[default]
+    Keyring,
+    /// Wrapped by a key derived from a user passphrase; unlocked at runtime
+    Passphrase,
+    /// Wrapped by a passphrase taken from `PROMPTHIST_KEY_PASSPHRASE` and kept in a key file
+    PassphraseFile,
+    /// Provided through `PROMPTHIST_ENCRYPTION_KEY`; rotation is not possible
+    Environment,
+}
+
 /// Configuration for system monitoring
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MonitoringConfig {
@@ -44,6 +361,14 @@ pub struct MonitoringConfig {
     pub capture_threshold: u32, // Minimum characters to capture
     pub auto_save: bool,
     pub encryption_enabled: bool,
+    #[serde(default)]
+    pub key_storage: KeyStorage,
+    #[serde(default)]
+    pub key_file: Option<String>, // Key file for KeyStorage::PassphraseFile; defaults to the config directory
+    #[serde(default)]
+    pub capture_primary_selection: bool, // Linux: also capture text that is selected but not copied
+    #[serde(default)]
+    pub keyboard_capture: bool, // Rebuild prompts typed into monitored applications; off unless opted in
 }
 
 impl Default for MonitoringConfig {
@@ -57,10 +382,16 @@ impl Default for MonitoringConfig {
                 "Grok".to_string(),
                 "Perplexity".to_string(),
                 "Ollama".to_string(),
+                "LM Studio".to_string(),
+                "VS Code".to_string(),
             ],
             capture_threshold: 10,
             auto_save: true,
             encryption_enabled: true,
+            key_storage: KeyStorage::default(),
+            key_file: None,
+            capture_primary_selection: false,
+            keyboard_capture: false,
         }
     }
 }
@@ -114,6 +445,10 @@ pub struct DetectedApplication {
     pub window_title: String,
     pub is_active: bool,
     pub last_activity: DateTime<Utc>,
+    #[serde(default)]
+    pub pid: Option<u32>, // Set for desktop apps found among running processes
+    #[serde(default)]
+    pub started_at: Option<DateTime<Utc>>,
 }
 
 /// Request payload for Ollama API
@@ -131,6 +466,32 @@ pub struct OllamaResponse {
     pub done: bool,
     pub model: String,
     pub created_at: String,
+    #[serde(default)]
+    pub total_duration: Option<i64>, // Nanoseconds
+    #[serde(default)]
+    pub prompt_eval_count: Option<i64>,
+    #[serde(default)]
+    pub eval_count: Option<i64>,
+}
+
+/// A model's answer to a stored prompt
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PromptResponse {
+    pub id: String,
+    pub prompt_id: String,
+    pub model: String,
+    pub response: String,
+    pub duration_ms: Option<i64>,
+    pub prompt_tokens: Option<i64>,
+    pub completion_tokens: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A prompt together with every response recorded for it, newest first
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PromptWithResponses {
+    pub prompt: PromptEntry,
+    pub responses: Vec<PromptResponse>,
 }
 
 /// System information structure
@@ -150,6 +511,9 @@ pub enum PromptHistError {
     #[error("Database error: {0}")]
     Database(#[from] sqlx::Error),
 
+    #[error("Migration error: {0}")]
+    Migration(String),
+
     #[error("Encryption error: {0}")]
     Encryption(String),
 
@@ -1,10 +1,11 @@
 use std::sync::{Arc, Mutex};
-use std::process::Command;
 use tokio::time::Duration;
 use tokio::sync::mpsc;
 use tokio::time;
 use chrono::Utc;
 use uuid::Uuid;
+use crate::capture::{self, ActiveWindow, CaptureSources};
+use crate::keyboard::{KeyAction, KeyBuffer};
 use crate::models::{MonitoringConfig, DetectedApplication, PromptHistError};
 use crate::prompt_storage::PromptDatabase;
 use std::collections::HashMap;
@@ -16,10 +17,22 @@ pub struct SystemMonitor {
     detected_apps: Arc<Mutex<Vec<DetectedApplication>>>,
     sender: Option<mpsc::UnboundedSender<String>>,
     recent_prompts: Arc<Mutex<HashMap<String, chrono::DateTime<Utc>>>>, // content -> timestamp
+    sources: Arc<Mutex<CaptureSources>>,
+    typing: Arc<Mutex<Typing>>,
+}
+
+// Applications where Enter types a newline, so typed prompts are submitted with Cmd/Ctrl+Enter
+const EDITOR_APPLICATIONS: &[&str] = &["Cursor", "VS Code"];
+
+/// Text being typed into the focused window
+#[derive(Default)]
+struct Typing {
+    window: Option<ActiveWindow>,
+    buffer: KeyBuffer,
 }
 
 impl SystemMonitor {
-    pub fn new(config: MonitoringConfig, db: Arc<PromptDatabase>) -> Self {
+    pub fn new(config: MonitoringConfig, db: Arc<PromptDatabase>, sources: CaptureSources) -> Self {
         Self {
             config,
             db,
@@ -27,6 +40,8 @@ impl SystemMonitor {
             detected_apps: Arc::new(Mutex::new(Vec::new())),
             sender: None,
             recent_prompts: Arc::new(Mutex::new(HashMap::new())),
+            sources: Arc::new(Mutex::new(sources)),
+            typing: Arc::new(Mutex::new(Typing::default())),
         }
     }
 
@@ -51,6 +66,8 @@ impl SystemMonitor {
         let running = Arc::clone(&self.running);
         let detected_apps = Arc::clone(&self.detected_apps);
         let recent_prompts = Arc::clone(&self.recent_prompts);
+        let sources = Arc::clone(&self.sources);
+        let typing = Arc::clone(&self.typing);
 
         // Start monitoring tasks
         tokio::spawn(async move {
@@ -58,10 +75,14 @@ impl SystemMonitor {
             println!("[MONITOR]   - Web browser monitoring: every 2 seconds");
             println!("[MONITOR]   - Desktop app monitoring: every 3 seconds");
             println!("[MONITOR]   - Clipboard monitoring: every 1 second");
+            if config.keyboard_capture {
+                println!("[MONITOR]   - Keyboard monitoring: every 250 milliseconds");
+            }
 
             let mut web_interval = time::interval(Duration::from_secs(2));
             let mut desktop_interval = time::interval(Duration::from_secs(3));
             let mut clipboard_interval = time::interval(Duration::from_secs(1));
+            let mut keyboard_interval = time::interval(Duration::from_millis(250));
 
             loop {
                 if !*running.lock().unwrap() {
@@ -70,20 +91,25 @@ impl SystemMonitor {
 
                 tokio::select! {
                     _ = web_interval.tick() => {
-                        if let Err(e) = Self::monitor_web_browsers(&config, &detected_apps).await {
+                        if let Err(e) = Self::monitor_web_browsers(&config, &detected_apps, &sources).await {
                             eprintln!("Web monitoring error: {}", e);
                         }
                     }
                     _ = desktop_interval.tick() => {
-                        if let Err(e) = Self::monitor_desktop_apps(&config, &detected_apps).await {
+                        if let Err(e) = Self::monitor_desktop_apps(&config, &detected_apps, &sources).await {
                             eprintln!("Desktop monitoring error: {}", e);
                         }
                     }
                     _ = clipboard_interval.tick() => {
-                        if let Err(e) = Self::monitor_clipboard(&config, &db, &recent_prompts).await {
+                        if let Err(e) = Self::monitor_clipboard(&config, &db, &recent_prompts, &detected_apps, &sources).await {
                             eprintln!("Clipboard monitoring error: {}", e);
                         }
                     }
+                    _ = keyboard_interval.tick() => {
+                        if let Err(e) = Self::monitor_keyboard(&config, &db, &recent_prompts, &detected_apps, &sources, &typing).await {
+                            eprintln!("Keyboard monitoring error: {}", e);
+                        }
+                    }
                 }
             }
         });
@@ -118,42 +144,37 @@ impl SystemMonitor {
     async fn monitor_web_browsers(
         config: &MonitoringConfig,
         detected_apps: &Arc<Mutex<Vec<DetectedApplication>>>,
+        sources: &Arc<Mutex<CaptureSources>>,
     ) -> std::result::Result<(), PromptHistError> {
         if !config.enabled {
             return Ok(());
         }
 
-        #[cfg(target_os = "macos")]
-        {
-            let browsers = vec!["Safari", "Google Chrome", "Firefox", "Microsoft Edge"];
-
-            for browser in browsers {
-                if let Ok(tabs) = Self::get_browser_tabs_macos(browser).await {
-                    for (title, url) in tabs {
-                        if let Some(app_name) = Self::identify_llm_application(&url) {
-                            println!("[MONITOR] Detected LLM application: {} in {} - {}", app_name, browser, title);
-
-                            if config.monitored_applications.contains(&app_name) {
-                                let detected_app = DetectedApplication {
-                                    name: app_name.clone(),
-                                    process_name: browser.to_string(),
-                                    window_title: title,
-                                    is_active: true,
-                                    last_activity: Utc::now(),
-                                };
-
-                                let mut apps = detected_apps.lock().unwrap();
-                                if !apps.iter().any(|a| a.name == detected_app.name && a.window_title == detected_app.window_title) {
-                                    println!("[MONITOR] Adding new detected application: {}", app_name);
-                                    apps.push(detected_app);
-                                } else {
-                                    println!("[MONITOR] Application {} already tracked", app_name);
-                                }
-                            } else {
-                                println!("[MONITOR] Application {} not in monitored list", app_name);
-                            }
-                        }
+        let tabs = sources.lock().unwrap().windows.browser_tabs()?;
+        for tab in tabs {
+            if let Some(app_name) = Self::identify_llm_application(&tab.url) {
+                println!("[MONITOR] Detected LLM application: {} in {} - {}", app_name, tab.browser, tab.title);
+
+                if config.monitored_applications.contains(&app_name) {
+                    let detected_app = DetectedApplication {
+                        name: app_name.clone(),
+                        process_name: tab.browser,
+                        window_title: tab.title,
+                        is_active: true,
+                        last_activity: Utc::now(),
+                        pid: None,
+                        started_at: None,
+                    };
+
+                    let mut apps = detected_apps.lock().unwrap();
+                    if !apps.iter().any(|a| a.name == detected_app.name && a.window_title == detected_app.window_title) {
+                        println!("[MONITOR] Adding new detected application: {}", app_name);
+                        apps.push(detected_app);
+                    } else {
+                        println!("[MONITOR] Application {} already tracked", app_name);
                     }
+                } else {
+                    println!("[MONITOR] Application {} not in monitored list", app_name);
                 }
             }
         }
@@ -164,29 +185,47 @@ impl SystemMonitor {
     async fn monitor_desktop_apps(
         config: &MonitoringConfig,
         detected_apps: &Arc<Mutex<Vec<DetectedApplication>>>,
+        sources: &Arc<Mutex<CaptureSources>>,
     ) -> std::result::Result<(), PromptHistError> {
         if !config.enabled {
             return Ok(());
         }
 
-        #[cfg(target_os = "macos")]
-        {
-            if let Ok(apps) = Self::get_running_applications_macos().await {
-                for (name, _pid) in apps {
-                    if config.monitored_applications.contains(&name) {
-                        let detected_app = DetectedApplication {
-                            name: name.clone(),
-                            process_name: name.clone(),
-                            window_title: String::new(),
-                            is_active: true,
-                            last_activity: Utc::now(),
-                        };
-
-                        let mut detected = detected_apps.lock().unwrap();
-                        if !detected.iter().any(|a| a.name == name) {
-                            detected.push(detected_app);
-                        }
+        let apps = sources.lock().unwrap().processes.running_applications()?;
+        let now = Utc::now();
+        let mut detected = detected_apps.lock().unwrap();
+
+        // Desktop entries carry a pid; the ones no longer running stay listed as inactive
+        for entry in detected.iter_mut().filter(|a| a.pid.is_some()) {
+            entry.is_active = apps.iter().any(|app| app.name == entry.name);
+        }
+
+        for app in apps {
+            if !config.monitored_applications.contains(&app.name) {
+                continue;
+            }
+
+            match detected.iter_mut().find(|a| a.pid.is_some() && a.name == app.name) {
+                Some(entry) => {
+                    if entry.pid != Some(app.pid) {
+                        println!("[MONITOR] {} is running as pid {}", app.name, app.pid);
                     }
+                    entry.process_name = app.process_name;
+                    entry.pid = Some(app.pid);
+                    entry.started_at = app.started_at;
+                    entry.last_activity = now;
+                }
+                None => {
+                    println!("[MONITOR] Detected desktop application: {} (pid {})", app.name, app.pid);
+                    detected.push(DetectedApplication {
+                        name: app.name,
+                        process_name: app.process_name,
+                        window_title: String::new(),
+                        is_active: true,
+                        last_activity: now,
+                        pid: Some(app.pid),
+                        started_at: app.started_at,
+                    });
                 }
             }
         }
@@ -198,98 +237,32 @@ impl SystemMonitor {
         config: &MonitoringConfig,
         db: &Arc<PromptDatabase>,
         recent_prompts: &Arc<Mutex<HashMap<String, chrono::DateTime<Utc>>>>,
+        detected_apps: &Arc<Mutex<Vec<DetectedApplication>>>,
+        sources: &Arc<Mutex<CaptureSources>>,
     ) -> std::result::Result<(), PromptHistError> {
         if !config.enabled {
             return Ok(());
         }
 
-        #[cfg(target_os = "macos")]
-        {
-            if let Ok(content) = Self::get_clipboard_content_macos().await {
-                // Log clipboard content detection
-                if !content.is_empty() {
-                    println!("[MONITOR] Clipboard content detected: {} chars", content.len());
-                }
-
-                if Self::looks_like_prompt(&content) {
-                    println!("[MONITOR] Content identified as prompt: {}",
-                        if content.len() > 100 {
-                            format!("{}...", &content[..100])
-                        } else {
-                            content.clone()
-                        }
-                    );
-
-                    if content.len() >= config.capture_threshold as usize {
-                        println!("[MONITOR] Prompt meets length threshold ({} >= {})",
-                            content.len(), config.capture_threshold);
-
-                        // Check for duplicates in recent prompts
-                        let now = Utc::now();
-                        let is_duplicate = {
-                            let mut recent = recent_prompts.lock().unwrap();
-                            
-                            // Clean up old entries (older than 1 hour)
-                            let one_hour_ago = now - chrono::Duration::hours(1);
-                            recent.retain(|_, timestamp| *timestamp > one_hour_ago);
-                            
-                            // Check if this content was recently seen (within last 5 minutes)
-                            let five_minutes_ago = now - chrono::Duration::minutes(5);
-                            if let Some(last_seen) = recent.get(&content) {
-                                if *last_seen > five_minutes_ago {
-                                    true // This is a duplicate
-                                } else {
-                                    recent.insert(content.clone(), now);
-                                    false // Not a recent duplicate
-                                }
-                            } else {
-                                recent.insert(content.clone(), now);
-                                false // First time seeing this content
-                            }
-                        };
-                        
-                        if is_duplicate {
-                            println!("[MONITOR] ⚠️  Duplicate prompt detected, skipping save (content seen within last 5 minutes)");
-                            return Ok(());
-                        }
-
-                        if config.auto_save {
-                            let entry = crate::models::PromptEntry {
-                                id: Uuid::new_v4().to_string(),
-                                content: content.clone(),
-                                application: "clipboard".to_string(),
-                                timestamp: chrono::Utc::now(),
-                                starred: false,
-                                tags: vec![],
-                                usage_count: 0,
-                                is_encrypted: false,
-                            };
-
-                            println!("[MONITOR] Attempting to save prompt with ID: {}", entry.id);
-
-                            match db.save_prompt(&entry).await {
-                                Ok(_) => {
-                                    println!("[MONITOR] ✅ Successfully saved prompt: ID={}, length={}, app={}",
-                                        entry.id, content.len(), entry.application);
-                                }
-                                Err(e) => {
-                                    eprintln!("[MONITOR] ❌ Failed to save clipboard prompt: {}", e);
-                                }
-                            }
-                        } else {
-                            println!("[MONITOR] Auto-save disabled, prompt not saved");
-                        }
-                    } else {
-                        println!("[MONITOR] Prompt too short ({} < {}), skipping",
-                            content.len(), config.capture_threshold);
+        let changes = sources.lock().unwrap().clipboard.changes();
+        match changes {
+            Ok(changes) if changes.is_empty() => {}
+            Ok(changes) => {
+                // Whatever has focus right after the copy is where the text came from
+                let focused = match sources.lock().unwrap().windows.active_window() {
+                    Ok(focused) => focused,
+                    Err(e) => {
+                        println!("[MONITOR] Failed to read the focused window: {}", e);
+                        None
                     }
-                } else if !content.is_empty() {
-                    println!("[MONITOR] Content not identified as prompt ({}...)",
-                        if content.len() > 50 { &content[..50] } else { &content });
+                };
+                for (selection, content) in changes {
+                    println!("[MONITOR] {:?} selection changed", selection);
+                    Self::process_captured_content(config, db, recent_prompts, detected_apps, focused.as_ref(), content).await;
                 }
-            } else {
+            }
+            Err(e) => {
                 // Only log clipboard read errors occasionally to avoid spam
-                use std::sync::Mutex;
                 use std::time::Instant;
                 static LAST_CLIPBOARD_ERROR: Mutex<Option<Instant>> = Mutex::new(None);
 
@@ -302,7 +275,7 @@ impl SystemMonitor {
                 };
 
                 if should_log {
-                    println!("[MONITOR] Failed to read clipboard content");
+                    println!("[MONITOR] Failed to read clipboard content: {}", e);
                     *last_error = Some(now);
                 }
             }
@@ -311,113 +284,202 @@ impl SystemMonitor {
         Ok(())
     }
 
-    #[cfg(target_os = "macos")]
-    async fn get_browser_tabs_macos(browser: &str) -> std::result::Result<Vec<(String, String)>, PromptHistError> {
-        let script = match browser {
-            "Safari" => r#"
-                tell application "Safari"
-                    set tabList to {}
-                    repeat with w in windows
-                        repeat with t in tabs of w
-                            set end of tabList to (name of t) & "|" & (URL of t)
-                        end repeat
-                    end repeat
-                    return tabList
-                end tell
-            "#,
-            "Google Chrome" => r#"
-                tell application "Google Chrome"
-                    set tabList to {}
-                    repeat with w in windows
-                        repeat with t in tabs of w
-                            set end of tabList to (title of t) & "|" & (URL of t)
-                        end repeat
-                    end repeat
-                    return tabList
-                end tell
-            "#,
-            _ => return Ok(vec![]),
+    /// Rebuilds prompts typed into monitored applications and saves them once submitted.
+    /// Keystrokes in any other window are dropped, as are those polled together with a
+    /// focus change since they may belong to either window.
+    async fn monitor_keyboard(
+        config: &MonitoringConfig,
+        db: &Arc<PromptDatabase>,
+        recent_prompts: &Arc<Mutex<HashMap<String, chrono::DateTime<Utc>>>>,
+        detected_apps: &Arc<Mutex<Vec<DetectedApplication>>>,
+        sources: &Arc<Mutex<CaptureSources>>,
+        typing: &Arc<Mutex<Typing>>,
+    ) -> std::result::Result<(), PromptHistError> {
+        if !config.enabled || !config.keyboard_capture {
+            return Ok(());
+        }
+
+        // Focus is read on every poll, so a switch is noticed before the next keys arrive
+        let keystrokes = sources.lock().unwrap().keyboard.keystrokes()?;
+        let focused = match sources.lock().unwrap().windows.active_window() {
+            Ok(focused) => focused,
+            Err(e) => {
+                println!("[MONITOR] Failed to read the focused window: {}", e);
+                None
+            }
         };
+        let application = focused.as_ref().map(|window| Self::application_for_window(window, &detected_apps.lock().unwrap()));
+        let monitored = application.as_ref().is_some_and(|application| config.monitored_applications.contains(application));
 
-        let output = Command::new("osascript")
-            .arg("-e")
-            .arg(script)
-            .output()
-            .map_err(|e| PromptHistError::SystemError(format!("Failed to execute AppleScript: {}", e)))?;
-
-        if output.status.success() {
-            let result = String::from_utf8_lossy(&output.stdout);
-            let tabs: Vec<(String, String)> = result
-                .lines()
-                .filter_map(|line| {
-                    let parts: Vec<&str> = line.split('|').collect();
-                    if parts.len() == 2 {
-                        Some((parts[0].to_string(), parts[1].to_string()))
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-            Ok(tabs)
-        } else {
-            Ok(vec![])
+        let mut submitted = Vec::new();
+        {
+            let mut typing = typing.lock().unwrap();
+
+            // Typing into another application starts a new prompt; a changing title doesn't
+            let owner = |window: &Option<ActiveWindow>| window.as_ref().map(|window| (window.process_name.clone(), window.pid));
+            if owner(&typing.window) != owner(&focused) {
+                typing.buffer.clear();
+                typing.buffer.submit_with_command(application.as_deref().is_some_and(|application| EDITOR_APPLICATIONS.contains(&application)));
+                typing.window = focused.clone();
+                if !keystrokes.is_empty() {
+                    println!("[MONITOR] Focus changed while typing, dropping {} keystrokes", keystrokes.len());
+                }
+                return Ok(());
+            }
+
+            for keystroke in &keystrokes {
+                match typing.buffer.handle(keystroke) {
+                    KeyAction::Paste if monitored => match sources.lock().unwrap().clipboard.current() {
+                        Ok(pasted) => typing.buffer.insert(&pasted),
+                        Err(e) => println!("[MONITOR] Failed to read pasted text: {}", e),
+                    },
+                    KeyAction::Submit(text) if monitored => submitted.push(text),
+                    _ => {}
+                }
+            }
+
+            if !monitored {
+                typing.buffer.clear();
+            }
+        }
+
+        for content in submitted {
+            println!("[MONITOR] Typed prompt submitted: {} chars", content.len());
+            Self::process_captured_content(config, db, recent_prompts, detected_apps, focused.as_ref(), content).await;
         }
+
+        Ok(())
     }
 
-    #[cfg(target_os = "macos")]
-    async fn get_running_applications_macos() -> std::result::Result<Vec<(String, u32)>, PromptHistError> {
-        let script = r#"
-            tell application "System Events"
-                set appList to {}
-                repeat with p in application processes
-                    if background only of p is false then
-                        set end of appList to (name of p) & "|" & (unix id of p)
-                    end if
-                end repeat
-                return appList
-            end tell
-        "#;
-
-        let output = Command::new("osascript")
-            .arg("-e")
-            .arg(script)
-            .output()
-            .map_err(|e| PromptHistError::SystemError(format!("Failed to execute AppleScript: {}", e)))?;
-
-        if output.status.success() {
-            let result = String::from_utf8_lossy(&output.stdout);
-            let apps: Vec<(String, u32)> = result
-                .lines()
-                .filter_map(|line| {
-                    let parts: Vec<&str> = line.split('|').collect();
-                    if parts.len() == 2 {
-                        if let Ok(pid) = parts[1].parse::<u32>() {
-                            Some((parts[0].to_string(), pid))
+    /// Saves captured text that looks like a prompt unless it was seen in the last five minutes
+    async fn process_captured_content(
+        config: &MonitoringConfig,
+        db: &Arc<PromptDatabase>,
+        recent_prompts: &Arc<Mutex<HashMap<String, chrono::DateTime<Utc>>>>,
+        detected_apps: &Arc<Mutex<Vec<DetectedApplication>>>,
+        focused: Option<&ActiveWindow>,
+        content: String,
+    ) {
+        // Log captured content detection
+        if !content.is_empty() {
+            println!("[MONITOR] Captured content detected: {} chars", content.len());
+        }
+
+        if Self::looks_like_prompt(&content) {
+            // Only sizes are logged: the text may have been typed or copied from anywhere
+            println!("[MONITOR] Content identified as prompt: {} chars", content.chars().count());
+
+            if content.len() >= config.capture_threshold as usize {
+                println!("[MONITOR] Prompt meets length threshold ({} >= {})",
+                    content.len(), config.capture_threshold);
+
+                // Check for duplicates in recent prompts
+                let now = Utc::now();
+                let is_duplicate = {
+                    let mut recent = recent_prompts.lock().unwrap();
+                    
+                    // Clean up old entries (older than 1 hour)
+                    let one_hour_ago = now - chrono::Duration::hours(1);
+                    recent.retain(|_, timestamp| *timestamp > one_hour_ago);
+                    
+                    // Check if this content was recently seen (within last 5 minutes)
+                    let five_minutes_ago = now - chrono::Duration::minutes(5);
+                    if let Some(last_seen) = recent.get(&content) {
+                        if *last_seen > five_minutes_ago {
+                            true // This is a duplicate
                         } else {
-                            None
+                            recent.insert(content.clone(), now);
+                            false // Not a recent duplicate
                         }
                     } else {
-                        None
+                        recent.insert(content.clone(), now);
+                        false // First time seeing this content
                     }
-                })
-                .collect();
-            Ok(apps)
-        } else {
-            Ok(vec![])
+                };
+                
+                if is_duplicate {
+                    println!("[MONITOR] ⚠️  Duplicate prompt detected, skipping save (content seen within last 5 minutes)");
+                    return;
+                }
+
+                if config.auto_save {
+                    let (application, context) = {
+                        let detected = detected_apps.lock().unwrap();
+                        let application = focused
+                            .map(|window| Self::application_for_window(window, &detected))
+                            .unwrap_or_else(|| "clipboard".to_string());
+
+                        // The focused window, or else the most recently seen LLM window, tells which conversation this belongs to
+                        let context = focused
+                            .map(|window| window.title.clone())
+                            .filter(|title| !title.is_empty())
+                            .or_else(|| {
+                                detected.iter()
+                                    .filter(|app| !app.window_title.is_empty())
+                                    .max_by_key(|app| app.last_activity)
+                                    .map(|app| app.window_title.clone())
+                            });
+                        (application, context)
+                    };
+
+                    let entry = crate::models::PromptEntry {
+                        id: Uuid::new_v4().to_string(),
+                        content: content.clone(),
+                        application,
+                        timestamp: chrono::Utc::now(),
+                        starred: false,
+                        tags: vec![],
+                        usage_count: 0,
+                        is_encrypted: false,
+                        last_used: None,
+                        template_id: None,
+                        session_id: None,
+                        window_title: focused.map(|window| window.title.clone()).filter(|title| !title.is_empty()),
+                    };
+
+                    println!("[MONITOR] Attempting to save prompt with ID: {}", entry.id);
+
+                    match db.save_prompt_with_context(&entry, context.as_deref()).await {
+                        Ok(id) if id != entry.id => {
+                            println!("[MONITOR] Prompt already stored as ID={}, usage count incremented", id);
+                        }
+                        Ok(_) => {
+                            println!("[MONITOR] ✅ Successfully saved prompt: ID={}, length={}, app={}",
+                                entry.id, content.len(), entry.application);
+                        }
+                        Err(e) => {
+                            eprintln!("[MONITOR] ❌ Failed to save captured prompt: {}", e);
+                        }
+                    }
+                } else {
+                    println!("[MONITOR] Auto-save disabled, prompt not saved");
+                }
+            } else {
+                println!("[MONITOR] Prompt too short ({} < {}), skipping",
+                    content.len(), config.capture_threshold);
+            }
+        } else if !content.is_empty() {
+            println!("[MONITOR] Content not identified as prompt ({} chars)", content.chars().count());
         }
     }
 
-    #[cfg(target_os = "macos")]
-    async fn get_clipboard_content_macos() -> std::result::Result<String, PromptHistError> {
-        let output = Command::new("pbpaste")
-            .output()
-            .map_err(|e| PromptHistError::SystemError(format!("Failed to read clipboard: {}", e)))?;
-
-        if output.status.success() {
-            Ok(String::from_utf8_lossy(&output.stdout).to_string())
-        } else {
-            Ok(String::new())
-        }
+    /// Application a focused window belongs to: a detected LLM app when the window is one,
+    /// otherwise the window's own application
+    fn application_for_window(window: &ActiveWindow, detected_apps: &[DetectedApplication]) -> String {
+        let desktop_app = detected_apps.iter()
+            .find(|app| app.pid.is_some() && app.pid == window.pid)
+            .map(|app| app.name.clone());
+        let known_app = || capture::application_for_process(&window.process_name, None, &[]).map(str::to_string);
+        // A browser window is titled after its current tab
+        let web_app = || detected_apps.iter()
+            .find(|app| app.pid.is_none() && !app.window_title.is_empty() && window.title.contains(&app.window_title))
+            .map(|app| app.name.clone());
+
+        desktop_app
+            .or_else(known_app)
+            .or_else(web_app)
+            .or_else(|| Some(window.process_name.clone()).filter(|name| !name.is_empty()))
+            .unwrap_or_else(|| "clipboard".to_string())
     }
 
     fn identify_llm_application(url: &str) -> Option<String> {
@@ -480,6 +542,261 @@ impl SystemMonitor {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::capture::{ActiveWindow, BrowserTab, CaptureFrame, RunningApp, Timeline};
+    use crate::keyboard::{chord, typing};
+    use crate::models::PromptSort;
+
+    fn copy(text: &str) -> CaptureFrame {
+        CaptureFrame { copied: Some(text.to_string()), ..Default::default() }
+    }
+
+    /// Runs every check once per frame, in the order the monitoring loop first runs them
+    async fn replay(config: MonitoringConfig, frames: Vec<CaptureFrame>) -> SystemMonitor {
+        let db = Arc::new(PromptDatabase::new_in_memory(None).await.unwrap());
+        let timeline = Timeline::new(frames);
+        let monitor = SystemMonitor::new(config, db, timeline.sources());
+
+        while timeline.advance() {
+            SystemMonitor::monitor_web_browsers(&monitor.config, &monitor.detected_apps, &monitor.sources).await.unwrap();
+            SystemMonitor::monitor_desktop_apps(&monitor.config, &monitor.detected_apps, &monitor.sources).await.unwrap();
+            SystemMonitor::monitor_clipboard(&monitor.config, &monitor.db, &monitor.recent_prompts, &monitor.detected_apps, &monitor.sources)
+                .await
+                .unwrap();
+            SystemMonitor::monitor_keyboard(
+                &monitor.config,
+                &monitor.db,
+                &monitor.recent_prompts,
+                &monitor.detected_apps,
+                &monitor.sources,
+                &monitor.typing,
+            )
+            .await
+            .unwrap();
+        }
+        monitor
+    }
+
+    async fn saved_prompts(monitor: &SystemMonitor) -> Vec<crate::models::PromptEntry> {
+        monitor.db.get_prompts(None, PromptSort::Timestamp, None, None).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_clipboard_capture_skips_duplicates_and_short_text() {
+        let config = MonitoringConfig { capture_threshold: 40, ..MonitoringConfig::default() };
+        let prompt = "Explain the difference between a struct and an enum in Rust";
+        let monitor = replay(config, vec![
+            copy(prompt),
+            copy("hello there"),
+            copy("How do I write a Rust function?"),
+            copy(prompt),
+            CaptureFrame::default(),
+        ])
+        .await;
+
+        let saved = saved_prompts(&monitor).await;
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].content, prompt);
+        assert_eq!(saved[0].application, "clipboard");
+    }
+
+    #[tokio::test]
+    async fn test_clipboard_capture_respects_auto_save_and_enabled() {
+        let frames = vec![copy("Can you help me debug this function?")];
+
+        let monitor = replay(MonitoringConfig { auto_save: false, ..MonitoringConfig::default() }, frames.clone()).await;
+        assert!(saved_prompts(&monitor).await.is_empty());
+
+        let monitor = replay(MonitoringConfig { enabled: false, ..MonitoringConfig::default() }, frames).await;
+        assert!(saved_prompts(&monitor).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_detected_windows_give_captured_prompts_context() {
+        let window = CaptureFrame {
+            tabs: vec![
+                BrowserTab { browser: "Firefox".to_string(), title: "Rust help - Claude".to_string(), url: "https://claude.ai/chat/1".to_string() },
+                BrowserTab { browser: "Firefox".to_string(), title: "News".to_string(), url: "https://example.com/".to_string() },
+            ],
+            applications: vec![
+                RunningApp { name: "Cursor".to_string(), process_name: "cursor".to_string(), pid: 42, started_at: None },
+                RunningApp { name: "Finder".to_string(), process_name: "Finder".to_string(), pid: 1, started_at: None },
+            ],
+            ..Default::default()
+        };
+        let monitor = replay(MonitoringConfig::default(), vec![
+            window.clone(),
+            CaptureFrame { copied: Some("Why does the borrow checker reject this loop?".to_string()), ..window },
+            // Cursor quit
+            CaptureFrame::default(),
+        ])
+        .await;
+
+        let detected = monitor.get_detected_applications();
+        let mut names: Vec<&str> = detected.iter().map(|app| app.name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, vec!["Claude", "Cursor"]);
+        let cursor = detected.iter().find(|app| app.name == "Cursor").unwrap();
+        assert_eq!(cursor.pid, Some(42));
+        assert!(!cursor.is_active);
+
+        let saved = saved_prompts(&monitor).await;
+        assert_eq!(saved.len(), 1);
+        let session = monitor.db.get_session(saved[0].session_id.as_ref().unwrap()).await.unwrap().unwrap();
+        assert_eq!(session.context.as_deref(), Some("Rust help - Claude"));
+    }
+
+    #[tokio::test]
+    async fn test_default_config_detects_every_known_desktop_app() {
+        let running = |name: &str, process_name: &str, pid: u32| RunningApp {
+            name: name.to_string(),
+            process_name: process_name.to_string(),
+            pid,
+            started_at: None,
+        };
+        let monitor = replay(MonitoringConfig::default(), vec![CaptureFrame {
+            applications: vec![running("LM Studio", "lm-studio", 5), running("VS Code", "code", 6)],
+            ..Default::default()
+        }])
+        .await;
+
+        let detected = monitor.get_detected_applications();
+        let mut names: Vec<&str> = detected.iter().map(|app| app.name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, vec!["LM Studio", "VS Code"]);
+    }
+
+    #[tokio::test]
+    async fn test_captures_are_attributed_to_the_focused_window() {
+        let focus = |process_name: &str, title: &str, pid: u32| ActiveWindow {
+            process_name: process_name.to_string(),
+            title: title.to_string(),
+            pid: Some(pid),
+        };
+        let window = CaptureFrame {
+            tabs: vec![BrowserTab { browser: "Firefox".to_string(), title: "Rust help - Claude".to_string(), url: "https://claude.ai/chat/1".to_string() }],
+            applications: vec![RunningApp { name: "Cursor".to_string(), process_name: "cursor".to_string(), pid: 42, started_at: None }],
+            ..Default::default()
+        };
+        let capture = |text: &str, active: ActiveWindow| CaptureFrame {
+            copied: Some(text.to_string()),
+            active: Some(active),
+            ..window.clone()
+        };
+        let monitor = replay(MonitoringConfig::default(), vec![
+            capture("Why does the borrow checker reject this loop?", focus("firefox", "Rust help - Claude — Mozilla Firefox", 7)),
+            // Cursor's editor window belongs to a helper process named after Electron
+            capture("Explain what this function returns", focus("electron", "main.rs - prompthist", 42)),
+            capture("How do I list files sorted by size?", focus("XTerm", "bash", 9)),
+        ])
+        .await;
+
+        let saved = saved_prompts(&monitor).await;
+        let attributed = |content: &str| {
+            let prompt = saved.iter().find(|prompt| prompt.content.starts_with(content)).unwrap();
+            (prompt.application.clone(), prompt.window_title.clone().unwrap_or_default())
+        };
+        assert_eq!(attributed("Why"), ("Claude".to_string(), "Rust help - Claude — Mozilla Firefox".to_string()));
+        assert_eq!(attributed("Explain"), ("Cursor".to_string(), "main.rs - prompthist".to_string()));
+        assert_eq!(attributed("How"), ("XTerm".to_string(), "bash".to_string()));
+
+        let stats = monitor.db.get_prompt_stats().await.unwrap();
+        assert_eq!(stats.applications.get("Claude"), Some(&1));
+        assert!(!stats.applications.contains_key("clipboard"));
+    }
+
+    #[tokio::test]
+    async fn test_typed_prompts_are_captured_in_monitored_applications() {
+        let cursor = CaptureFrame {
+            applications: vec![RunningApp { name: "Cursor".to_string(), process_name: "cursor".to_string(), pid: 42, started_at: None }],
+            active: Some(ActiveWindow { process_name: "cursor".to_string(), title: "main.rs - prompthist".to_string(), pid: Some(42) }),
+            ..Default::default()
+        };
+        let terminal = CaptureFrame {
+            active: Some(ActiveWindow { process_name: "XTerm".to_string(), title: "bash".to_string(), pid: Some(9) }),
+            ..cursor.clone()
+        };
+        let submit = |text: &str| {
+            let mut keystrokes = typing(text);
+            keystrokes.extend(chord(rdev::Key::ControlLeft, rdev::Key::Return));
+            keystrokes
+        };
+        let mut pasting = typing("How do I ");
+        pasting.extend(chord(rdev::Key::ControlLeft, rdev::Key::KeyV));
+        pasting.extend(submit(" in Rust?"));
+        let frames = vec![
+            CaptureFrame { copied: Some("parse JSON".to_string()), ..cursor.clone() },
+            CaptureFrame { typed: pasting, ..cursor.clone() },
+            CaptureFrame { typed: typing("Why is the build failing again?\nhalf a thought"), ..terminal },
+            cursor.clone(),
+            CaptureFrame { typed: submit("Can you explain closures?"), ..cursor },
+        ];
+
+        let monitor = replay(MonitoringConfig { keyboard_capture: true, ..MonitoringConfig::default() }, frames.clone()).await;
+        let saved = saved_prompts(&monitor).await;
+        let mut typed: Vec<(&str, &str)> = saved.iter().map(|prompt| (prompt.content.as_str(), prompt.application.as_str())).collect();
+        typed.sort();
+        assert_eq!(typed, vec![("Can you explain closures?", "Cursor"), ("How do I parse JSON in Rust?", "Cursor")]);
+
+        // Keyboard capture is opt-in
+        let monitor = replay(MonitoringConfig::default(), frames).await;
+        assert!(saved_prompts(&monitor).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_keystrokes_polled_with_a_focus_change_are_dropped() {
+        let cursor = CaptureFrame {
+            applications: vec![RunningApp { name: "Cursor".to_string(), process_name: "cursor".to_string(), pid: 42, started_at: None }],
+            active: Some(ActiveWindow { process_name: "cursor".to_string(), title: "main.rs - prompthist".to_string(), pid: Some(42) }),
+            ..Default::default()
+        };
+        let terminal = CaptureFrame {
+            active: Some(ActiveWindow { process_name: "XTerm".to_string(), title: "bash".to_string(), pid: Some(9) }),
+            ..cursor.clone()
+        };
+        let submit = |text: &str| {
+            let mut keystrokes = typing(text);
+            keystrokes.extend(chord(rdev::Key::ControlLeft, rdev::Key::Return));
+            keystrokes
+        };
+        let frames = vec![
+            terminal,
+            // Typed into the terminal, but Cursor had focus by the time the batch was polled
+            CaptureFrame { typed: submit("How do I delete every docker volume?"), ..cursor.clone() },
+            CaptureFrame { typed: submit("Can you explain closures?"), ..cursor },
+        ];
+
+        let monitor = replay(MonitoringConfig { keyboard_capture: true, ..MonitoringConfig::default() }, frames).await;
+        let saved = saved_prompts(&monitor).await;
+        let typed: Vec<&str> = saved.iter().map(|prompt| prompt.content.as_str()).collect();
+        assert_eq!(typed, vec!["Can you explain closures?"]);
+    }
+
+    #[tokio::test]
+    async fn test_code_typed_into_editors_is_not_saved() {
+        let cursor = CaptureFrame {
+            applications: vec![RunningApp { name: "Cursor".to_string(), process_name: "cursor".to_string(), pid: 42, started_at: None }],
+            active: Some(ActiveWindow { process_name: "cursor".to_string(), title: "main.rs - prompthist".to_string(), pid: Some(42) }),
+            ..Default::default()
+        };
+        let frames = vec![
+            CaptureFrame { typed: typing("fn parse(input: &str) -> Result<Config> {\n"), ..cursor.clone() },
+            CaptureFrame { typed: typing("    // What does this return when the file is empty?\n"), ..cursor.clone() },
+            CaptureFrame { typed: typing("    toml::from_str(input)\n}\n"), ..cursor },
+        ];
+
+        let monitor = replay(MonitoringConfig { keyboard_capture: true, ..MonitoringConfig::default() }, frames).await;
+        assert!(saved_prompts(&monitor).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_multibyte_captures_are_handled() {
+        let prompt = format!("Pourquoi {} ?", "é".repeat(120));
+        let monitor = replay(MonitoringConfig::default(), vec![copy(&prompt), copy(&"€".repeat(60))]).await;
+
+        let saved = saved_prompts(&monitor).await;
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].content, prompt);
+    }
 
     #[test]
     fn test_identify_llm_application() {
@@ -0,0 +1,37 @@
+use crate::models::{OllamaRequest, OllamaResponse, Result};
+
+/// Default address of a local Ollama server
+pub const DEFAULT_OLLAMA_URL: &str = "http://localhost:11434";
+
+/// Minimal client for Ollama's generate endpoint
+pub struct OllamaClient {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl Default for OllamaClient {
+    fn default() -> Self {
+        Self::new(DEFAULT_OLLAMA_URL)
+    }
+}
+
+impl OllamaClient {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Sends a non-streaming generate request and returns the complete response
+    pub async fn generate(&self, request: &OllamaRequest) -> Result<OllamaResponse> {
+        let response = self.client
+            .post(format!("{}/api/generate", self.base_url))
+            .json(request)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(response.json().await?)
+    }
+}
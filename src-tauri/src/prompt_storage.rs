@@ -1,18 +1,207 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
 
+use base64::{engine::general_purpose, Engine as _};
 use chrono::{DateTime, Utc};
-use sqlx::{sqlite::SqliteConnectOptions, SqlitePool, Row};
+use sha2::{Digest, Sha256};
+use sqlx::{sqlite::{SqliteConnectOptions, SqliteRow}, SqliteConnection, SqlitePool, Row};
 use dirs::data_local_dir;
 
-use crate::models::{PromptEntry, PromptFilter, PromptStats, Result, PromptHistError};
+use crate::crypto::{CryptoManager, PassphraseHeader, LEGACY_KEY_ID};
+use crate::migrations;
+use crate::diff;
+use crate::fuzzy::FuzzyIndex;
+use crate::models::{
+    Collection, CollectionNode, FuzzyMatch, FuzzySearchResults, HighlightOptions, KeyRotationReport, MatchRange, OllamaResponse, PromptCluster, PromptEntry, PromptFilter, PromptPage, PromptResponse, PromptSort,
+    PromptRevision, PromptStats, PromptTemplate, PromptWithResponses, RenderedTemplate, Result, PromptHistError, RevisionDiff,
+    RunStatus, SearchHit, SearchHitPage, Session, TagCount, TagMatch, TemplateVariable, Workflow, WorkflowRun, WorkflowRunStep, WorkflowStep,
+};
+use crate::search_index::{self, CorpusStats};
+use crate::search_query::SearchQuery;
+use crate::similarity;
+use crate::templates;
+
+/// Prompts further apart than this start a new session
+const SESSION_GAP_MINUTES: i64 = 30;
+/// Rows re-encrypted per transaction during key rotation
+const ROTATION_BATCH_SIZE: i64 = 200;
+/// Newest prompts considered by fuzzy search, bounding the per-call index build
+const MAX_FUZZY_CANDIDATES: i64 = 5000;
+// Tables whose rows carry `is_encrypted` and `key_id`
+const ENCRYPTED_TABLES: &[&str] = &["prompts", "prompt_revisions", "tags", "prompt_templates", "workflows", "workflow_run_steps", "responses", "sessions"];
+
+/// Value bound to a `?` of a built query
+#[derive(Debug, Clone)]
+enum QueryParam {
+    Text(String),
+    Integer(i64),
+    Real(f64),
+}
+
+fn bind_params<'q>(
+    mut query: sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>>,
+    params: &'q [QueryParam],
+) -> sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>> {
+    for param in params {
+        query = match param {
+            QueryParam::Text(value) => query.bind(value.as_str()),
+            QueryParam::Integer(value) => query.bind(*value),
+            QueryParam::Real(value) => query.bind(*value),
+        };
+    }
+    query
+}
+
+/// Composes the SELECT behind prompt listings: filter conditions, an optional set of
+/// scored search matches, the sort and keyset or offset paging.
+struct PromptQuery {
+    sort: PromptSort,
+    // SELECT of (prompt_id, score, snippet, highlighted) rows; only matching prompts are listed
+    matches: Option<(String, Vec<QueryParam>)>,
+    conditions: Vec<(String, Vec<QueryParam>)>,
+    // Set when matches should carry FTS5 snippets and highlights
+    highlight: Option<HighlightOptions>,
+}
+
+impl PromptQuery {
+    fn new(sort: PromptSort) -> Self {
+        Self { sort, matches: None, conditions: Vec::new(), highlight: None }
+    }
+
+    fn condition(&mut self, sql: impl Into<String>, params: Vec<QueryParam>) {
+        self.conditions.push((sql.into(), params));
+    }
+
+    fn matches(&mut self, sql: String, params: Vec<QueryParam>) {
+        self.matches = Some((sql, params));
+    }
+
+    fn effective_sort(&self) -> PromptSort {
+        match self.sort {
+            PromptSort::Relevance if self.matches.is_none() => PromptSort::Timestamp,
+            sort => sort,
+        }
+    }
+
+    /// Sort expression and whether it is descending
+    fn sort_key(&self) -> (&'static str, bool) {
+        match self.effective_sort() {
+            PromptSort::Timestamp => ("prompts.timestamp", true),
+            PromptSort::UsageCount => ("prompts.usage_count", true),
+            PromptSort::UpdatedAt => ("prompts.updated_at", true),
+            // bm25 scores are negative; lower is better
+            PromptSort::Relevance => ("matches.score", false),
+        }
+    }
+
+    /// FROM and WHERE clauses shared by the count and the select
+    fn body(&self, select: &str) -> (String, Vec<QueryParam>) {
+        let mut sql = String::new();
+        let mut params = Vec::new();
+
+        if let Some((matches, match_params)) = &self.matches {
+            sql.push_str(&format!("WITH matches(prompt_id, score, snippet, highlighted) AS ({}) ", matches));
+            params.extend(match_params.iter().cloned());
+        }
+        sql.push_str(&format!("SELECT {} FROM prompts", select));
+        if self.matches.is_some() {
+            sql.push_str(" JOIN matches ON matches.prompt_id = prompts.id");
+        }
+        sql.push_str(" WHERE 1=1");
+        for (condition, condition_params) in &self.conditions {
+            sql.push_str(&format!(" AND ({})", condition));
+            params.extend(condition_params.iter().cloned());
+        }
+
+        (sql, params)
+    }
+
+    fn count(&self) -> (String, Vec<QueryParam>) {
+        self.body("COUNT(*)")
+    }
+
+    /// Rows after the `(sort value, id)` position `after`. A negative limit means no limit.
+    fn select(&self, after: Option<(QueryParam, String)>, limit: i64, offset: i64) -> (String, Vec<QueryParam>) {
+        let (key, descending) = self.sort_key();
+        let mut columns = format!("prompts.*, {} AS sort_key", key);
+        if self.matches.is_some() {
+            columns.push_str(", matches.score AS score, matches.snippet AS snippet, matches.highlighted AS highlighted");
+        }
+        let (mut sql, mut params) = self.body(&columns);
+        let (comparison, direction) = if descending { ("<", "DESC") } else { (">", "ASC") };
+
+        if let Some((value, id)) = after {
+            sql.push_str(&format!(
+                " AND ({key} {cmp} ? OR ({key} = ? AND prompts.id {cmp} ?))",
+                key = key,
+                cmp = comparison
+            ));
+            params.extend([value.clone(), value, QueryParam::Text(id)]);
+        }
+
+        sql.push_str(&format!(" ORDER BY {key} {dir}, prompts.id {dir} LIMIT ? OFFSET ?", key = key, dir = direction));
+        params.extend([QueryParam::Integer(limit), QueryParam::Integer(offset)]);
+        (sql, params)
+    }
+
+    /// Opaque cursor pointing just past `row`
+    fn encode_cursor(&self, row: &SqliteRow) -> String {
+        let key = match self.effective_sort() {
+            PromptSort::Timestamp | PromptSort::UpdatedAt => row.get::<String, _>("sort_key"),
+            PromptSort::UsageCount => row.get::<i64, _>("sort_key").to_string(),
+            PromptSort::Relevance => format!("{:016x}", row.get::<f64, _>("sort_key").to_bits()),
+        };
+        general_purpose::URL_SAFE_NO_PAD.encode(format!("{}\n{}", key, row.get::<String, _>("id")))
+    }
+
+    fn decode_cursor(&self, cursor: &str) -> Result<(QueryParam, String)> {
+        let invalid = || PromptHistError::InvalidInput("Invalid cursor".to_string());
+        let decoded = general_purpose::URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        let (key, id) = decoded.split_once('\n').ok_or_else(invalid)?;
+
+        let value = match self.effective_sort() {
+            PromptSort::Timestamp | PromptSort::UpdatedAt => QueryParam::Text(key.to_string()),
+            PromptSort::UsageCount => QueryParam::Integer(key.parse().map_err(|_| invalid())?),
+            PromptSort::Relevance => QueryParam::Real(f64::from_bits(u64::from_str_radix(key, 16).map_err(|_| invalid())?)),
+        };
+        Ok((value, id.to_string()))
+    }
+}
 
 pub struct PromptDatabase {
     pool: SqlitePool,
+    crypto: RwLock<Option<Arc<CryptoManager>>>,
+    // Keys come from a passphrase, so the database can be locked and unlocked at runtime
+    passphrase_protected: bool,
+    // Held shared while encrypting writes, exclusively while retiring keys
+    key_lock: tokio::sync::RwLock<()>,
 }
 
 impl PromptDatabase {
-    pub async fn new() -> Result<Self> {
+    /// Opens the on-disk database. When `crypto` is provided, prompt content and
+    /// tags are encrypted at rest.
+    pub async fn new(crypto: Option<CryptoManager>) -> Result<Self> {
+        let pool = Self::connect().await?;
+        Self::open(pool, crypto, false).await
+    }
+
+    /// Opens the on-disk database locked. Encrypted prompts are unavailable until
+    /// `unlock_with_passphrase` succeeds.
+    pub async fn new_passphrase_protected() -> Result<Self> {
+        let pool = Self::connect().await?;
+        Self::open(pool, None, true).await
+    }
+
+    /// Reverts the on-disk schema down to `target` version so an older build can open it.
+    /// The database is not opened, so nothing newer is migrated or backfilled first.
+    pub async fn rollback_schema(target: i64) -> Result<()> {
+        let pool = Self::connect().await?;
+        migrations::rollback_to(&pool, target).await
+    }
+
+    async fn connect() -> Result<SqlitePool> {
         let db_path = Self::get_database_path()?;
 
         // Ensure the directory exists
@@ -24,14 +213,38 @@ impl PromptDatabase {
             .filename(&db_path)
             .create_if_missing(true);
 
-        let pool = SqlitePool::connect_with(options).await?;
+        Ok(SqlitePool::connect_with(options).await?)
+    }
 
-        let db = Self { pool };
+    async fn open(pool: SqlitePool, crypto: Option<CryptoManager>, passphrase_protected: bool) -> Result<Self> {
+        let db = Self {
+            pool,
+            crypto: RwLock::new(crypto.map(Arc::new)),
+            passphrase_protected,
+            key_lock: tokio::sync::RwLock::new(()),
+        };
         db.initialize_schema().await?;
+        db.backfill_content_hashes().await?;
+        db.backfill_tags().await?;
+        db.backfill_sessions().await?;
+        db.prepare_encryption().await?;
 
         Ok(db)
     }
 
+    #[cfg(test)]
+    async fn memory_pool() -> Result<SqlitePool> {
+        Ok(sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await?)
+    }
+
+    #[cfg(test)]
+    pub(crate) async fn new_in_memory(crypto: Option<CryptoManager>) -> Result<Self> {
+        Self::open(Self::memory_pool().await?, crypto, false).await
+    }
+
     fn get_database_path() -> Result<PathBuf> {
         let mut path = data_local_dir()
             .ok_or_else(|| PromptHistError::InvalidInput("Could not find data directory".to_string()))?;
@@ -43,202 +256,667 @@ impl PromptDatabase {
     }
 
     async fn initialize_schema(&self) -> Result<()> {
-        // Create prompts table
-        sqlx::query(
+        migrations::migrate(&self.pool).await
+    }
+
+    /// Current key material. Errors while a passphrase protected database is locked.
+    fn crypto(&self) -> Result<Option<Arc<CryptoManager>>> {
+        let crypto = self.crypto.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        if crypto.is_none() && self.passphrase_protected {
+            return Err(PromptHistError::Encryption(
+                "Database is locked; unlock it with your passphrase".to_string(),
+            ));
+        }
+        Ok(crypto)
+    }
+
+    /// Whether prompts are encrypted at rest, even while a passphrase protected database is locked
+    pub fn encryption_enabled(&self) -> bool {
+        self.passphrase_protected || matches!(self.crypto(), Ok(Some(_)))
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.crypto().is_err()
+    }
+
+    /// Key id new rows are encrypted with, or `None` when stored in plaintext
+    fn active_key_id(&self) -> Result<Option<String>> {
+        Ok(self.crypto()?.map(|crypto| crypto.active_key_id()))
+    }
+
+    /// Encrypts a value with the given key, or returns it unchanged for plaintext rows
+    fn seal(&self, key_id: Option<&str>, value: &str) -> Result<String> {
+        let Some(key_id) = key_id else {
+            return Ok(value.to_string());
+        };
+        match self.crypto()? {
+            Some(crypto) => crypto.encrypt_with(key_id, value),
+            None => Ok(value.to_string()),
+        }
+    }
+
+    /// Decrypts a stored value if the row was written encrypted
+    fn unseal(&self, value: String, key_id: Option<&str>) -> Result<String> {
+        let Some(key_id) = key_id else {
+            return Ok(value);
+        };
+        match self.crypto()? {
+            Some(crypto) => crypto.decrypt_with(key_id, &value),
+            None => Err(PromptHistError::Encryption(
+                "Prompt is encrypted but encryption is not enabled".to_string(),
+            )),
+        }
+    }
+
+    fn row_bool(row: &SqliteRow, column: &str) -> bool {
+        // Try to get as integer first, then fall back to string
+        if let Ok(value) = row.try_get::<i32, _>(column) {
+            value != 0
+        } else if let Ok(value) = row.try_get::<String, _>(column) {
+            value == "1" || value.to_lowercase() == "true"
+        } else {
+            false
+        }
+    }
+
+    /// Key that encrypted the row, or `None` for plaintext rows
+    fn row_key_id(row: &SqliteRow) -> Option<String> {
+        if !Self::row_bool(row, "is_encrypted") {
+            return None;
+        }
+        let stored: Option<String> = row.try_get("key_id").unwrap_or(None);
+        Some(stored.unwrap_or_else(|| LEGACY_KEY_ID.to_string()))
+    }
+
+    fn parse_timestamp(value: &str) -> Result<DateTime<Utc>> {
+        Ok(DateTime::parse_from_rfc3339(value)
+            .map_err(|e| PromptHistError::InvalidInput(format!("Invalid timestamp: {}", e)))?
+            .with_timezone(&Utc))
+    }
+
+    fn row_to_prompt(&self, row: &SqliteRow) -> Result<PromptEntry> {
+        let key_id = Self::row_key_id(row);
+        let is_encrypted = key_id.is_some();
+
+        let tags_json = self.unseal(row.get("tags"), key_id.as_deref())?;
+        let tags: Vec<String> = serde_json::from_str(&tags_json).unwrap_or_default();
+        let timestamp_str: String = row.get("timestamp");
+        let timestamp = Self::parse_timestamp(&timestamp_str)?;
+        let last_used: Option<String> = row.try_get("last_used").unwrap_or(None);
+        let last_used = last_used.as_deref().map(Self::parse_timestamp).transpose()?;
+        let window_title: Option<String> = row.try_get("window_title").unwrap_or(None);
+
+        Ok(PromptEntry {
+            id: row.get("id"),
+            content: self.unseal(row.get("content"), key_id.as_deref())?,
+            application: row.get("application"),
+            timestamp,
+            starred: Self::row_bool(row, "starred"),
+            tags,
+            usage_count: row.get("usage_count"),
+            is_encrypted,
+            last_used,
+            template_id: row.try_get("template_id").unwrap_or(None),
+            session_id: row.try_get("session_id").unwrap_or(None),
+            window_title: window_title.map(|title| self.unseal(title, key_id.as_deref())).transpose()?,
+        })
+    }
+
+    fn rows_to_prompts(&self, rows: &[SqliteRow]) -> Result<Vec<PromptEntry>> {
+        rows.iter().map(|row| self.row_to_prompt(row)).collect()
+    }
+
+    /// Stores a new prompt and returns its id. `is_encrypted` is ignored and derived from
+    /// whether the database was opened with encryption.
+    ///
+    /// If the same content is already stored, that prompt's usage count is bumped instead
+    /// and its id is returned.
+    pub async fn save_prompt(&self, prompt: &PromptEntry) -> Result<String> {
+        self.save_prompt_with_context(prompt, None).await
+    }
+
+    /// Like `save_prompt`, with the window title the prompt came from to pick its session
+    pub async fn save_prompt_with_context(&self, prompt: &PromptEntry, context: Option<&str>) -> Result<String> {
+        let tags = Self::normalize_tags(&prompt.tags);
+        let tags_json = serde_json::to_string(&tags)?;
+        let _key_guard = self.key_lock.read().await;
+        let key_id = self.active_key_id()?;
+        let mut tx = self.pool.begin().await?;
+
+        if let Some(existing_id) = self.find_duplicate(&mut tx, &prompt.content, None).await? {
+            self.record_repeat(&mut tx, key_id.as_deref(), &existing_id, prompt, context).await?;
+            tx.commit().await?;
+            return Ok(existing_id);
+        }
+
+        let inserted = sqlx::query(
             r#"
-            CREATE TABLE IF NOT EXISTS prompts (
-                id TEXT PRIMARY KEY,
-                content TEXT NOT NULL,
-                application TEXT NOT NULL,
-                timestamp TEXT NOT NULL,
-                starred BOOLEAN NOT NULL DEFAULT FALSE,
-                tags TEXT NOT NULL DEFAULT '[]',
-                usage_count INTEGER NOT NULL DEFAULT 1,
-                is_encrypted BOOLEAN NOT NULL DEFAULT FALSE,
-                created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
-                updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            INSERT INTO prompts (
+                id, content, application, timestamp, starred, tags, usage_count, is_encrypted, key_id, content_hash, last_used, template_id,
+                window_title
             )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             "#,
         )
-        .execute(&self.pool)
-        .await?;
+        .bind(&prompt.id)
+        .bind(self.seal(key_id.as_deref(), &prompt.content)?)
+        .bind(&prompt.application)
+        .bind(prompt.timestamp.to_rfc3339())
+        .bind(if prompt.starred { 1 } else { 0 })
+        .bind(self.seal(key_id.as_deref(), &tags_json)?)
+        .bind(prompt.usage_count)
+        .bind(if key_id.is_some() { 1 } else { 0 })
+        .bind(&key_id)
+        .bind(self.content_hash(key_id.as_deref(), &prompt.content)?)
+        .bind(prompt.last_used.unwrap_or(prompt.timestamp).to_rfc3339())
+        .bind(&prompt.template_id)
+        .bind(prompt.window_title.as_deref().map(|title| self.seal(key_id.as_deref(), title)).transpose()?)
+        .execute(&mut *tx)
+        .await;
 
-        // Create indexes for better performance
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_prompts_application ON prompts(application)")
-            .execute(&self.pool)
-            .await?;
+        if let Err(e) = inserted {
+            drop(tx);
+            // Another capture of the same content may have committed in the meantime
+            let unique_violation = matches!(&e, sqlx::Error::Database(db_err) if db_err.is_unique_violation());
+            if unique_violation {
+                let mut tx = self.pool.begin().await?;
+                if let Some(existing_id) = self.find_duplicate(&mut tx, &prompt.content, None).await? {
+                    self.record_repeat(&mut tx, key_id.as_deref(), &existing_id, prompt, context).await?;
+                    tx.commit().await?;
+                    return Ok(existing_id);
+                }
+            }
+            return Err(e.into());
+        }
 
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_prompts_timestamp ON prompts(timestamp)")
-            .execute(&self.pool)
+        self.index_blind_tokens(&mut tx, key_id.as_deref(), &prompt.id, &prompt.content, &prompt.application, &tags)
             .await?;
+        self.sync_prompt_tags(&mut tx, key_id.as_deref(), &prompt.id, &tags).await?;
+        self.assign_session(&mut tx, key_id.as_deref(), &prompt.id, &prompt.application, context, prompt.timestamp)
+            .await?;
+        tx.commit().await?;
 
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_prompts_starred ON prompts(starred)")
-            .execute(&self.pool)
+        Ok(prompt.id.clone())
+    }
+
+    /// Counts another use of a stored prompt and moves it to the session it was just sent in
+    async fn record_repeat(
+        &self,
+        conn: &mut SqliteConnection,
+        key_id: Option<&str>,
+        existing_id: &str,
+        prompt: &PromptEntry,
+        context: Option<&str>,
+    ) -> Result<()> {
+        Self::increment_usage_count(conn, existing_id).await?;
+
+        let previous: Option<String> = sqlx::query_scalar("SELECT session_id FROM prompts WHERE id = ?")
+            .bind(existing_id)
+            .fetch_one(&mut *conn)
+            .await?;
+        let session_id = self.assign_session(conn, key_id, existing_id, &prompt.application, context, prompt.timestamp)
             .await?;
+        if let Some(previous) = previous.filter(|previous| *previous != session_id) {
+            Self::prune_sessions(conn).await?;
+            Self::refresh_session(conn, &previous).await?;
+        }
+        Ok(())
+    }
 
-        // Create FTS table for full-text search
-        sqlx::query(
-            r#"
-            CREATE VIRTUAL TABLE IF NOT EXISTS prompts_fts USING fts5(
-                id,
-                content,
-                application,
-                tags,
-                content='prompts',
-                content_rowid='rowid'
-            )
-            "#,
+    /// Hash used to spot duplicates. Encrypted rows use a keyed hash so the
+    /// database never holds a plain digest of their content.
+    fn content_hash(&self, key_id: Option<&str>, content: &str) -> Result<String> {
+        // Whitespace around a copied prompt doesn't make it a different prompt
+        let content = content.trim();
+        match key_id {
+            Some(key_id) => {
+                let crypto = self.crypto()?
+                    .ok_or_else(|| PromptHistError::Encryption("No encryption key available".to_string()))?;
+                crypto.content_hash(key_id, content)
+            }
+            None => Ok(general_purpose::STANDARD_NO_PAD.encode(Sha256::digest(content.as_bytes()))),
+        }
+    }
+
+    /// Finds a stored prompt with the same content, whichever key it was encrypted with
+    async fn find_duplicate(
+        &self,
+        conn: &mut SqliteConnection,
+        content: &str,
+        exclude_id: Option<&str>,
+    ) -> Result<Option<String>> {
+        let mut hashes = vec![self.content_hash(None, content)?];
+        if let Some(crypto) = self.crypto()? {
+            for key_id in crypto.key_ids() {
+                hashes.push(self.content_hash(Some(&key_id), content)?);
+            }
+        }
+
+        let placeholders = vec!["?"; hashes.len()].join(", ");
+        let sql = format!(
+            "SELECT id FROM prompts WHERE content_hash IN ({}) AND id != ? LIMIT 1",
+            placeholders
+        );
+        let mut query = sqlx::query_scalar(&sql);
+        for hash in &hashes {
+            query = query.bind(hash);
+        }
+
+        Ok(query.bind(exclude_id.unwrap_or("")).fetch_optional(&mut *conn).await?)
+    }
+
+    /// Hashes prompts stored before deduplication existed, folding repeats into the oldest copy.
+    ///
+    /// Encrypted prompts are only handled once their keys are available.
+    async fn backfill_content_hashes(&self) -> Result<()> {
+        let _key_guard = self.key_lock.read().await;
+        let keys_available = matches!(self.crypto(), Ok(Some(_)));
+        let rows = sqlx::query(
+            "SELECT * FROM prompts WHERE content_hash IS NULL AND (is_encrypted = 0 OR ?) ORDER BY timestamp ASC",
         )
-        .execute(&self.pool)
+        .bind(keys_available)
+        .fetch_all(&self.pool)
         .await?;
 
-        // Create triggers to keep FTS table in sync
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        println!("[DB] Hashing {} prompts for deduplication", rows.len());
+
+        let mut merged = 0;
+        let mut tx = self.pool.begin().await?;
+        for row in &rows {
+            let prompt = self.row_to_prompt(row)?;
+
+            match self.find_duplicate(&mut tx, &prompt.content, Some(&prompt.id)).await? {
+                Some(survivor_id) => {
+                    self.fold_duplicate(&mut tx, &survivor_id, &prompt).await?;
+                    merged += 1;
+                }
+                None => {
+                    sqlx::query("UPDATE prompts SET content_hash = ? WHERE id = ?")
+                        .bind(self.content_hash(Self::row_key_id(row).as_deref(), &prompt.content)?)
+                        .bind(&prompt.id)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+            }
+        }
+        tx.commit().await?;
+
+        if merged > 0 {
+            println!("[DB] Merged {} duplicate prompts", merged);
+        }
+
+        Ok(())
+    }
+
+    /// Adds a duplicate's usage, star, tags, template and history to the surviving prompt, then deletes it
+    async fn fold_duplicate(&self, conn: &mut SqliteConnection, survivor_id: &str, duplicate: &PromptEntry) -> Result<()> {
+        let row = sqlx::query("SELECT * FROM prompts WHERE id = ?")
+            .bind(survivor_id)
+            .fetch_one(&mut *conn)
+            .await?;
+        let survivor = self.row_to_prompt(&row)?;
+        let key_id = Self::row_key_id(&row);
+
+        let mut tags = survivor.tags.clone();
+        for tag in &duplicate.tags {
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+        let last_used = survivor.last_used.max(duplicate.last_used).unwrap_or(survivor.timestamp);
+
+        // Every stored copy was at least one use
         sqlx::query(
             r#"
-            CREATE TRIGGER IF NOT EXISTS prompts_ai AFTER INSERT ON prompts BEGIN
-                INSERT INTO prompts_fts(rowid, id, content, application, tags)
-                VALUES (new.rowid, new.id, new.content, new.application, new.tags);
-            END
+            UPDATE prompts
+            SET usage_count = usage_count + ?, starred = ?, tags = ?, last_used = ?,
+                template_id = COALESCE(template_id, ?), updated_at = CURRENT_TIMESTAMP
+            WHERE id = ?
             "#,
         )
-        .execute(&self.pool)
+        .bind(duplicate.usage_count.max(1))
+        .bind(if survivor.starred || duplicate.starred { 1 } else { 0 })
+        .bind(self.seal(key_id.as_deref(), &serde_json::to_string(&tags)?)?)
+        .bind(last_used.to_rfc3339())
+        .bind(&duplicate.template_id)
+        .bind(survivor_id)
+        .execute(&mut *conn)
         .await?;
 
+        // Keep what was recorded against the duplicate
+        for table in ["responses", "workflow_run_steps"] {
+            sqlx::query(&format!("UPDATE {} SET prompt_id = ? WHERE prompt_id = ?", table))
+                .bind(survivor_id)
+                .bind(&duplicate.id)
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        // The duplicate's revisions continue the survivor's history instead of cascading away
+        let last_revision: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(revision), 0) FROM prompt_revisions WHERE prompt_id = ?")
+            .bind(survivor_id)
+            .fetch_one(&mut *conn)
+            .await?;
+        sqlx::query("UPDATE prompt_revisions SET prompt_id = ?, revision = revision + ? WHERE prompt_id = ?")
+            .bind(survivor_id)
+            .bind(last_revision)
+            .bind(&duplicate.id)
+            .execute(&mut *conn)
+            .await?;
+
+        // The survivor joins the duplicate's collections, at the earlier position where both were listed
         sqlx::query(
             r#"
-            CREATE TRIGGER IF NOT EXISTS prompts_ad AFTER DELETE ON prompts BEGIN
-                INSERT INTO prompts_fts(prompts_fts, rowid, id, content, application, tags)
-                VALUES('delete', old.rowid, old.id, old.content, old.application, old.tags);
-            END
+            UPDATE collection_prompts
+            SET position = MIN(position, (
+                SELECT d.position FROM collection_prompts d
+                WHERE d.collection_id = collection_prompts.collection_id AND d.prompt_id = ?
+            ))
+            WHERE prompt_id = ? AND collection_id IN (SELECT collection_id FROM collection_prompts WHERE prompt_id = ?)
             "#,
         )
-        .execute(&self.pool)
+        .bind(&duplicate.id)
+        .bind(survivor_id)
+        .bind(&duplicate.id)
+        .execute(&mut *conn)
         .await?;
-
         sqlx::query(
             r#"
-            CREATE TRIGGER IF NOT EXISTS prompts_au AFTER UPDATE ON prompts BEGIN
-                INSERT INTO prompts_fts(prompts_fts, rowid, id, content, application, tags)
-                VALUES('delete', old.rowid, old.id, old.content, old.application, old.tags);
-                INSERT INTO prompts_fts(rowid, id, content, application, tags)
-                VALUES (new.rowid, new.id, new.content, new.application, new.tags);
-            END
+            INSERT OR IGNORE INTO collection_prompts (collection_id, prompt_id, position, added_at)
+            SELECT collection_id, ?, position, added_at FROM collection_prompts WHERE prompt_id = ?
             "#,
         )
-        .execute(&self.pool)
+        .bind(survivor_id)
+        .bind(&duplicate.id)
+        .execute(&mut *conn)
         .await?;
 
+        sqlx::query("DELETE FROM prompts WHERE id = ?")
+            .bind(&duplicate.id)
+            .execute(&mut *conn)
+            .await?;
+
+        if tags != survivor.tags {
+            self.index_blind_tokens(conn, key_id.as_deref(), survivor_id, &survivor.content, &survivor.application, &tags)
+                .await?;
+            self.sync_prompt_tags(conn, key_id.as_deref(), survivor_id, &tags).await?;
+        }
+        Self::prune_tags(conn).await?;
+        // Empty sessions go first, as a session without prompts has no time range
+        Self::prune_sessions(conn).await?;
+        for session_id in [survivor.session_id.as_deref(), duplicate.session_id.as_deref()].into_iter().flatten() {
+            Self::refresh_session(conn, session_id).await?;
+        }
+
         Ok(())
     }
 
-    pub async fn save_prompt(&self, prompt: &PromptEntry) -> Result<()> {
-        let tags_json = serde_json::to_string(&prompt.tags)?;
+    /// Rewrites the blind token index for one prompt. Plaintext prompts are covered by
+    /// `prompts_fts`, so only encrypted ones get entries here.
+    async fn index_blind_tokens(
+        &self,
+        conn: &mut SqliteConnection,
+        key_id: Option<&str>,
+        prompt_id: &str,
+        content: &str,
+        application: &str,
+        tags: &[String],
+    ) -> Result<()> {
+        sqlx::query("DELETE FROM prompt_search_tokens WHERE prompt_id = ?")
+            .bind(prompt_id)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("DELETE FROM prompt_search_docs WHERE prompt_id = ?")
+            .bind(prompt_id)
+            .execute(&mut *conn)
+            .await?;
+
+        let Some(key_id) = key_id else {
+            return Ok(());
+        };
+        let Some(crypto) = self.crypto()? else {
+            return Ok(());
+        };
 
-        sqlx::query(
+        let mut tokens = search_index::tokenize(content);
+        tokens.extend(search_index::tokenize(application));
+        for tag in tags {
+            tokens.extend(search_index::tokenize(tag));
+        }
+
+        sqlx::query("INSERT INTO prompt_search_docs (prompt_id, token_count) VALUES (?, ?)")
+            .bind(prompt_id)
+            .bind(tokens.len() as i64)
+            .execute(&mut *conn)
+            .await?;
+
+        for (token, frequency) in search_index::term_frequencies(&tokens) {
+            sqlx::query("INSERT INTO prompt_search_tokens (token, prompt_id, term_frequency) VALUES (?, ?, ?)")
+                .bind(crypto.blind_token(key_id, &token)?)
+                .bind(prompt_id)
+                .bind(frequency)
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Brings encrypted data up to date once keys are available
+    async fn prepare_encryption(&self) -> Result<()> {
+        if !matches!(self.crypto(), Ok(Some(_))) {
+            return Ok(());
+        }
+
+        self.backfill_search_index().await?;
+        self.backfill_content_hashes().await?;
+        self.backfill_tags().await?;
+
+        // Finish a key rotation that was interrupted last run
+        if let Err(e) = self.resume_key_rotation().await {
+            eprintln!("[DB] Failed to resume key rotation: {}", e);
+        }
+
+        Ok(())
+    }
+
+    /// Indexes encrypted prompts that were stored before the blind index existed
+    async fn backfill_search_index(&self) -> Result<()> {
+        let rows = sqlx::query(
             r#"
-            INSERT INTO prompts (id, content, application, timestamp, starred, tags, usage_count, is_encrypted)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            SELECT * FROM prompts
+            WHERE is_encrypted = 1
+              AND id NOT IN (SELECT prompt_id FROM prompt_search_docs)
             "#,
         )
-        .bind(&prompt.id)
-        .bind(&prompt.content)
-        .bind(&prompt.application)
-        .bind(prompt.timestamp.to_rfc3339())
-        .bind(if prompt.starred { 1 } else { 0 })
-        .bind(tags_json)
-        .bind(prompt.usage_count)
-        .bind(if prompt.is_encrypted { 1 } else { 0 })
-        .execute(&self.pool)
+        .fetch_all(&self.pool)
         .await?;
 
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        println!("[DB] Building search index for {} encrypted prompts", rows.len());
+
+        let mut tx = self.pool.begin().await?;
+        for row in &rows {
+            let prompt = self.row_to_prompt(row)?;
+            let key_id = Self::row_key_id(row);
+            self.index_blind_tokens(&mut tx, key_id.as_deref(), &prompt.id, &prompt.content, &prompt.application, &prompt.tags)
+                .await?;
+        }
+        tx.commit().await?;
+
         Ok(())
     }
 
     pub async fn get_prompts(
         &self,
         filter: Option<PromptFilter>,
+        sort: PromptSort,
         limit: Option<i32>,
         offset: Option<i32>,
     ) -> Result<Vec<PromptEntry>> {
-        let mut query = "SELECT * FROM prompts WHERE 1=1".to_string();
-        let mut params: Vec<String> = Vec::new();
+        let query = self.prompt_query(filter.as_ref(), sort, None).await?;
+        let (sql, params) = query.select(None, limit.unwrap_or(-1) as i64, offset.unwrap_or(0) as i64);
 
-        if let Some(f) = filter {
-            if let Some(app) = f.application {
-                query.push_str(" AND application = ?");
-                params.push(app);
-            }
-            if let Some(starred) = f.starred {
-                query.push_str(" AND starred = ?");
-                params.push(starred.to_string());
-            }
-            if let Some(start_date) = f.start_date {
-                query.push_str(" AND timestamp >= ?");
-                params.push(start_date.to_rfc3339());
-            }
-            if let Some(end_date) = f.end_date {
-                query.push_str(" AND timestamp <= ?");
-                params.push(end_date.to_rfc3339());
+        let rows = bind_params(sqlx::query(&sql), &params).fetch_all(&self.pool).await?;
+        self.rows_to_prompts(&rows)
+    }
+
+    /// Prompts in `sort` order, continuing after `cursor` from a previous page.
+    ///
+    /// Pages are keyed on the sort value and the id, so deep pages cost the same as
+    /// the first and rows added meanwhile don't shift later pages.
+    pub async fn get_prompts_page(
+        &self,
+        filter: Option<&PromptFilter>,
+        sort: PromptSort,
+        limit: i32,
+        cursor: Option<&str>,
+    ) -> Result<PromptPage> {
+        let query = self.prompt_query(filter, sort, None).await?;
+        let (rows, next_cursor, total_matching) = self.fetch_page(&query, limit, cursor).await?;
+
+        Ok(PromptPage { prompts: self.rows_to_prompts(&rows)?, next_cursor, total_matching })
+    }
+
+    /// Rows of one page of `query`, the cursor for the next page and the total row count
+    async fn fetch_page(&self, query: &PromptQuery, limit: i32, cursor: Option<&str>) -> Result<(Vec<SqliteRow>, Option<String>, i64)> {
+        let limit = limit.max(1);
+
+        let (sql, params) = query.count();
+        let total_matching: i64 = bind_params(sqlx::query(&sql), &params).fetch_one(&self.pool).await?.get(0);
+
+        let after = cursor.map(|cursor| query.decode_cursor(cursor)).transpose()?;
+        // One extra row tells whether another page follows
+        let (sql, params) = query.select(after, limit as i64 + 1, 0);
+        let mut rows = bind_params(sqlx::query(&sql), &params).fetch_all(&self.pool).await?;
+
+        let next_cursor = if rows.len() > limit as usize {
+            rows.truncate(limit as usize);
+            rows.last().map(|row| query.encode_cursor(row))
+        } else {
+            None
+        };
+
+        Ok((rows, next_cursor, total_matching))
+    }
+
+    /// Turns a filter into a query. `search_text` uses the search syntax of `SearchQuery`
+    /// and its terms provide the relevance scores.
+    async fn prompt_query(
+        &self,
+        filter: Option<&PromptFilter>,
+        sort: PromptSort,
+        highlight: Option<&HighlightOptions>,
+    ) -> Result<PromptQuery> {
+        let mut query = PromptQuery::new(sort);
+        query.highlight = highlight.cloned();
+        let Some(f) = filter else {
+            return Ok(query);
+        };
+
+        if let Some(app) = &f.application {
+            query.condition("prompts.application = ?", vec![QueryParam::Text(app.clone())]);
+        }
+        if let Some(starred) = f.starred {
+            query.condition("prompts.starred = ?", vec![QueryParam::Integer(if starred { 1 } else { 0 })]);
+        }
+        if let Some(start_date) = f.start_date {
+            query.condition("prompts.timestamp >= ?", vec![QueryParam::Text(start_date.to_rfc3339())]);
+        }
+        if let Some(end_date) = f.end_date {
+            query.condition("prompts.timestamp <= ?", vec![QueryParam::Text(end_date.to_rfc3339())]);
+        }
+        if let Some(tags) = f.tags.as_deref() {
+            if let Some(condition) = self.tag_filter_sql(tags, f.tag_match).await? {
+                query.condition(condition, vec![]);
             }
         }
 
-        query.push_str(" ORDER BY timestamp DESC");
-
-        if let Some(limit) = limit {
-            query.push_str(&format!(" LIMIT {}", limit));
+        if let Some(text) = f.search_text.as_deref() {
+            self.apply_search(&mut query, &SearchQuery::parse(text)?).await?;
         }
 
-        if let Some(offset) = offset {
-            query.push_str(&format!(" OFFSET {}", offset));
+        Ok(query)
+    }
+
+    /// Adds a parsed search to a query. Plaintext prompts are matched through FTS and
+    /// encrypted ones through the blind index, which has no word positions, so phrases
+    /// in encrypted prompts match when all their words appear.
+    async fn apply_search(&self, query: &mut PromptQuery, search: &SearchQuery) -> Result<()> {
+        if let Some(app) = &search.application {
+            query.condition("prompts.application = ? COLLATE NOCASE", vec![QueryParam::Text(app.clone())]);
+        }
+        if let Some(starred) = search.starred {
+            query.condition("prompts.starred = ?", vec![QueryParam::Integer(if starred { 1 } else { 0 })]);
+        }
+        if let Some(before) = search.before {
+            query.condition("prompts.timestamp < ?", vec![QueryParam::Text(before.to_rfc3339())]);
+        }
+        if let Some(after) = search.after {
+            query.condition("prompts.timestamp >= ?", vec![QueryParam::Text(after.to_rfc3339())]);
+        }
+        if let Some(condition) = self.tag_filter_sql(&search.tags, TagMatch::All).await? {
+            query.condition(condition, vec![]);
         }
 
-        let mut sql_query = sqlx::query(&query);
-        for param in params {
-            sql_query = sql_query.bind(param);
+        if let Some(expression) = search.fts_expression() {
+            // Column 1 of prompts_fts is the content
+            let (snippets, mut params) = match &query.highlight {
+                Some(options) => (
+                    "snippet(prompts_fts, 1, ?, ?, ?, ?), highlight(prompts_fts, 1, ?, ?)",
+                    vec![
+                        QueryParam::Text(options.open.clone()),
+                        QueryParam::Text(options.close.clone()),
+                        QueryParam::Text(options.ellipsis.clone()),
+                        QueryParam::Integer(options.snippet_tokens.clamp(1, 64)),
+                        QueryParam::Text(options.open.clone()),
+                        QueryParam::Text(options.close.clone()),
+                    ],
+                ),
+                None => ("NULL, NULL", vec![]),
+            };
+            let mut sql = format!(
+                "SELECT p.id, bm25(prompts_fts), {} FROM prompts_fts JOIN prompts p ON p.rowid = prompts_fts.rowid WHERE prompts_fts MATCH ?",
+                snippets
+            );
+            params.push(QueryParam::Text(expression));
+
+            let encrypted = self.search_encrypted(&search.terms.join(" ")).await?;
+            if !encrypted.is_empty() {
+                sql.push_str(" UNION ALL VALUES ");
+                // Encrypted content is invisible to FTS; its snippets are made after decrypting
+                sql.push_str(&vec!["(?, ?, NULL, NULL)"; encrypted.len()].join(", "));
+                for (score, id) in encrypted {
+                    params.extend([QueryParam::Text(id), QueryParam::Real(score)]);
+                }
+            }
+            query.matches(sql, params);
         }
 
-        let rows = sql_query.fetch_all(&self.pool).await?;
-        let mut prompts = Vec::new();
+        if let Some(expression) = search.exclusion_expression() {
+            query.condition(
+                "prompts.rowid NOT IN (SELECT rowid FROM prompts_fts WHERE prompts_fts MATCH ?)",
+                vec![QueryParam::Text(expression)],
+            );
 
-        for row in rows {
-            let tags_json: String = row.get("tags");
-            let tags: Vec<String> = serde_json::from_str(&tags_json).unwrap_or_default();
-            let timestamp_str: String = row.get("timestamp");
-            let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
-                .map_err(|e| PromptHistError::InvalidInput(format!("Invalid timestamp: {}", e)))?
-                .with_timezone(&Utc);
-
-            prompts.push(PromptEntry {
-                id: row.get("id"),
-                content: row.get("content"),
-                application: row.get("application"),
-                timestamp,
-                starred: {
-                    // Try to get as integer first, then fall back to string
-                    if let Ok(starred_int) = row.try_get::<i32, _>("starred") {
-                        starred_int != 0
-                    } else if let Ok(starred_str) = row.try_get::<String, _>("starred") {
-                        starred_str == "1" || starred_str.to_lowercase() == "true"
-                    } else {
-                        false
-                    }
-                },
-                tags,
-                usage_count: row.get("usage_count"),
-                is_encrypted: {
-                    // Try to get as integer first, then fall back to string
-                    if let Ok(encrypted_int) = row.try_get::<i32, _>("is_encrypted") {
-                        encrypted_int != 0
-                    } else if let Ok(encrypted_str) = row.try_get::<String, _>("is_encrypted") {
-                        encrypted_str == "1" || encrypted_str.to_lowercase() == "true"
-                    } else {
-                        false
-                    }
-                },
-            });
+            let mut excluded_ids = Vec::new();
+            for term in &search.excluded {
+                excluded_ids.extend(self.search_encrypted(term).await?.into_iter().map(|(_, id)| QueryParam::Text(id)));
+            }
+            if !excluded_ids.is_empty() {
+                let placeholders = vec!["?"; excluded_ids.len()].join(", ");
+                query.condition(format!("prompts.id NOT IN ({})", placeholders), excluded_ids);
+            }
         }
 
-        Ok(prompts)
+        Ok(())
     }
 
     pub async fn get_prompt_by_id(&self, id: &str) -> Result<Option<PromptEntry>> {
@@ -247,45 +925,7 @@ impl PromptDatabase {
             .fetch_optional(&self.pool)
             .await?;
 
-        if let Some(row) = row {
-            let tags_json: String = row.get("tags");
-            let tags: Vec<String> = serde_json::from_str(&tags_json).unwrap_or_default();
-            let timestamp_str: String = row.get("timestamp");
-            let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
-                .map_err(|e| PromptHistError::InvalidInput(format!("Invalid timestamp: {}", e)))?
-                .with_timezone(&Utc);
-
-            Ok(Some(PromptEntry {
-                id: row.get("id"),
-                content: row.get("content"),
-                application: row.get("application"),
-                timestamp,
-                starred: {
-                    // Try to get as integer first, then fall back to string
-                    if let Ok(starred_int) = row.try_get::<i32, _>("starred") {
-                        starred_int != 0
-                    } else if let Ok(starred_str) = row.try_get::<String, _>("starred") {
-                        starred_str == "1" || starred_str.to_lowercase() == "true"
-                    } else {
-                        false
-                    }
-                },
-                tags,
-                usage_count: row.get("usage_count"),
-                is_encrypted: {
-                    // Try to get as integer first, then fall back to string
-                    if let Ok(encrypted_int) = row.try_get::<i32, _>("is_encrypted") {
-                        encrypted_int != 0
-                    } else if let Ok(encrypted_str) = row.try_get::<String, _>("is_encrypted") {
-                        encrypted_str == "1" || encrypted_str.to_lowercase() == "true"
-                    } else {
-                        false
-                    }
-                },
-            }))
-        } else {
-            Ok(None)
-        }
+        row.map(|row| self.row_to_prompt(&row)).transpose()
     }
 
     pub async fn update_prompt(
@@ -295,108 +935,2089 @@ impl PromptDatabase {
         starred: Option<bool>,
         tags: Option<Vec<String>>,
     ) -> Result<()> {
-        let mut updates = Vec::new();
-        let mut params = Vec::new();
-
-        if let Some(content) = content {
-            updates.push("content = ?");
-            params.push(content);
-        }
-        if let Some(starred) = starred {
-            updates.push("starred = ?");
-            params.push(if starred { "1".to_string() } else { "0".to_string() });
-        }
-        if let Some(tags) = tags {
-            updates.push("tags = ?");
-            params.push(serde_json::to_string(&tags)?);
-        }
-
-        if updates.is_empty() {
+        if content.is_none() && starred.is_none() && tags.is_none() {
             return Ok(());
         }
 
-        updates.push("updated_at = CURRENT_TIMESTAMP");
-        params.push(id.to_string());
+        let _key_guard = self.key_lock.read().await;
+        let key_id = self.active_key_id()?;
+        let mut tx = self.pool.begin().await?;
 
-        let query = format!(
-            "UPDATE prompts SET {} WHERE id = ?",
-            updates.join(", ")
+        // Read-modify-write so content and tags always share the row's encryption state
+        let row = sqlx::query("SELECT * FROM prompts WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(existing) = row.map(|row| self.row_to_prompt(&row)).transpose()? else {
+            return Ok(());
+        };
+
+        let content = content.unwrap_or_else(|| existing.content.clone());
+        let starred = starred.unwrap_or(existing.starred);
+        let tags = Self::normalize_tags(&tags.unwrap_or_else(|| existing.tags.clone()));
+        let tags_json = serde_json::to_string(&tags)?;
+
+        if content == existing.content && starred == existing.starred && tags == existing.tags {
+            return Ok(());
+        }
+        if self.find_duplicate(&mut tx, &content, Some(id)).await?.is_some() {
+            return Err(PromptHistError::InvalidInput("Another prompt already has this content".to_string()));
+        }
+
+        self.record_revision(&mut tx, key_id.as_deref(), &existing).await?;
+
+        sqlx::query(
+            r#"
+            UPDATE prompts
+            SET content = ?, starred = ?, tags = ?, window_title = ?, is_encrypted = ?, key_id = ?, content_hash = ?, updated_at = CURRENT_TIMESTAMP
+            WHERE id = ?
+            "#,
+        )
+        .bind(self.seal(key_id.as_deref(), &content)?)
+        .bind(if starred { 1 } else { 0 })
+        .bind(self.seal(key_id.as_deref(), &tags_json)?)
+        .bind(existing.window_title.as_deref().map(|title| self.seal(key_id.as_deref(), title)).transpose()?)
+        .bind(if key_id.is_some() { 1 } else { 0 })
+        .bind(&key_id)
+        .bind(self.content_hash(key_id.as_deref(), &content)?)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+        self.index_blind_tokens(&mut tx, key_id.as_deref(), id, &content, &existing.application, &tags)
+            .await?;
+        self.sync_prompt_tags(&mut tx, key_id.as_deref(), id, &tags).await?;
+        Self::prune_tags(&mut tx).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Keeps the state an edit is about to replace
+    async fn record_revision(&self, conn: &mut SqliteConnection, key_id: Option<&str>, prompt: &PromptEntry) -> Result<()> {
+        let revision: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(revision), 0) + 1 FROM prompt_revisions WHERE prompt_id = ?")
+            .bind(&prompt.id)
+            .fetch_one(&mut *conn)
+            .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO prompt_revisions (prompt_id, revision, content, tags, starred, is_encrypted, key_id, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&prompt.id)
+        .bind(revision)
+        .bind(self.seal(key_id, &prompt.content)?)
+        .bind(self.seal(key_id, &serde_json::to_string(&prompt.tags)?)?)
+        .bind(if prompt.starred { 1 } else { 0 })
+        .bind(if key_id.is_some() { 1 } else { 0 })
+        .bind(key_id)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    fn row_to_revision(&self, row: &SqliteRow) -> Result<PromptRevision> {
+        let key_id = Self::row_key_id(row);
+        let tags_json = self.unseal(row.get("tags"), key_id.as_deref())?;
+        let created_at: String = row.get("created_at");
+
+        Ok(PromptRevision {
+            prompt_id: row.get("prompt_id"),
+            revision: row.get("revision"),
+            content: self.unseal(row.get("content"), key_id.as_deref())?,
+            tags: serde_json::from_str(&tags_json).unwrap_or_default(),
+            starred: Self::row_bool(row, "starred"),
+            created_at: Self::parse_timestamp(&created_at)?,
+        })
+    }
+
+    /// Past states of a prompt, newest first
+    pub async fn get_prompt_revisions(&self, prompt_id: &str) -> Result<Vec<PromptRevision>> {
+        let rows = sqlx::query("SELECT * FROM prompt_revisions WHERE prompt_id = ? ORDER BY revision DESC")
+            .bind(prompt_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter().map(|row| self.row_to_revision(row)).collect()
+    }
+
+    async fn get_prompt_revision(&self, prompt_id: &str, revision: i64) -> Result<PromptRevision> {
+        let row = sqlx::query("SELECT * FROM prompt_revisions WHERE prompt_id = ? AND revision = ?")
+            .bind(prompt_id)
+            .bind(revision)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| PromptHistError::InvalidInput(format!("Revision {} of prompt {} not found", revision, prompt_id)))?;
+
+        self.row_to_revision(&row)
+    }
+
+    /// Compares a revision with a later one, or with the current prompt when `to_revision` is `None`
+    pub async fn diff_prompt_revisions(&self, prompt_id: &str, from_revision: i64, to_revision: Option<i64>) -> Result<RevisionDiff> {
+        let from = self.get_prompt_revision(prompt_id, from_revision).await?;
+        let (content, tags, starred) = match to_revision {
+            Some(revision) => {
+                let to = self.get_prompt_revision(prompt_id, revision).await?;
+                (to.content, to.tags, to.starred)
+            }
+            None => {
+                let current = self.get_prompt_by_id(prompt_id).await?
+                    .ok_or_else(|| PromptHistError::InvalidInput(format!("Prompt not found: {}", prompt_id)))?;
+                (current.content, current.tags, current.starred)
+            }
+        };
+
+        Ok(RevisionDiff {
+            prompt_id: prompt_id.to_string(),
+            from_revision,
+            to_revision,
+            lines: diff::diff_lines(&from.content, &content),
+            tags_added: tags.iter().filter(|tag| !from.tags.contains(tag)).cloned().collect(),
+            tags_removed: from.tags.iter().filter(|tag| !tags.contains(tag)).cloned().collect(),
+            starred_before: from.starred,
+            starred_after: starred,
+        })
+    }
+
+    /// Brings back an earlier state. The state being replaced becomes a revision itself.
+    pub async fn restore_prompt_revision(&self, prompt_id: &str, revision: i64) -> Result<PromptEntry> {
+        let revision = self.get_prompt_revision(prompt_id, revision).await?;
+        self.update_prompt(prompt_id, Some(revision.content), Some(revision.starred), Some(revision.tags))
+            .await?;
+
+        self.get_prompt_by_id(prompt_id).await?
+            .ok_or_else(|| PromptHistError::InvalidInput(format!("Prompt not found: {}", prompt_id)))
+    }
+
+    pub async fn delete_prompt(&self, id: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM prompts WHERE id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        Self::prune_tags(&mut tx).await?;
+        Self::prune_sessions(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Trims tag names and drops empty and repeated ones, keeping their order
+    fn normalize_tags(tags: &[String]) -> Vec<String> {
+        let mut normalized: Vec<String> = Vec::with_capacity(tags.len());
+        for tag in tags.iter().map(|tag| tag.trim()) {
+            if !tag.is_empty() && !normalized.iter().any(|existing| existing == tag) {
+                normalized.push(tag.to_string());
+            }
+        }
+        normalized
+    }
+
+    /// Values of `tags.lookup` a tag name may be stored under: the name itself for
+    /// plaintext tags, a keyed hash per key for encrypted ones
+    fn tag_lookups(&self, name: &str, plain: bool, keyed: bool) -> Result<Vec<String>> {
+        let mut lookups = Vec::new();
+        if plain {
+            lookups.push(name.to_string());
+        }
+        if keyed {
+            if let Some(crypto) = self.crypto()? {
+                for key_id in crypto.key_ids() {
+                    lookups.push(crypto.tag_hash(&key_id, name)?);
+                }
+            }
+        }
+        Ok(lookups)
+    }
+
+    async fn tag_ids(&self, conn: &mut SqliteConnection, lookups: &[String]) -> Result<Vec<i64>> {
+        if lookups.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let placeholders = vec!["?"; lookups.len()].join(", ");
+        let sql = format!("SELECT id FROM tags WHERE lookup IN ({})", placeholders);
+        let mut query = sqlx::query_scalar(&sql);
+        for lookup in lookups {
+            query = query.bind(lookup);
+        }
+
+        Ok(query.fetch_all(&mut *conn).await?)
+    }
+
+    /// Finds or creates the tag row. Encrypted prompts only ever share encrypted tag rows,
+    /// so a tag name is never stored in the clear because of an encrypted prompt.
+    async fn get_or_create_tag(&self, conn: &mut SqliteConnection, key_id: Option<&str>, name: &str) -> Result<i64> {
+        let lookups = self.tag_lookups(name, key_id.is_none(), key_id.is_some())?;
+        if let Some(&id) = self.tag_ids(conn, &lookups).await?.first() {
+            return Ok(id);
+        }
+
+        let lookup = match key_id {
+            Some(key_id) => {
+                let crypto = self.crypto()?
+                    .ok_or_else(|| PromptHistError::Encryption("No encryption key available".to_string()))?;
+                crypto.tag_hash(key_id, name)?
+            }
+            None => name.to_string(),
+        };
+
+        let result = sqlx::query("INSERT INTO tags (name, lookup, is_encrypted, key_id) VALUES (?, ?, ?, ?)")
+            .bind(self.seal(key_id, name)?)
+            .bind(lookup)
+            .bind(if key_id.is_some() { 1 } else { 0 })
+            .bind(key_id)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    /// Points `prompt_tags` at the prompt's current tags
+    async fn sync_prompt_tags(&self, conn: &mut SqliteConnection, key_id: Option<&str>, prompt_id: &str, tags: &[String]) -> Result<()> {
+        sqlx::query("DELETE FROM prompt_tags WHERE prompt_id = ?")
+            .bind(prompt_id)
+            .execute(&mut *conn)
+            .await?;
+
+        for tag in Self::normalize_tags(tags) {
+            let tag_id = self.get_or_create_tag(conn, key_id, &tag).await?;
+            sqlx::query("INSERT OR IGNORE INTO prompt_tags (prompt_id, tag_id) VALUES (?, ?)")
+                .bind(prompt_id)
+                .bind(tag_id)
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        sqlx::query("DELETE FROM pending_tag_sync WHERE prompt_id = ?")
+            .bind(prompt_id)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Drops tags no prompt carries anymore
+    async fn prune_tags(conn: &mut SqliteConnection) -> Result<()> {
+        sqlx::query("DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM prompt_tags)")
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Fills `prompt_tags` for prompts stored before tags were normalized
+    async fn backfill_tags(&self) -> Result<()> {
+        let _key_guard = self.key_lock.read().await;
+        let keys_available = matches!(self.crypto(), Ok(Some(_)));
+        let rows = sqlx::query(
+            r#"
+            SELECT p.* FROM prompts p
+            JOIN pending_tag_sync s ON s.prompt_id = p.id
+            WHERE p.is_encrypted = 0 OR ?
+            "#,
+        )
+        .bind(keys_available)
+        .fetch_all(&self.pool)
+        .await?;
+
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        println!("[DB] Indexing tags for {} prompts", rows.len());
+
+        let mut tx = self.pool.begin().await?;
+        for row in &rows {
+            let prompt = self.row_to_prompt(row)?;
+            self.sync_prompt_tags(&mut tx, Self::row_key_id(row).as_deref(), &prompt.id, &prompt.tags)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// SQL conditions limiting `prompts` to rows carrying any or all of `tags`
+    async fn tag_filter_sql(&self, tags: &[String], tag_match: TagMatch) -> Result<Option<String>> {
+        let tags = Self::normalize_tags(tags);
+        if tags.is_empty() {
+            return Ok(None);
+        }
+
+        let mut conn = self.pool.acquire().await?;
+        let mut groups = Vec::with_capacity(tags.len());
+        for tag in &tags {
+            let lookups = self.tag_lookups(tag, true, true)?;
+            groups.push(self.tag_ids(&mut conn, &lookups).await?);
+        }
+
+        // Tag ids come from the database, so they are safe to inline
+        let condition = |ids: &[i64]| {
+            if ids.is_empty() {
+                return "0".to_string();
+            }
+            let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+            format!("prompts.id IN (SELECT prompt_id FROM prompt_tags WHERE tag_id IN ({}))", ids.join(", "))
+        };
+
+        Ok(Some(match tag_match {
+            TagMatch::Any => condition(&groups.concat()),
+            TagMatch::All => groups.iter().map(|ids| condition(ids)).collect::<Vec<_>>().join(" AND "),
+        }))
+    }
+
+    /// All tags in use with the number of prompts carrying each, most used first
+    pub async fn list_tags(&self) -> Result<Vec<TagCount>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT t.name, t.is_encrypted, t.key_id, COUNT(*) AS prompt_count
+            FROM tags t
+            JOIN prompt_tags pt ON pt.tag_id = t.id
+            GROUP BY t.id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        // The same name can exist once in the clear and once encrypted
+        let mut counts: HashMap<String, i64> = HashMap::new();
+        for row in &rows {
+            let name = self.unseal(row.get("name"), Self::row_key_id(row).as_deref())?;
+            *counts.entry(name).or_insert(0) += row.get::<i64, _>("prompt_count");
+        }
+
+        let mut tags: Vec<TagCount> = counts.into_iter()
+            .map(|(name, count)| TagCount { name, count })
+            .collect();
+        tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+        Ok(tags)
+    }
+
+    /// Renames a tag on every prompt. Renaming onto an existing tag merges the two.
+    pub async fn rename_tag(&self, old_name: &str, new_name: &str) -> Result<i64> {
+        if new_name.trim().is_empty() {
+            return Err(PromptHistError::InvalidInput("Tag name must not be empty".to_string()));
+        }
+        self.retag(&[old_name.to_string()], Some(new_name.trim())).await
+    }
+
+    /// Replaces each of `sources` with `target` on every prompt
+    pub async fn merge_tags(&self, sources: &[String], target: &str) -> Result<i64> {
+        if target.trim().is_empty() {
+            return Err(PromptHistError::InvalidInput("Tag name must not be empty".to_string()));
+        }
+        self.retag(sources, Some(target.trim())).await
+    }
+
+    /// Removes a tag from every prompt
+    pub async fn delete_tag(&self, name: &str) -> Result<i64> {
+        self.retag(&[name.to_string()], None).await
+    }
+
+    /// Swaps the `from` tags for `to`, or removes them, on every prompt carrying one.
+    /// Returns how many prompts changed.
+    async fn retag(&self, from: &[String], to: Option<&str>) -> Result<i64> {
+        let from = Self::normalize_tags(from);
+        let _key_guard = self.key_lock.read().await;
+        let mut tx = self.pool.begin().await?;
+
+        let mut lookups = Vec::new();
+        for name in &from {
+            lookups.extend(self.tag_lookups(name, true, true)?);
+        }
+        let tag_ids = self.tag_ids(&mut tx, &lookups).await?;
+        if tag_ids.is_empty() {
+            return Ok(0);
+        }
+
+        let tag_ids: Vec<String> = tag_ids.iter().map(|id| id.to_string()).collect();
+        let sql = format!(
+            "SELECT * FROM prompts WHERE id IN (SELECT prompt_id FROM prompt_tags WHERE tag_id IN ({}))",
+            tag_ids.join(", ")
+        );
+        let rows = sqlx::query(&sql).fetch_all(&mut *tx).await?;
+
+        for row in &rows {
+            let prompt = self.row_to_prompt(row)?;
+            let key_id = Self::row_key_id(row);
+            let tags: Vec<String> = Self::normalize_tags(&prompt.tags)
+                .into_iter()
+                .filter_map(|tag| if from.contains(&tag) { to.map(str::to_string) } else { Some(tag) })
+                .collect();
+            let tags = Self::normalize_tags(&tags);
+
+            sqlx::query("UPDATE prompts SET tags = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?")
+                .bind(self.seal(key_id.as_deref(), &serde_json::to_string(&tags)?)?)
+                .bind(&prompt.id)
+                .execute(&mut *tx)
+                .await?;
+
+            self.index_blind_tokens(&mut tx, key_id.as_deref(), &prompt.id, &prompt.content, &prompt.application, &tags)
+                .await?;
+            self.sync_prompt_tags(&mut tx, key_id.as_deref(), &prompt.id, &tags).await?;
+        }
+
+        Self::prune_tags(&mut tx).await?;
+        tx.commit().await?;
+
+        println!("[DB] Retagged {} prompts", rows.len());
+        Ok(rows.len() as i64)
+    }
+
+    /// Full-text search ranked by bm25. `query` may use filters, phrases and
+    /// exclusions, e.g. `app:claude tag:sql "join order" -draft`.
+    pub async fn search_prompts(&self, query: &str, limit: Option<i32>) -> Result<Vec<PromptEntry>> {
+        Ok(self.search_prompts_page(query, limit.unwrap_or(50), None).await?.prompts)
+    }
+
+    /// Best matches first, continuing after `cursor` from a previous page.
+    ///
+    /// Scores shift a little as prompts are added, so a page boundary may repeat or
+    /// skip a result whose score changed.
+    pub async fn search_prompts_page(&self, query: &str, limit: i32, cursor: Option<&str>) -> Result<PromptPage> {
+        let filter = PromptFilter {
+            application: None,
+            starred: None,
+            tags: None,
+            tag_match: TagMatch::Any,
+            search_text: Some(query.to_string()),
+            start_date: None,
+            end_date: None,
+        };
+        self.get_prompts_page(Some(&filter), PromptSort::Relevance, limit, cursor).await
+    }
+
+    /// Search results with snippets, highlighted content and match offsets, best first.
+    ///
+    /// Plaintext prompts use FTS5's snippet() and highlight(); encrypted prompts get the
+    /// same treatment in Rust after decrypting. Offsets mark every word of the search terms.
+    pub async fn search_hits_page(
+        &self,
+        query: &str,
+        options: &HighlightOptions,
+        limit: i32,
+        cursor: Option<&str>,
+    ) -> Result<SearchHitPage> {
+        let search = SearchQuery::parse(query)?;
+        let words: Vec<String> = search.terms.iter().flat_map(|term| search_index::tokenize(term)).collect();
+
+        let filter = PromptFilter {
+            application: None,
+            starred: None,
+            tags: None,
+            tag_match: TagMatch::Any,
+            search_text: Some(query.to_string()),
+            start_date: None,
+            end_date: None,
+        };
+        let prompt_query = self.prompt_query(Some(&filter), PromptSort::Relevance, Some(options)).await?;
+        let (rows, next_cursor, total_matching) = self.fetch_page(&prompt_query, limit, cursor).await?;
+
+        let mut hits = Vec::with_capacity(rows.len());
+        for row in &rows {
+            let prompt = self.row_to_prompt(row)?;
+            let (score, snippet, highlighted) = if prompt_query.matches.is_some() {
+                (
+                    row.get::<Option<f64>, _>("score"),
+                    row.get::<Option<String>, _>("snippet"),
+                    row.get::<Option<String>, _>("highlighted"),
+                )
+            } else {
+                (None, None, None)
+            };
+
+            let content: Vec<char> = prompt.content.chars().collect();
+            let spans: Vec<(usize, usize)> = search_index::token_spans(&prompt.content)
+                .into_iter()
+                .filter(|&(start, end)| words.contains(&content[start..end].iter().collect::<String>().to_lowercase()))
+                .collect();
+
+            let snippet = snippet.unwrap_or_else(|| {
+                let max_tokens = options.snippet_tokens.clamp(1, 64) as usize;
+                search_index::snippet(&prompt.content, &spans, &options.open, &options.close, &options.ellipsis, max_tokens)
+            });
+            let highlighted = highlighted
+                .unwrap_or_else(|| search_index::highlight(&prompt.content, &spans, &options.open, &options.close));
+
+            hits.push(SearchHit {
+                score,
+                snippet,
+                highlighted,
+                matches: spans.into_iter().map(|(start, end)| MatchRange { start, end }).collect(),
+                prompt,
+            });
+        }
+
+        Ok(SearchHitPage { hits, next_cursor, total_matching })
+    }
+
+    /// Typo-tolerant search: every query word must be at least `threshold` similar to a
+    /// word of the prompt, its application or tags. Filters and exclusions work as in
+    /// `search_prompts`. Most similar first; equally similar prompts go by bm25, then newest.
+    ///
+    /// The trigram index is built in memory from the matching prompts on each call, so
+    /// encrypted prompts are searched without storing anything derived from their words.
+    /// That costs a decrypt and an index build per search, so only the newest
+    /// `MAX_FUZZY_CANDIDATES` prompts that pass the filters are considered; `truncated`
+    /// tells when older ones were left out.
+    pub async fn fuzzy_search_prompts(&self, query: &str, threshold: f64, limit: Option<i32>) -> Result<FuzzySearchResults> {
+        self.fuzzy_search_within(query, threshold, limit, MAX_FUZZY_CANDIDATES).await
+    }
+
+    async fn fuzzy_search_within(&self, query: &str, threshold: f64, limit: Option<i32>, max_candidates: i64) -> Result<FuzzySearchResults> {
+        if !(threshold > 0.0 && threshold <= 1.0) {
+            return Err(PromptHistError::InvalidInput("Fuzzy threshold must be between 0 and 1".to_string()));
+        }
+
+        let search = SearchQuery::parse(query)?;
+        let words: Vec<String> = search.terms.iter().flat_map(|term| search_index::tokenize(term)).collect();
+
+        // Filters and exclusions pick the candidates; the terms are matched fuzzily below
+        let mut candidates = PromptQuery::new(PromptSort::Timestamp);
+        self.apply_search(&mut candidates, &SearchQuery { terms: Vec::new(), ..search.clone() }).await?;
+        // One extra row tells whether anything was left out
+        let (sql, params) = candidates.select(None, max_candidates + 1, 0);
+        let mut rows = bind_params(sqlx::query(&sql), &params).fetch_all(&self.pool).await?;
+        let truncated = rows.len() as i64 > max_candidates;
+        rows.truncate(max_candidates as usize);
+        let prompts = self.rows_to_prompts(&rows)?;
+
+        let mut exact_scores: HashMap<String, f64> = HashMap::new();
+        if !search.terms.is_empty() {
+            let mut exact = PromptQuery::new(PromptSort::Relevance);
+            self.apply_search(&mut exact, &search).await?;
+            // Only the scores are needed, so nothing is decrypted
+            let (sql, params) = exact.body("prompts.id AS id, matches.score AS score");
+            for row in bind_params(sqlx::query(&sql), &params).fetch_all(&self.pool).await? {
+                exact_scores.insert(row.get("id"), row.get("score"));
+            }
+        }
+
+        let documents: Vec<String> = prompts.iter()
+            .map(|prompt| format!("{} {} {}", prompt.content, prompt.application, prompt.tags.join(" ")))
+            .collect();
+        let index = FuzzyIndex::new(documents.iter().map(String::as_str));
+
+        let mut matches: Vec<FuzzyMatch> = index.search(&words, threshold)
+            .into_iter()
+            .map(|(document, similarity)| {
+                let prompt = prompts[document].clone();
+                FuzzyMatch { score: exact_scores.get(&prompt.id).copied(), similarity, prompt }
+            })
+            .collect();
+        // Candidates come newest first and the sort is stable
+        matches.sort_by(|a, b| {
+            b.similarity.total_cmp(&a.similarity)
+                .then_with(|| a.score.unwrap_or(f64::INFINITY).total_cmp(&b.score.unwrap_or(f64::INFINITY)))
+        });
+        matches.truncate(limit.unwrap_or(50).max(0) as usize);
+
+        Ok(FuzzySearchResults { matches, truncated })
+    }
+
+    /// Ids of encrypted prompts matching every query term, best bm25 score first
+    async fn search_encrypted(&self, query: &str) -> Result<Vec<(f64, String)>> {
+        let Some(crypto) = self.crypto()? else {
+            return Ok(Vec::new());
+        };
+
+        let mut terms = search_index::tokenize(query);
+        terms.sort();
+        terms.dedup();
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Rows keep the tokens of the key that encrypted them, so look up every key's variant
+        let mut term_for_token: HashMap<String, usize> = HashMap::new();
+        for key_id in crypto.key_ids() {
+            for (index, term) in terms.iter().enumerate() {
+                term_for_token.insert(crypto.blind_token(&key_id, term)?, index);
+            }
+        }
+        let blinded: Vec<&String> = term_for_token.keys().collect();
+
+        let corpus = sqlx::query("SELECT COUNT(*) AS docs, COALESCE(AVG(token_count), 0.0) AS avg_len FROM prompt_search_docs")
+            .fetch_one(&self.pool)
+            .await?;
+        let stats = CorpusStats {
+            document_count: corpus.get("docs"),
+            average_length: corpus.get("avg_len"),
+        };
+
+        let placeholders = vec!["?"; blinded.len()].join(", ");
+        let sql = format!(
+            r#"
+            SELECT t.prompt_id, t.token, t.term_frequency, d.token_count
+            FROM prompt_search_tokens t
+            JOIN prompt_search_docs d ON d.prompt_id = t.prompt_id
+            WHERE t.token IN ({})
+            "#,
+            placeholders
         );
+        let mut sql_query = sqlx::query(&sql);
+        for token in &blinded {
+            sql_query = sql_query.bind(*token);
+        }
+        let rows = sql_query.fetch_all(&self.pool).await?;
+
+        let mut document_frequency = vec![0i64; terms.len()];
+        for row in &rows {
+            document_frequency[term_for_token[&row.get::<String, _>("token")]] += 1;
+        }
+
+        // prompt_id -> (matched terms, score)
+        let mut matches: HashMap<String, (usize, f64)> = HashMap::new();
+        for row in rows {
+            let term = term_for_token[&row.get::<String, _>("token")];
+            let entry = matches.entry(row.get("prompt_id")).or_insert((0, 0.0));
+            entry.0 += 1;
+            entry.1 += search_index::bm25_term(
+                row.get("term_frequency"),
+                row.get("token_count"),
+                document_frequency[term],
+                stats,
+            );
+        }
+
+        // Implicit AND, same as an FTS5 query of bare terms
+        let mut ranked: Vec<(f64, String)> = matches
+            .into_iter()
+            .filter(|(_, (matched, _))| *matched == terms.len())
+            .map(|(id, (_, score))| (score, id))
+            .collect();
+        ranked.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+
+        Ok(ranked)
+    }
+
+    /// Groups prompts that differ only slightly, most similar groups first
+    pub async fn find_near_duplicates(&self, threshold: f64) -> Result<Vec<PromptCluster>> {
+        if !(threshold > 0.0 && threshold <= 1.0) {
+            return Err(PromptHistError::InvalidInput("Similarity threshold must be between 0 and 1".to_string()));
+        }
+
+        // Compared in memory so nothing derived from encrypted content is stored
+        let rows = sqlx::query("SELECT * FROM prompts ORDER BY timestamp ASC")
+            .fetch_all(&self.pool)
+            .await?;
+        let prompts = self.rows_to_prompts(&rows)?;
+        let shingles: Vec<_> = prompts.iter().map(|prompt| similarity::shingles(&prompt.content)).collect();
+
+        let mut clusters: Vec<PromptCluster> = similarity::clusters(&shingles, threshold)
+            .into_iter()
+            .map(|mut members| {
+                // Oldest first already, so a stable sort keeps the oldest of equally used prompts first
+                members.sort_by_key(|&index| std::cmp::Reverse(prompts[index].usage_count));
+                let primary = members[0];
+                let similarity = members[1..].iter()
+                    .map(|&index| similarity::jaccard(&shingles[primary], &shingles[index]))
+                    .fold(1.0, f64::min);
+
+                PromptCluster {
+                    primary_id: prompts[primary].id.clone(),
+                    similarity,
+                    prompts: members.iter().map(|&index| prompts[index].clone()).collect(),
+                }
+            })
+            .collect();
+
+        clusters.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+        Ok(clusters)
+    }
+
+    /// Folds `duplicate_ids` into `primary_id`, summing usage and unioning tags, and returns the result
+    pub async fn merge_prompts(&self, primary_id: &str, duplicate_ids: &[String]) -> Result<PromptEntry> {
+        if duplicate_ids.iter().any(|id| id == primary_id) {
+            return Err(PromptHistError::InvalidInput("Cannot merge a prompt into itself".to_string()));
+        }
+
+        let _key_guard = self.key_lock.read().await;
+        let mut tx = self.pool.begin().await?;
+
+        for id in std::iter::once(primary_id).chain(duplicate_ids.iter().map(String::as_str)) {
+            let row = sqlx::query("SELECT * FROM prompts WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&mut *tx)
+                .await?
+                .ok_or_else(|| PromptHistError::InvalidInput(format!("Prompt not found: {}", id)))?;
+
+            if id != primary_id {
+                let duplicate = self.row_to_prompt(&row)?;
+                self.fold_duplicate(&mut tx, primary_id, &duplicate).await?;
+            }
+        }
+        tx.commit().await?;
+
+        println!("[DB] Merged {} prompts into {}", duplicate_ids.len(), primary_id);
+
+        self.get_prompt_by_id(primary_id).await?
+            .ok_or_else(|| PromptHistError::InvalidInput(format!("Prompt not found: {}", primary_id)))
+    }
+
+    /// Switches to a freshly generated key and re-encrypts every encrypted prompt with it
+    pub async fn rotate_encryption_key(&self) -> Result<KeyRotationReport> {
+        let crypto = self.crypto()?
+            .ok_or_else(|| PromptHistError::Encryption("Encryption is not enabled".to_string()))?;
+
+        let key_id = {
+            let _key_guard = self.key_lock.write().await;
+            let previous_key_id = crypto.active_key_id();
+            let key_id = crypto.rotate_key()?;
+
+            // Passphrase-wrapped keys must be safely stored before anything uses the new key
+            if let Err(e) = self.store_passphrase_header(&crypto).await {
+                crypto.discard_key(&key_id, &previous_key_id);
+                return Err(e);
+            }
+            key_id
+        };
+        println!("[DB] Rotated encryption key, new key id {}", key_id);
+
+        self.resume_key_rotation().await
+    }
+
+    /// Re-encrypts prompts still protected by an older key, then retires keys nothing uses.
+    ///
+    /// Each batch commits on its own, so an interrupted run picks up where it left off.
+    pub async fn resume_key_rotation(&self) -> Result<KeyRotationReport> {
+        let crypto = self.crypto()?
+            .ok_or_else(|| PromptHistError::Encryption("Encryption is not enabled".to_string()))?;
+        let active_key_id = crypto.active_key_id();
+        let mut reencrypted = 0;
+
+        loop {
+            let mut tx = self.pool.begin().await?;
+            let rows = sqlx::query(
+                "SELECT * FROM prompts WHERE is_encrypted = 1 AND COALESCE(key_id, ?) != ? LIMIT ?",
+            )
+            .bind(LEGACY_KEY_ID)
+            .bind(&active_key_id)
+            .bind(ROTATION_BATCH_SIZE)
+            .fetch_all(&mut *tx)
+            .await?;
+
+            if rows.is_empty() {
+                break;
+            }
+
+            for prompt in self.rows_to_prompts(&rows)? {
+                let tags_json = serde_json::to_string(&prompt.tags)?;
+
+                sqlx::query("UPDATE prompts SET content = ?, tags = ?, window_title = ?, key_id = ?, content_hash = ? WHERE id = ?")
+                    .bind(self.seal(Some(&active_key_id), &prompt.content)?)
+                    .bind(self.seal(Some(&active_key_id), &tags_json)?)
+                    .bind(prompt.window_title.as_deref().map(|title| self.seal(Some(&active_key_id), title)).transpose()?)
+                    .bind(&active_key_id)
+                    .bind(self.content_hash(Some(&active_key_id), &prompt.content)?)
+                    .bind(&prompt.id)
+                    .execute(&mut *tx)
+                    .await?;
+
+                self.index_blind_tokens(&mut tx, Some(&active_key_id), &prompt.id, &prompt.content, &prompt.application, &prompt.tags)
+                    .await?;
+            }
+
+            tx.commit().await?;
+            reencrypted += rows.len() as i64;
+            println!("[DB] Re-encrypted {} prompts with key {}", reencrypted, active_key_id);
+        }
+
+        self.reseal_table("prompt_revisions", &["content", "tags"], &active_key_id).await?;
+        self.reseal_table("prompt_templates", &["name", "description", "body", "variables"], &active_key_id).await?;
+        self.reseal_table("workflows", &["name", "description", "steps"], &active_key_id).await?;
+        self.reseal_table("workflow_run_steps", &["output"], &active_key_id).await?;
+        self.reseal_table("responses", &["response"], &active_key_id).await?;
+        self.reseal_table("sessions", &["context"], &active_key_id).await?;
+
+        loop {
+            let mut tx = self.pool.begin().await?;
+            let rows = sqlx::query("SELECT * FROM tags WHERE is_encrypted = 1 AND COALESCE(key_id, ?) != ? LIMIT ?")
+                .bind(LEGACY_KEY_ID)
+                .bind(&active_key_id)
+                .bind(ROTATION_BATCH_SIZE)
+                .fetch_all(&mut *tx)
+                .await?;
+
+            if rows.is_empty() {
+                break;
+            }
+
+            for row in &rows {
+                let name = self.unseal(row.get("name"), Self::row_key_id(row).as_deref())?;
 
-        let mut sql_query = sqlx::query(&query);
-        for param in params {
-            sql_query = sql_query.bind(param);
+                sqlx::query("UPDATE tags SET name = ?, lookup = ?, key_id = ? WHERE id = ?")
+                    .bind(self.seal(Some(&active_key_id), &name)?)
+                    .bind(crypto.tag_hash(&active_key_id, &name)?)
+                    .bind(&active_key_id)
+                    .bind(row.get::<i64, _>("id"))
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+            println!("[DB] Re-encrypted {} tags with key {}", rows.len(), active_key_id);
+        }
+
+        // No writer may be mid-way through encrypting with a key we're about to forget
+        let _key_guard = self.key_lock.write().await;
+        let sql = ENCRYPTED_TABLES.iter()
+            .map(|table| format!("SELECT COALESCE(key_id, '{}') FROM {} WHERE is_encrypted = 1", LEGACY_KEY_ID, table))
+            .collect::<Vec<_>>()
+            .join(" UNION ");
+        let in_use: Vec<String> = sqlx::query_scalar(&sql)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut retired_keys = Vec::new();
+        for key_id in crypto.key_ids() {
+            if key_id != active_key_id && !in_use.contains(&key_id) {
+                crypto.retire_key(&key_id)?;
+                retired_keys.push(key_id);
+            }
         }
+        if !retired_keys.is_empty() {
+            if let Err(e) = self.store_passphrase_header(&crypto).await {
+                eprintln!("[DB] Failed to store passphrase header after retiring keys: {}", e);
+            }
+        }
+
+        Ok(KeyRotationReport {
+            active_key_id,
+            reencrypted,
+            retired_keys,
+        })
+    }
+
+    /// Re-encrypts `columns` of every row in `table` still sealed with an older key.
+    /// NULL columns stay NULL.
+    async fn reseal_table(&self, table: &str, columns: &[&str], active_key_id: &str) -> Result<()> {
+        let select = format!(
+            "SELECT rowid AS row_ref, * FROM {} WHERE is_encrypted = 1 AND COALESCE(key_id, ?) != ? LIMIT ?",
+            table
+        );
+        let assignments: Vec<String> = columns.iter().map(|column| format!("{} = ?", column)).collect();
+        let update = format!("UPDATE {} SET {}, key_id = ? WHERE rowid = ?", table, assignments.join(", "));
+
+        loop {
+            let mut tx = self.pool.begin().await?;
+            let rows = sqlx::query(&select)
+                .bind(LEGACY_KEY_ID)
+                .bind(active_key_id)
+                .bind(ROTATION_BATCH_SIZE)
+                .fetch_all(&mut *tx)
+                .await?;
+
+            if rows.is_empty() {
+                break;
+            }
+
+            for row in &rows {
+                let key_id = Self::row_key_id(row);
+                let mut query = sqlx::query(&update);
+                for column in columns {
+                    let value: Option<String> = row.get(*column);
+                    let resealed = value
+                        .map(|value| -> Result<String> {
+                            let plaintext = self.unseal(value, key_id.as_deref())?;
+                            self.seal(Some(active_key_id), &plaintext)
+                        })
+                        .transpose()?;
+                    query = query.bind(resealed);
+                }
+
+                query.bind(active_key_id)
+                    .bind(row.get::<i64, _>("row_ref"))
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+            println!("[DB] Re-encrypted {} rows of {} with key {}", rows.len(), table, active_key_id);
+        }
+
+        Ok(())
+    }
+
+    /// Unlocks a passphrase protected database. The first unlock sets the passphrase.
+    pub async fn unlock_with_passphrase(&self, passphrase: &str) -> Result<()> {
+        if !self.passphrase_protected {
+            return Err(PromptHistError::Encryption("Database is not passphrase protected".to_string()));
+        }
+        if passphrase.is_empty() {
+            return Err(PromptHistError::InvalidInput("Passphrase must not be empty".to_string()));
+        }
+
+        let crypto = match self.load_passphrase_header().await? {
+            Some(header) => CryptoManager::unlock_with_passphrase(passphrase, &header)?,
+            None => {
+                println!("[DB] No passphrase header found, creating new encryption keys");
+                let (crypto, header) = CryptoManager::create_with_passphrase(passphrase)?;
+                self.write_passphrase_header(&header).await?;
+                crypto
+            }
+        };
+
+        *self.crypto.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Arc::new(crypto));
+        self.prepare_encryption().await
+    }
+
+    /// Forgets the unwrapped keys of a passphrase protected database
+    pub async fn lock(&self) -> Result<()> {
+        if !self.passphrase_protected {
+            return Err(PromptHistError::Encryption("Database is not passphrase protected".to_string()));
+        }
+
+        let _key_guard = self.key_lock.write().await;
+        *self.crypto.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+        Ok(())
+    }
+
+    async fn load_passphrase_header(&self) -> Result<Option<PassphraseHeader>> {
+        let row = sqlx::query("SELECT * FROM encryption_header WHERE id = 1")
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|row| PassphraseHeader {
+            kdf_memory_kib: row.get("kdf_memory_kib"),
+            kdf_iterations: row.get("kdf_iterations"),
+            kdf_parallelism: row.get("kdf_parallelism"),
+            salt: row.get("salt"),
+            wrapped_keys: row.get("wrapped_keys"),
+        }))
+    }
+
+    async fn write_passphrase_header(&self, header: &PassphraseHeader) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO encryption_header (id, kdf, kdf_memory_kib, kdf_iterations, kdf_parallelism, salt, wrapped_keys)
+            VALUES (1, 'argon2id', ?, ?, ?, ?, ?)
+            ON CONFLICT(id) DO UPDATE SET
+                kdf_memory_kib = excluded.kdf_memory_kib,
+                kdf_iterations = excluded.kdf_iterations,
+                kdf_parallelism = excluded.kdf_parallelism,
+                salt = excluded.salt,
+                wrapped_keys = excluded.wrapped_keys,
+                updated_at = CURRENT_TIMESTAMP
+            "#,
+        )
+        .bind(header.kdf_memory_kib)
+        .bind(header.kdf_iterations)
+        .bind(header.kdf_parallelism)
+        .bind(&header.salt)
+        .bind(&header.wrapped_keys)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Persists the wrapped keys after they change; a no-op for keyring-stored keys
+    async fn store_passphrase_header(&self, crypto: &CryptoManager) -> Result<()> {
+        match crypto.passphrase_header()? {
+            Some(header) => self.write_passphrase_header(&header).await,
+            None => Ok(()),
+        }
+    }
+
+    fn row_to_collection(row: &SqliteRow) -> Result<Collection> {
+        let created_at: String = row.get("created_at");
+        Ok(Collection {
+            id: row.get("id"),
+            parent_id: row.get("parent_id"),
+            name: row.get("name"),
+            position: row.get("position"),
+            created_at: Self::parse_timestamp(&created_at)?,
+        })
+    }
+
+    fn collection_name_error(e: sqlx::Error, name: &str) -> PromptHistError {
+        match &e {
+            sqlx::Error::Database(db_err) if db_err.is_unique_violation() => PromptHistError::InvalidInput(
+                format!("A collection named '{}' already exists here", name),
+            ),
+            _ => e.into(),
+        }
+    }
+
+    async fn get_collection(&self, conn: &mut SqliteConnection, id: &str) -> Result<Collection> {
+        let row = sqlx::query("SELECT * FROM collections WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .ok_or_else(|| PromptHistError::InvalidInput(format!("Collection not found: {}", id)))?;
+        Self::row_to_collection(&row)
+    }
+
+    /// Ids of the collections directly inside `parent_id`, in display order
+    async fn sibling_collection_ids(conn: &mut SqliteConnection, parent_id: Option<&str>) -> Result<Vec<String>> {
+        Ok(sqlx::query_scalar("SELECT id FROM collections WHERE parent_id IS ? ORDER BY position, name")
+            .bind(parent_id)
+            .fetch_all(&mut *conn)
+            .await?)
+    }
+
+    /// Puts `id` at `position` (or last) in `ids`
+    fn place(ids: &mut Vec<String>, id: &str, position: Option<i64>) {
+        ids.retain(|existing| existing != id);
+        let index = position.map(|p| p.clamp(0, ids.len() as i64) as usize).unwrap_or(ids.len());
+        ids.insert(index, id.to_string());
+    }
+
+    pub async fn create_collection(&self, name: &str, parent_id: Option<&str>) -> Result<Collection> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(PromptHistError::InvalidInput("Collection name must not be empty".to_string()));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        if let Some(parent_id) = parent_id {
+            self.get_collection(&mut tx, parent_id).await?;
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let now = Utc::now().to_rfc3339();
+        let position = Self::sibling_collection_ids(&mut tx, parent_id).await?.len() as i64;
+        sqlx::query(
+            "INSERT INTO collections (id, parent_id, name, position, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&id)
+        .bind(parent_id)
+        .bind(name)
+        .bind(position)
+        .bind(&now)
+        .bind(&now)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| Self::collection_name_error(e, name))?;
+
+        let collection = self.get_collection(&mut tx, &id).await?;
+        tx.commit().await?;
+        Ok(collection)
+    }
+
+    pub async fn rename_collection(&self, id: &str, name: &str) -> Result<()> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(PromptHistError::InvalidInput("Collection name must not be empty".to_string()));
+        }
+
+        let result = sqlx::query("UPDATE collections SET name = ?, updated_at = ? WHERE id = ?")
+            .bind(name)
+            .bind(Utc::now().to_rfc3339())
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| Self::collection_name_error(e, name))?;
+
+        if result.rows_affected() == 0 {
+            return Err(PromptHistError::InvalidInput(format!("Collection not found: {}", id)));
+        }
+        Ok(())
+    }
+
+    /// Moves a collection under `parent_id` (or to the top level) at `position` among its
+    /// new siblings. Also used to reorder within the same parent.
+    pub async fn move_collection(&self, id: &str, parent_id: Option<&str>, position: Option<i64>) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let collection = self.get_collection(&mut tx, id).await?;
+
+        if let Some(parent_id) = parent_id {
+            // The new parent must not be the collection itself or anything inside it
+            let inside: bool = sqlx::query_scalar(
+                r#"
+                WITH RECURSIVE subtree(id) AS (
+                    SELECT ?
+                    UNION ALL
+                    SELECT c.id FROM collections c JOIN subtree s ON c.parent_id = s.id
+                )
+                SELECT EXISTS (SELECT 1 FROM subtree WHERE id = ?)
+                "#,
+            )
+            .bind(id)
+            .bind(parent_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+            if inside {
+                return Err(PromptHistError::InvalidInput("Cannot move a collection into itself".to_string()));
+            }
+            self.get_collection(&mut tx, parent_id).await?;
+        }
+
+        sqlx::query("UPDATE collections SET parent_id = ?, updated_at = ? WHERE id = ?")
+            .bind(parent_id)
+            .bind(Utc::now().to_rfc3339())
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Self::collection_name_error(e, &collection.name))?;
+
+        let mut siblings = Self::sibling_collection_ids(&mut tx, parent_id).await?;
+        Self::place(&mut siblings, id, position);
+        for (position, sibling_id) in siblings.iter().enumerate() {
+            sqlx::query("UPDATE collections SET position = ? WHERE id = ?")
+                .bind(position as i64)
+                .bind(sibling_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Deletes a collection with everything nested in it. The prompts themselves are kept.
+    pub async fn delete_collection(&self, id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM collections WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// All collections as a tree, each level in display order
+    pub async fn get_collection_tree(&self) -> Result<Vec<CollectionNode>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT c.*, (SELECT COUNT(*) FROM collection_prompts cp WHERE cp.collection_id = c.id) AS prompt_count
+            FROM collections c
+            ORDER BY c.position, c.name
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut children: HashMap<Option<String>, Vec<CollectionNode>> = HashMap::new();
+        for row in &rows {
+            let collection = Self::row_to_collection(row)?;
+            children.entry(collection.parent_id.clone()).or_default().push(CollectionNode {
+                collection,
+                prompt_count: row.get("prompt_count"),
+                children: Vec::new(),
+            });
+        }
+
+        fn attach(node: &mut CollectionNode, children: &mut HashMap<Option<String>, Vec<CollectionNode>>) {
+            node.children = children.remove(&Some(node.collection.id.clone())).unwrap_or_default();
+            for child in &mut node.children {
+                attach(child, children);
+            }
+        }
+
+        let mut roots = children.remove(&None).unwrap_or_default();
+        for root in &mut roots {
+            attach(root, &mut children);
+        }
+        Ok(roots)
+    }
+
+    /// Adds a prompt to a collection at `position` (or last). Adding it again just moves it.
+    pub async fn add_prompt_to_collection(&self, collection_id: &str, prompt_id: &str, position: Option<i64>) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        self.get_collection(&mut tx, collection_id).await?;
+
+        let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM prompts WHERE id = ?)")
+            .bind(prompt_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        if !exists {
+            return Err(PromptHistError::InvalidInput(format!("Prompt not found: {}", prompt_id)));
+        }
+
+        sqlx::query("INSERT OR IGNORE INTO collection_prompts (collection_id, prompt_id, added_at) VALUES (?, ?, ?)")
+            .bind(collection_id)
+            .bind(prompt_id)
+            .bind(Utc::now().to_rfc3339())
+            .execute(&mut *tx)
+            .await?;
+
+        let mut prompt_ids = Self::collection_prompt_ids(&mut tx, collection_id).await?;
+        Self::place(&mut prompt_ids, prompt_id, position);
+        Self::write_collection_order(&mut tx, collection_id, &prompt_ids).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn remove_prompt_from_collection(&self, collection_id: &str, prompt_id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM collection_prompts WHERE collection_id = ? AND prompt_id = ?")
+            .bind(collection_id)
+            .bind(prompt_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Sets the order of prompts in a collection. `prompt_ids` must list every member exactly once.
+    pub async fn reorder_collection_prompts(&self, collection_id: &str, prompt_ids: &[String]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let mut members = Self::collection_prompt_ids(&mut tx, collection_id).await?;
+        let mut requested = prompt_ids.to_vec();
+        members.sort();
+        requested.sort();
+        if members != requested {
+            return Err(PromptHistError::InvalidInput(
+                "New order must list every prompt in the collection exactly once".to_string(),
+            ));
+        }
+
+        Self::write_collection_order(&mut tx, collection_id, prompt_ids).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Prompts in a collection, in their collection order
+    pub async fn get_collection_prompts(&self, collection_id: &str) -> Result<Vec<PromptEntry>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT p.* FROM prompts p
+            JOIN collection_prompts cp ON cp.prompt_id = p.id
+            WHERE cp.collection_id = ?
+            ORDER BY cp.position
+            "#,
+        )
+        .bind(collection_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        self.rows_to_prompts(&rows)
+    }
+
+    async fn collection_prompt_ids(conn: &mut SqliteConnection, collection_id: &str) -> Result<Vec<String>> {
+        Ok(sqlx::query_scalar("SELECT prompt_id FROM collection_prompts WHERE collection_id = ? ORDER BY position, added_at")
+            .bind(collection_id)
+            .fetch_all(&mut *conn)
+            .await?)
+    }
+
+    async fn write_collection_order(conn: &mut SqliteConnection, collection_id: &str, prompt_ids: &[String]) -> Result<()> {
+        for (position, prompt_id) in prompt_ids.iter().enumerate() {
+            sqlx::query("UPDATE collection_prompts SET position = ? WHERE collection_id = ? AND prompt_id = ?")
+                .bind(position as i64)
+                .bind(collection_id)
+                .bind(prompt_id)
+                .execute(&mut *conn)
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn row_to_template(&self, row: &SqliteRow) -> Result<PromptTemplate> {
+        let key_id = Self::row_key_id(row);
+        let description: Option<String> = row.get("description");
+        let variables_json = self.unseal(row.get("variables"), key_id.as_deref())?;
+        let created_at: String = row.get("created_at");
+        let updated_at: String = row.get("updated_at");
+
+        Ok(PromptTemplate {
+            id: row.get("id"),
+            name: self.unseal(row.get("name"), key_id.as_deref())?,
+            description: description.map(|d| self.unseal(d, key_id.as_deref())).transpose()?,
+            body: self.unseal(row.get("body"), key_id.as_deref())?,
+            variables: serde_json::from_str(&variables_json)?,
+            created_at: Self::parse_timestamp(&created_at)?,
+            updated_at: Self::parse_timestamp(&updated_at)?,
+        })
+    }
+
+    fn validate_template(name: &str, body: &str, variables: &[TemplateVariable]) -> Result<()> {
+        if name.trim().is_empty() {
+            return Err(PromptHistError::InvalidInput("Template name must not be empty".to_string()));
+        }
+        templates::validate(body, variables)
+    }
+
+    /// Stores a new template after checking its placeholders against `variables`
+    pub async fn create_template(
+        &self,
+        name: &str,
+        description: Option<&str>,
+        body: &str,
+        variables: &[TemplateVariable],
+    ) -> Result<PromptTemplate> {
+        Self::validate_template(name, body, variables)?;
+
+        let _key_guard = self.key_lock.read().await;
+        let key_id = self.active_key_id()?;
+        let key_id = key_id.as_deref();
+        let id = uuid::Uuid::new_v4().to_string();
+        let now = Utc::now().to_rfc3339();
+
+        sqlx::query(
+            r#"
+            INSERT INTO prompt_templates (id, name, description, body, variables, is_encrypted, key_id, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&id)
+        .bind(self.seal(key_id, name.trim())?)
+        .bind(description.map(|d| self.seal(key_id, d)).transpose()?)
+        .bind(self.seal(key_id, body)?)
+        .bind(self.seal(key_id, &serde_json::to_string(variables)?)?)
+        .bind(if key_id.is_some() { 1 } else { 0 })
+        .bind(key_id)
+        .bind(&now)
+        .bind(&now)
+        .execute(&self.pool)
+        .await?;
+
+        self.get_template(&id).await?
+            .ok_or_else(|| PromptHistError::InvalidInput(format!("Template not found: {}", id)))
+    }
+
+    /// Replaces a template's name, description, body and variables
+    pub async fn update_template(
+        &self,
+        id: &str,
+        name: &str,
+        description: Option<&str>,
+        body: &str,
+        variables: &[TemplateVariable],
+    ) -> Result<PromptTemplate> {
+        Self::validate_template(name, body, variables)?;
+
+        let _key_guard = self.key_lock.read().await;
+        let key_id = self.active_key_id()?;
+        let key_id = key_id.as_deref();
+
+        let updated = sqlx::query(
+            r#"
+            UPDATE prompt_templates
+            SET name = ?, description = ?, body = ?, variables = ?, is_encrypted = ?, key_id = ?, updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(self.seal(key_id, name.trim())?)
+        .bind(description.map(|d| self.seal(key_id, d)).transpose()?)
+        .bind(self.seal(key_id, body)?)
+        .bind(self.seal(key_id, &serde_json::to_string(variables)?)?)
+        .bind(if key_id.is_some() { 1 } else { 0 })
+        .bind(key_id)
+        .bind(Utc::now().to_rfc3339())
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+
+        if updated.rows_affected() == 0 {
+            return Err(PromptHistError::InvalidInput(format!("Template not found: {}", id)));
+        }
+        self.get_template(id).await?
+            .ok_or_else(|| PromptHistError::InvalidInput(format!("Template not found: {}", id)))
+    }
+
+    /// Deletes a template. Prompts rendered from it are kept but lose the link.
+    pub async fn delete_template(&self, id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM prompt_templates WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_template(&self, id: &str) -> Result<Option<PromptTemplate>> {
+        let row = sqlx::query("SELECT * FROM prompt_templates WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.map(|row| self.row_to_template(&row)).transpose()
+    }
+
+    /// All templates sorted by name
+    pub async fn list_templates(&self) -> Result<Vec<PromptTemplate>> {
+        let rows = sqlx::query("SELECT * FROM prompt_templates")
+            .fetch_all(&self.pool)
+            .await?;
+
+        // Names may be encrypted, so sort after decrypting
+        let mut templates = rows.iter()
+            .map(|row| self.row_to_template(row))
+            .collect::<Result<Vec<_>>>()?;
+        templates.sort_by_key(|template| template.name.to_lowercase());
+        Ok(templates)
+    }
+
+    /// Fills in a template. With `save`, the result is also stored in history linked to the template.
+    pub async fn render_template(&self, id: &str, values: &HashMap<String, String>, save: bool) -> Result<RenderedTemplate> {
+        let template = self.get_template(id).await?
+            .ok_or_else(|| PromptHistError::InvalidInput(format!("Template not found: {}", id)))?;
+        let content = templates::render(&template.body, &template.variables, values)?;
+
+        if !save {
+            return Ok(RenderedTemplate { content, prompt_id: None });
+        }
+
+        let entry = PromptEntry {
+            id: uuid::Uuid::new_v4().to_string(),
+            content: content.clone(),
+            application: "template".to_string(),
+            timestamp: Utc::now(),
+            starred: false,
+            tags: vec![],
+            usage_count: 0,
+            is_encrypted: false,
+            last_used: None,
+            template_id: Some(template.id.clone()),
+            session_id: None,
+            window_title: None,
+        };
+        let prompt_id = self.save_prompt(&entry).await?;
+
+        // A rendering that matched an existing prompt still gets linked to the template
+        sqlx::query("UPDATE prompts SET template_id = COALESCE(template_id, ?) WHERE id = ?")
+            .bind(&template.id)
+            .bind(&prompt_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(RenderedTemplate { content, prompt_id: Some(prompt_id) })
+    }
+
+    /// Saved prompts that were rendered from a template, newest first
+    pub async fn get_template_prompts(&self, template_id: &str) -> Result<Vec<PromptEntry>> {
+        let rows = sqlx::query("SELECT * FROM prompts WHERE template_id = ? ORDER BY timestamp DESC")
+            .bind(template_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        self.rows_to_prompts(&rows)
+    }
+
+    fn row_to_workflow(&self, row: &SqliteRow) -> Result<Workflow> {
+        let key_id = Self::row_key_id(row);
+        let description: Option<String> = row.get("description");
+        let steps_json = self.unseal(row.get("steps"), key_id.as_deref())?;
+        let created_at: String = row.get("created_at");
+        let updated_at: String = row.get("updated_at");
+
+        Ok(Workflow {
+            id: row.get("id"),
+            name: self.unseal(row.get("name"), key_id.as_deref())?,
+            description: description.map(|d| self.unseal(d, key_id.as_deref())).transpose()?,
+            steps: serde_json::from_str(&steps_json)?,
+            created_at: Self::parse_timestamp(&created_at)?,
+            updated_at: Self::parse_timestamp(&updated_at)?,
+        })
+    }
+
+    fn validate_workflow(name: &str, steps: &[WorkflowStep]) -> Result<()> {
+        if name.trim().is_empty() {
+            return Err(PromptHistError::InvalidInput("Workflow name must not be empty".to_string()));
+        }
+        if steps.is_empty() {
+            return Err(PromptHistError::InvalidInput("Workflow needs at least one step".to_string()));
+        }
+
+        for (index, step) in steps.iter().enumerate() {
+            if let Some(variable) = &step.output_variable {
+                if !templates::is_identifier(variable) {
+                    return Err(PromptHistError::InvalidInput(format!("Invalid output variable: \"{}\"", variable)));
+                }
+                if steps[..index].iter().any(|other| other.output_variable.as_ref() == Some(variable)) {
+                    return Err(PromptHistError::InvalidInput(format!("Output variable used twice: {}", variable)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stores a new workflow
+    pub async fn create_workflow(&self, name: &str, description: Option<&str>, steps: &[WorkflowStep]) -> Result<Workflow> {
+        Self::validate_workflow(name, steps)?;
+
+        let _key_guard = self.key_lock.read().await;
+        let key_id = self.active_key_id()?;
+        let key_id = key_id.as_deref();
+        let id = uuid::Uuid::new_v4().to_string();
+        let now = Utc::now().to_rfc3339();
+
+        sqlx::query(
+            r#"
+            INSERT INTO workflows (id, name, description, steps, is_encrypted, key_id, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&id)
+        .bind(self.seal(key_id, name.trim())?)
+        .bind(description.map(|d| self.seal(key_id, d)).transpose()?)
+        .bind(self.seal(key_id, &serde_json::to_string(steps)?)?)
+        .bind(if key_id.is_some() { 1 } else { 0 })
+        .bind(key_id)
+        .bind(&now)
+        .bind(&now)
+        .execute(&self.pool)
+        .await?;
+
+        self.get_workflow(&id).await?
+            .ok_or_else(|| PromptHistError::InvalidInput(format!("Workflow not found: {}", id)))
+    }
+
+    /// Replaces a workflow's name, description and steps. Earlier runs are kept.
+    pub async fn update_workflow(&self, id: &str, name: &str, description: Option<&str>, steps: &[WorkflowStep]) -> Result<Workflow> {
+        Self::validate_workflow(name, steps)?;
+
+        let _key_guard = self.key_lock.read().await;
+        let key_id = self.active_key_id()?;
+        let key_id = key_id.as_deref();
+
+        let updated = sqlx::query(
+            r#"
+            UPDATE workflows
+            SET name = ?, description = ?, steps = ?, is_encrypted = ?, key_id = ?, updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(self.seal(key_id, name.trim())?)
+        .bind(description.map(|d| self.seal(key_id, d)).transpose()?)
+        .bind(self.seal(key_id, &serde_json::to_string(steps)?)?)
+        .bind(if key_id.is_some() { 1 } else { 0 })
+        .bind(key_id)
+        .bind(Utc::now().to_rfc3339())
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+
+        if updated.rows_affected() == 0 {
+            return Err(PromptHistError::InvalidInput(format!("Workflow not found: {}", id)));
+        }
+        self.get_workflow(id).await?
+            .ok_or_else(|| PromptHistError::InvalidInput(format!("Workflow not found: {}", id)))
+    }
+
+    /// Deletes a workflow and its runs. Prompts sent by the runs stay in history.
+    pub async fn delete_workflow(&self, id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM workflows WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_workflow(&self, id: &str) -> Result<Option<Workflow>> {
+        let row = sqlx::query("SELECT * FROM workflows WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.map(|row| self.row_to_workflow(&row)).transpose()
+    }
+
+    /// All workflows sorted by name
+    pub async fn list_workflows(&self) -> Result<Vec<Workflow>> {
+        let rows = sqlx::query("SELECT * FROM workflows")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut workflows = rows.iter()
+            .map(|row| self.row_to_workflow(row))
+            .collect::<Result<Vec<_>>>()?;
+        workflows.sort_by_key(|workflow| workflow.name.to_lowercase());
+        Ok(workflows)
+    }
+
+    /// Records the start of a run and returns its id
+    pub async fn start_workflow_run(&self, workflow_id: &str, model: &str) -> Result<String> {
+        let id = uuid::Uuid::new_v4().to_string();
+        sqlx::query("INSERT INTO workflow_runs (id, workflow_id, model, status, started_at) VALUES (?, ?, ?, ?, ?)")
+            .bind(&id)
+            .bind(workflow_id)
+            .bind(model)
+            .bind(Self::run_status_str(RunStatus::Running))
+            .bind(Utc::now().to_rfc3339())
+            .execute(&self.pool)
+            .await?;
+        Ok(id)
+    }
+
+    /// Stores the output of one step. The output is encrypted like prompt content.
+    pub async fn record_workflow_step(&self, run_id: &str, step: &WorkflowRunStep) -> Result<()> {
+        let _key_guard = self.key_lock.read().await;
+        let key_id = self.active_key_id()?;
+        let key_id = key_id.as_deref();
+
+        sqlx::query(
+            r#"
+            INSERT INTO workflow_run_steps (run_id, step_index, prompt_id, model, output, duration_ms, is_encrypted, key_id, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(run_id)
+        .bind(step.step_index)
+        .bind(&step.prompt_id)
+        .bind(&step.model)
+        .bind(self.seal(key_id, &step.output)?)
+        .bind(step.duration_ms)
+        .bind(if key_id.is_some() { 1 } else { 0 })
+        .bind(key_id)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Marks a run completed, or failed when `error` is given
+    pub async fn finish_workflow_run(&self, run_id: &str, error: Option<&str>) -> Result<()> {
+        let status = if error.is_some() { RunStatus::Failed } else { RunStatus::Completed };
+        sqlx::query("UPDATE workflow_runs SET status = ?, error = ?, finished_at = ? WHERE id = ?")
+            .bind(Self::run_status_str(status))
+            .bind(error)
+            .bind(Utc::now().to_rfc3339())
+            .bind(run_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    fn run_status_str(status: RunStatus) -> &'static str {
+        match status {
+            RunStatus::Running => "running",
+            RunStatus::Completed => "completed",
+            RunStatus::Failed => "failed",
+        }
+    }
+
+    fn row_to_workflow_run(row: &SqliteRow, steps: Vec<WorkflowRunStep>) -> Result<WorkflowRun> {
+        let status = match row.get::<String, _>("status").as_str() {
+            "completed" => RunStatus::Completed,
+            "failed" => RunStatus::Failed,
+            _ => RunStatus::Running,
+        };
+        let started_at: String = row.get("started_at");
+        let finished_at: Option<String> = row.get("finished_at");
+
+        Ok(WorkflowRun {
+            id: row.get("id"),
+            workflow_id: row.get("workflow_id"),
+            model: row.get("model"),
+            status,
+            error: row.get("error"),
+            started_at: Self::parse_timestamp(&started_at)?,
+            finished_at: finished_at.as_deref().map(Self::parse_timestamp).transpose()?,
+            steps,
+        })
+    }
+
+    async fn get_workflow_run_steps(&self, run_id: &str) -> Result<Vec<WorkflowRunStep>> {
+        let rows = sqlx::query("SELECT * FROM workflow_run_steps WHERE run_id = ? ORDER BY step_index")
+            .bind(run_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter()
+            .map(|row| {
+                let key_id = Self::row_key_id(row);
+                Ok(WorkflowRunStep {
+                    step_index: row.get("step_index"),
+                    prompt_id: row.get("prompt_id"),
+                    model: row.get("model"),
+                    output: self.unseal(row.get("output"), key_id.as_deref())?,
+                    duration_ms: row.get("duration_ms"),
+                })
+            })
+            .collect()
+    }
+
+    /// A run with the results of its steps
+    pub async fn get_workflow_run(&self, run_id: &str) -> Result<Option<WorkflowRun>> {
+        let row = sqlx::query("SELECT * FROM workflow_runs WHERE id = ?")
+            .bind(run_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        match row {
+            Some(row) => {
+                let steps = self.get_workflow_run_steps(run_id).await?;
+                Ok(Some(Self::row_to_workflow_run(&row, steps)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Runs of a workflow, newest first, without their step outputs
+    pub async fn list_workflow_runs(&self, workflow_id: &str) -> Result<Vec<WorkflowRun>> {
+        let rows = sqlx::query("SELECT * FROM workflow_runs WHERE workflow_id = ? ORDER BY started_at DESC")
+            .bind(workflow_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter().map(|row| Self::row_to_workflow_run(row, vec![])).collect()
+    }
+
+    /// Puts a prompt in the latest session of its application that was active within
+    /// `SESSION_GAP_MINUTES`, or starts a new one. A known context (window title) only
+    /// joins a session with the same or no context.
+    async fn assign_session(
+        &self,
+        conn: &mut SqliteConnection,
+        key_id: Option<&str>,
+        prompt_id: &str,
+        application: &str,
+        context: Option<&str>,
+        timestamp: DateTime<Utc>,
+    ) -> Result<String> {
+        let gap = chrono::Duration::minutes(SESSION_GAP_MINUTES);
+        let candidates = sqlx::query(
+            "SELECT * FROM sessions WHERE application = ? AND ended_at >= ? AND started_at <= ? ORDER BY ended_at DESC",
+        )
+        .bind(application)
+        .bind((timestamp - gap).to_rfc3339())
+        .bind((timestamp + gap).to_rfc3339())
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let mut session = None;
+        for row in &candidates {
+            let session_context: Option<String> = row.get("context");
+            let matches = match (context, session_context) {
+                (Some(context), Some(session_context)) => {
+                    self.unseal(session_context, Self::row_key_id(row).as_deref())? == context
+                }
+                _ => true,
+            };
+            if matches {
+                session = Some(row);
+                break;
+            }
+        }
+
+        let session_id = match session {
+            Some(row) => {
+                let id: String = row.get("id");
+                if row.get::<Option<String>, _>("context").is_none() {
+                    if let Some(context) = context {
+                        sqlx::query("UPDATE sessions SET context = ?, is_encrypted = ?, key_id = ? WHERE id = ?")
+                            .bind(self.seal(key_id, context)?)
+                            .bind(if key_id.is_some() { 1 } else { 0 })
+                            .bind(key_id)
+                            .bind(&id)
+                            .execute(&mut *conn)
+                            .await?;
+                    }
+                }
+                sqlx::query("UPDATE sessions SET started_at = MIN(started_at, ?), ended_at = MAX(ended_at, ?) WHERE id = ?")
+                    .bind(timestamp.to_rfc3339())
+                    .bind(timestamp.to_rfc3339())
+                    .bind(&id)
+                    .execute(&mut *conn)
+                    .await?;
+                id
+            }
+            None => {
+                let id = uuid::Uuid::new_v4().to_string();
+                sqlx::query(
+                    r#"
+                    INSERT INTO sessions (id, application, context, is_encrypted, key_id, started_at, ended_at)
+                    VALUES (?, ?, ?, ?, ?, ?, ?)
+                    "#,
+                )
+                .bind(&id)
+                .bind(application)
+                .bind(context.map(|context| self.seal(key_id, context)).transpose()?)
+                .bind(if key_id.is_some() && context.is_some() { 1 } else { 0 })
+                .bind(if context.is_some() { key_id } else { None })
+                .bind(timestamp.to_rfc3339())
+                .bind(timestamp.to_rfc3339())
+                .execute(&mut *conn)
+                .await?;
+                id
+            }
+        };
+
+        sqlx::query("UPDATE prompts SET session_id = ? WHERE id = ?")
+            .bind(&session_id)
+            .bind(prompt_id)
+            .execute(&mut *conn)
+            .await?;
+        Ok(session_id)
+    }
+
+    /// Groups prompts stored before sessions existed, oldest first
+    async fn backfill_sessions(&self) -> Result<()> {
+        let rows = sqlx::query("SELECT id, application, timestamp FROM prompts WHERE session_id IS NULL ORDER BY timestamp")
+            .fetch_all(&self.pool)
+            .await?;
+
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        let mut tx = self.pool.begin().await?;
+        for row in &rows {
+            let timestamp: String = row.get("timestamp");
+            let application: String = row.get("application");
+            self.assign_session(&mut tx, None, row.get("id"), &application, None, Self::parse_timestamp(&timestamp)?)
+                .await?;
+        }
+        tx.commit().await?;
+
+        println!("[DB] Grouped {} prompts into sessions", rows.len());
+        Ok(())
+    }
+
+    async fn prune_sessions(conn: &mut SqliteConnection) -> Result<()> {
+        sqlx::query("DELETE FROM sessions WHERE id NOT IN (SELECT session_id FROM prompts WHERE session_id IS NOT NULL)")
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Recomputes a session's time range from its prompts
+    async fn refresh_session(conn: &mut SqliteConnection, id: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE sessions
+            SET started_at = (SELECT MIN(timestamp) FROM prompts WHERE session_id = sessions.id),
+                ended_at = (SELECT MAX(timestamp) FROM prompts WHERE session_id = sessions.id)
+            WHERE id = ?
+            "#,
+        )
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    fn row_to_session(&self, row: &SqliteRow) -> Result<Session> {
+        let context: Option<String> = row.get("context");
+        let started_at: String = row.get("started_at");
+        let ended_at: String = row.get("ended_at");
+
+        Ok(Session {
+            id: row.get("id"),
+            application: row.get("application"),
+            context: context.map(|c| self.unseal(c, Self::row_key_id(row).as_deref())).transpose()?,
+            started_at: Self::parse_timestamp(&started_at)?,
+            ended_at: Self::parse_timestamp(&ended_at)?,
+            prompt_count: row.get("prompt_count"),
+        })
+    }
+
+    /// Sessions, most recently active first
+    pub async fn list_sessions(&self, application: Option<&str>, limit: Option<i32>, offset: Option<i32>) -> Result<Vec<Session>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT sessions.*, COUNT(prompts.id) AS prompt_count
+            FROM sessions JOIN prompts ON prompts.session_id = sessions.id
+            WHERE ? IS NULL OR sessions.application = ?
+            GROUP BY sessions.id
+            ORDER BY sessions.ended_at DESC
+            LIMIT ? OFFSET ?
+            "#,
+        )
+        .bind(application)
+        .bind(application)
+        .bind(limit.unwrap_or(50))
+        .bind(offset.unwrap_or(0))
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(|row| self.row_to_session(row)).collect()
+    }
+
+    pub async fn get_session(&self, id: &str) -> Result<Option<Session>> {
+        let row = sqlx::query(
+            r#"
+            SELECT sessions.*, COUNT(prompts.id) AS prompt_count
+            FROM sessions LEFT JOIN prompts ON prompts.session_id = sessions.id
+            WHERE sessions.id = ?
+            GROUP BY sessions.id
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
 
-        sql_query.execute(&self.pool).await?;
-        Ok(())
+        row.map(|row| self.row_to_session(&row)).transpose()
     }
 
-    pub async fn delete_prompt(&self, id: &str) -> Result<()> {
-        sqlx::query("DELETE FROM prompts WHERE id = ?")
+    /// A session's prompts in the order they were captured
+    pub async fn get_session_prompts(&self, id: &str) -> Result<Vec<PromptEntry>> {
+        let rows = sqlx::query("SELECT * FROM prompts WHERE session_id = ? ORDER BY timestamp, id")
             .bind(id)
-            .execute(&self.pool)
+            .fetch_all(&self.pool)
             .await?;
-        Ok(())
+
+        self.rows_to_prompts(&rows)
     }
 
-    pub async fn search_prompts(&self, query: &str, limit: Option<i32>) -> Result<Vec<PromptEntry>> {
-        let limit = limit.unwrap_or(50);
+    /// Moves `prompt_id` and every later prompt of its session into a new session
+    pub async fn split_session(&self, id: &str, prompt_id: &str) -> Result<Session> {
+        let mut tx = self.pool.begin().await?;
+        let timestamp: String = sqlx::query_scalar("SELECT timestamp FROM prompts WHERE id = ? AND session_id = ?")
+            .bind(prompt_id)
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| PromptHistError::InvalidInput(format!("Prompt {} is not in session {}", prompt_id, id)))?;
 
-        let sql = r#"
-            SELECT p.* FROM prompts p
-            JOIN prompts_fts fts ON p.rowid = fts.rowid
-            WHERE prompts_fts MATCH ?
-            ORDER BY rank
-            LIMIT ?
-        "#;
-
-        let rows = sqlx::query(sql)
-            .bind(query)
-            .bind(limit)
+        let earlier: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM prompts WHERE session_id = ? AND (timestamp < ? OR (timestamp = ? AND id < ?))",
+        )
+        .bind(id)
+        .bind(&timestamp)
+        .bind(&timestamp)
+        .bind(prompt_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if earlier == 0 {
+            return Err(PromptHistError::InvalidInput("Cannot split a session at its first prompt".to_string()));
+        }
+
+        let new_id = uuid::Uuid::new_v4().to_string();
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (id, application, context, is_encrypted, key_id, started_at, ended_at)
+            SELECT ?, application, context, is_encrypted, key_id, started_at, ended_at FROM sessions WHERE id = ?
+            "#,
+        )
+        .bind(&new_id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            "UPDATE prompts SET session_id = ? WHERE session_id = ? AND (timestamp > ? OR (timestamp = ? AND id >= ?))",
+        )
+        .bind(&new_id)
+        .bind(id)
+        .bind(&timestamp)
+        .bind(&timestamp)
+        .bind(prompt_id)
+        .execute(&mut *tx)
+        .await?;
+
+        Self::refresh_session(&mut tx, id).await?;
+        Self::refresh_session(&mut tx, &new_id).await?;
+        tx.commit().await?;
+
+        println!("[DB] Split session {} at prompt {} into {}", id, prompt_id, new_id);
+        self.get_session(&new_id).await?
+            .ok_or_else(|| PromptHistError::InvalidInput(format!("Session not found: {}", new_id)))
+    }
+
+    /// Moves the prompts of `source_ids` into `target_id` and removes the emptied sessions
+    pub async fn merge_sessions(&self, target_id: &str, source_ids: &[String]) -> Result<Session> {
+        if source_ids.iter().any(|id| id == target_id) {
+            return Err(PromptHistError::InvalidInput("Cannot merge a session into itself".to_string()));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        for id in std::iter::once(target_id).chain(source_ids.iter().map(String::as_str)) {
+            let exists: Option<String> = sqlx::query_scalar("SELECT id FROM sessions WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&mut *tx)
+                .await?;
+            if exists.is_none() {
+                return Err(PromptHistError::InvalidInput(format!("Session not found: {}", id)));
+            }
+        }
+
+        for id in source_ids {
+            sqlx::query("UPDATE prompts SET session_id = ? WHERE session_id = ?")
+                .bind(target_id)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM sessions WHERE id = ?")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        Self::refresh_session(&mut tx, target_id).await?;
+        tx.commit().await?;
+
+        println!("[DB] Merged {} sessions into {}", source_ids.len(), target_id);
+        self.get_session(target_id).await?
+            .ok_or_else(|| PromptHistError::InvalidInput(format!("Session not found: {}", target_id)))
+    }
+
+    /// Stores a model's answer to a prompt. `elapsed_ms` is used when Ollama reports no duration.
+    pub async fn save_response(&self, prompt_id: &str, response: &OllamaResponse, elapsed_ms: i64) -> Result<PromptResponse> {
+        let _key_guard = self.key_lock.read().await;
+        let key_id = self.active_key_id()?;
+        let key_id = key_id.as_deref();
+
+        let saved = PromptResponse {
+            id: uuid::Uuid::new_v4().to_string(),
+            prompt_id: prompt_id.to_string(),
+            model: response.model.clone(),
+            response: response.response.clone(),
+            duration_ms: Some(response.total_duration.map(|ns| ns / 1_000_000).unwrap_or(elapsed_ms)),
+            prompt_tokens: response.prompt_eval_count,
+            completion_tokens: response.eval_count,
+            created_at: Utc::now(),
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO responses (
+                id, prompt_id, model, response, duration_ms, prompt_tokens, completion_tokens, is_encrypted, key_id, created_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&saved.id)
+        .bind(&saved.prompt_id)
+        .bind(&saved.model)
+        .bind(self.seal(key_id, &saved.response)?)
+        .bind(saved.duration_ms)
+        .bind(saved.prompt_tokens)
+        .bind(saved.completion_tokens)
+        .bind(if key_id.is_some() { 1 } else { 0 })
+        .bind(key_id)
+        .bind(saved.created_at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(saved)
+    }
+
+    fn row_to_response(&self, row: &SqliteRow) -> Result<PromptResponse> {
+        let key_id = Self::row_key_id(row);
+        let created_at: String = row.get("created_at");
+
+        Ok(PromptResponse {
+            id: row.get("id"),
+            prompt_id: row.get("prompt_id"),
+            model: row.get("model"),
+            response: self.unseal(row.get("response"), key_id.as_deref())?,
+            duration_ms: row.get("duration_ms"),
+            prompt_tokens: row.get("prompt_tokens"),
+            completion_tokens: row.get("completion_tokens"),
+            created_at: Self::parse_timestamp(&created_at)?,
+        })
+    }
+
+    /// Responses recorded for a prompt, newest first
+    pub async fn get_prompt_responses(&self, prompt_id: &str) -> Result<Vec<PromptResponse>> {
+        let rows = sqlx::query("SELECT * FROM responses WHERE prompt_id = ? ORDER BY created_at DESC")
+            .bind(prompt_id)
             .fetch_all(&self.pool)
             .await?;
 
-        let mut prompts = Vec::new();
-        for row in rows {
-            let tags_json: String = row.get("tags");
-            let tags: Vec<String> = serde_json::from_str(&tags_json).unwrap_or_default();
-            let timestamp_str: String = row.get("timestamp");
-            let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
-                .map_err(|e| PromptHistError::InvalidInput(format!("Invalid timestamp: {}", e)))?
-                .with_timezone(&Utc);
-
-            prompts.push(PromptEntry {
-                id: row.get("id"),
-                content: row.get("content"),
-                application: row.get("application"),
-                timestamp,
-                starred: {
-                    // Try to get as integer first, then fall back to string
-                    if let Ok(starred_int) = row.try_get::<i32, _>("starred") {
-                        starred_int != 0
-                    } else if let Ok(starred_str) = row.try_get::<String, _>("starred") {
-                        starred_str == "1" || starred_str.to_lowercase() == "true"
-                    } else {
-                        false
-                    }
-                },
-                tags,
-                usage_count: row.get("usage_count"),
-                is_encrypted: {
-                    // Try to get as integer first, then fall back to string
-                    if let Ok(encrypted_int) = row.try_get::<i32, _>("is_encrypted") {
-                        encrypted_int != 0
-                    } else if let Ok(encrypted_str) = row.try_get::<String, _>("is_encrypted") {
-                        encrypted_str == "1" || encrypted_str.to_lowercase() == "true"
-                    } else {
-                        false
-                    }
-                },
-            });
+        rows.iter().map(|row| self.row_to_response(row)).collect()
+    }
+
+    pub async fn get_prompt_with_responses(&self, prompt_id: &str) -> Result<Option<PromptWithResponses>> {
+        match self.get_prompt_by_id(prompt_id).await? {
+            Some(prompt) => Ok(Some(PromptWithResponses {
+                responses: self.get_prompt_responses(&prompt.id).await?,
+                prompt,
+            })),
+            None => Ok(None),
         }
+    }
 
-        Ok(prompts)
+    pub async fn delete_response(&self, id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM responses WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
     }
 
     pub async fn get_prompt_stats(&self) -> Result<PromptStats> {
@@ -423,92 +3044,16 @@ impl PromptDatabase {
         }
 
         // Get most used prompts
-        let most_used_rows = sqlx::query("SELECT * FROM prompts ORDER BY usage_count DESC LIMIT 10")
+        let most_used_rows = sqlx::query("SELECT * FROM prompts ORDER BY usage_count DESC, last_used DESC LIMIT 10")
             .fetch_all(&self.pool)
             .await?;
-
-        let mut most_used_prompts = Vec::new();
-        for row in most_used_rows {
-            let tags_json: String = row.get("tags");
-            let tags: Vec<String> = serde_json::from_str(&tags_json).unwrap_or_default();
-            let timestamp_str: String = row.get("timestamp");
-            let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
-                .map_err(|e| PromptHistError::InvalidInput(format!("Invalid timestamp: {}", e)))?
-                .with_timezone(&Utc);
-
-            most_used_prompts.push(PromptEntry {
-                id: row.get("id"),
-                content: row.get("content"),
-                application: row.get("application"),
-                timestamp,
-                starred: {
-                    // Try to get as integer first, then fall back to string
-                    if let Ok(starred_int) = row.try_get::<i32, _>("starred") {
-                        starred_int != 0
-                    } else if let Ok(starred_str) = row.try_get::<String, _>("starred") {
-                        starred_str == "1" || starred_str.to_lowercase() == "true"
-                    } else {
-                        false
-                    }
-                },
-                tags,
-                usage_count: row.get("usage_count"),
-                is_encrypted: {
-                    // Try to get as integer first, then fall back to string
-                    if let Ok(encrypted_int) = row.try_get::<i32, _>("is_encrypted") {
-                        encrypted_int != 0
-                    } else if let Ok(encrypted_str) = row.try_get::<String, _>("is_encrypted") {
-                        encrypted_str == "1" || encrypted_str.to_lowercase() == "true"
-                    } else {
-                        false
-                    }
-                },
-            });
-        }
+        let most_used_prompts = self.rows_to_prompts(&most_used_rows)?;
 
         // Get recent activity
         let recent_rows = sqlx::query("SELECT * FROM prompts ORDER BY timestamp DESC LIMIT 10")
             .fetch_all(&self.pool)
             .await?;
-
-        let mut recent_activity = Vec::new();
-        for row in recent_rows {
-            let tags_json: String = row.get("tags");
-            let tags: Vec<String> = serde_json::from_str(&tags_json).unwrap_or_default();
-            let timestamp_str: String = row.get("timestamp");
-            let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
-                .map_err(|e| PromptHistError::InvalidInput(format!("Invalid timestamp: {}", e)))?
-                .with_timezone(&Utc);
-
-            recent_activity.push(PromptEntry {
-                id: row.get("id"),
-                content: row.get("content"),
-                application: row.get("application"),
-                timestamp,
-                starred: {
-                    // Try to get as integer first, then fall back to string
-                    if let Ok(starred_int) = row.try_get::<i32, _>("starred") {
-                        starred_int != 0
-                    } else if let Ok(starred_str) = row.try_get::<String, _>("starred") {
-                        starred_str == "1" || starred_str.to_lowercase() == "true"
-                    } else {
-                        false
-                    }
-                },
-                tags,
-                usage_count: row.get("usage_count"),
-                is_encrypted: {
-                    // Try to get as integer first, then fall back to string
-                    if let Ok(encrypted_int) = row.try_get::<i32, _>("is_encrypted") {
-                        encrypted_int != 0
-                    } else if let Ok(encrypted_str) = row.try_get::<String, _>("is_encrypted") {
-                        encrypted_str == "1" || encrypted_str.to_lowercase() == "true"
-                    } else {
-                        false
-                    }
-                },
-            });
-        }
+        let recent_activity = self.rows_to_prompts(&recent_rows)?;
 
         Ok(PromptStats {
             total_prompts,
@@ -519,11 +3064,847 @@ impl PromptDatabase {
         })
     }
 
-    pub async fn increment_usage_count(&self, id: &str) -> Result<()> {
-        sqlx::query("UPDATE prompts SET usage_count = usage_count + 1, updated_at = CURRENT_TIMESTAMP WHERE id = ?")
+    async fn increment_usage_count(conn: &mut SqliteConnection, id: &str) -> Result<()> {
+        sqlx::query("UPDATE prompts SET usage_count = usage_count + 1, last_used = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?")
+            .bind(Utc::now().to_rfc3339())
             .bind(id)
-            .execute(&self.pool)
+            .execute(&mut *conn)
             .await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuzzy;
+    use crate::keystore::MemoryKeyStore;
+    use crate::models::LineChange;
+
+    fn sample_prompt(content: &str) -> PromptEntry {
+        PromptEntry {
+            id: uuid::Uuid::new_v4().to_string(),
+            content: content.to_string(),
+            application: "Claude".to_string(),
+            timestamp: Utc::now(),
+            starred: false,
+            tags: vec!["rust".to_string()],
+            usage_count: 1,
+            is_encrypted: false,
+            last_used: None,
+            template_id: None,
+            session_id: None,
+            window_title: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_save_and_update_roundtrip() {
+        let db = PromptDatabase::new_in_memory(None).await.unwrap();
+        let prompt = sample_prompt("Explain lifetimes in Rust");
+        db.save_prompt(&prompt).await.unwrap();
+
+        db.update_prompt(&prompt.id, None, Some(true), None).await.unwrap();
+
+        let stored = db.get_prompt_by_id(&prompt.id).await.unwrap().unwrap();
+        assert_eq!(stored.content, prompt.content);
+        assert_eq!(stored.tags, prompt.tags);
+        assert!(stored.starred);
+        assert!(!stored.is_encrypted);
+    }
+
+    #[tokio::test]
+    async fn test_update_reseals_window_title() {
+        let db = PromptDatabase::new_in_memory(None).await.unwrap();
+        let prompt = PromptEntry { window_title: Some("main.rs - prompthist".to_string()), ..sample_prompt("Explain lifetimes in Rust") };
+        db.save_prompt(&prompt).await.unwrap();
+
+        // Captured before encryption was turned on
+        let crypto = CryptoManager::with_store(Box::new(MemoryKeyStore::default())).unwrap();
+        *db.crypto.write().unwrap() = Some(Arc::new(crypto));
+        db.update_prompt(&prompt.id, None, Some(true), None).await.unwrap();
+
+        let stored = db.get_prompt_by_id(&prompt.id).await.unwrap().unwrap();
+        assert!(stored.is_encrypted);
+        assert_eq!(stored.window_title, prompt.window_title);
+        assert_eq!(db.get_prompts(None, PromptSort::Timestamp, None, None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_row_requires_crypto() {
+        let db = PromptDatabase::new_in_memory(None).await.unwrap();
+        assert!(!db.encryption_enabled());
+        sqlx::query(
+            "INSERT INTO prompts (id, content, application, timestamp, tags, is_encrypted) VALUES ('x', 'ciphertext', 'Claude', ?, 'ciphertext', 1)",
+        )
+        .bind(Utc::now().to_rfc3339())
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            db.get_prompt_by_id("x").await,
+            Err(PromptHistError::Encryption(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_search_ranks_plaintext_matches() {
+        let db = PromptDatabase::new_in_memory(None).await.unwrap();
+        let once = sample_prompt("Refactor this function");
+        let twice = sample_prompt("Refactor the refactor helper");
+        db.save_prompt(&once).await.unwrap();
+        db.save_prompt(&twice).await.unwrap();
+        db.save_prompt(&sample_prompt("Write a haiku")).await.unwrap();
+
+        let results = db.search_prompts("refactor", None).await.unwrap();
+        assert_eq!(results.iter().map(|p| &p.id).collect::<Vec<_>>(), vec![&twice.id, &once.id]);
+    }
+
+    #[tokio::test]
+    async fn test_passphrase_lock_unlock_and_rotation() {
+        let pool = PromptDatabase::memory_pool().await.unwrap();
+        let db = PromptDatabase::open(pool, None, true).await.unwrap();
+        let prompt = sample_prompt("Summarize this design doc");
+
+        assert!(db.is_locked());
+        assert!(db.save_prompt(&prompt).await.is_err());
+
+        db.unlock_with_passphrase("hunter2").await.unwrap();
+        db.save_prompt(&prompt).await.unwrap();
+        let report = db.rotate_encryption_key().await.unwrap();
+        assert_eq!(report.reencrypted, 1);
+        assert_eq!(report.retired_keys.len(), 1);
+
+        db.lock().await.unwrap();
+        assert!(db.get_prompt_by_id(&prompt.id).await.is_err());
+        assert!(db.unlock_with_passphrase("hunter3").await.is_err());
+
+        // The rotated key must have been persisted in the header
+        db.unlock_with_passphrase("hunter2").await.unwrap();
+        let stored = db.get_prompt_by_id(&prompt.id).await.unwrap().unwrap();
+        assert_eq!(stored.content, prompt.content);
+        assert!(stored.is_encrypted);
+        assert_eq!(db.search_prompts("design", None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_saves_bump_usage_count() {
+        let db = PromptDatabase::new_in_memory(None).await.unwrap();
+        let first = sample_prompt("Write a haiku about borrow checking");
+        let again = sample_prompt("  Write a haiku about borrow checking\n");
+        let other = sample_prompt("Write a limerick instead");
+
+        assert_eq!(db.save_prompt(&first).await.unwrap(), first.id);
+        assert_eq!(db.save_prompt(&again).await.unwrap(), first.id);
+        db.save_prompt(&other).await.unwrap();
+
+        let stored = db.get_prompt_by_id(&first.id).await.unwrap().unwrap();
+        assert_eq!(stored.usage_count, 2);
+        assert!(stored.last_used.unwrap() >= first.timestamp);
+        assert!(db.get_prompt_by_id(&again.id).await.unwrap().is_none());
+
+        let result = db.update_prompt(&other.id, Some(first.content.clone()), None, None).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_duplicates_across_rotation() {
+        let crypto = CryptoManager::with_store(Box::new(MemoryKeyStore::default())).unwrap();
+        let db = PromptDatabase::new_in_memory(Some(crypto)).await.unwrap();
+        assert!(db.encryption_enabled());
+        let first = sample_prompt("Review this migration plan");
+
+        db.save_prompt(&first).await.unwrap();
+        let stored_hash: String = sqlx::query_scalar("SELECT content_hash FROM prompts")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_ne!(stored_hash, db.content_hash(None, &first.content).unwrap());
+
+        db.rotate_encryption_key().await.unwrap();
+        assert_eq!(db.save_prompt(&sample_prompt("Review this migration plan")).await.unwrap(), first.id);
+        assert_eq!(db.get_prompt_by_id(&first.id).await.unwrap().unwrap().usage_count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_folds_existing_duplicates() {
+        let db = PromptDatabase::new_in_memory(None).await.unwrap();
+        for (id, timestamp, starred, tags) in [
+            ("old", "2024-01-01T00:00:00+00:00", 0, r#"["a"]"#),
+            ("new", "2024-02-01T00:00:00+00:00", 1, r#"["b"]"#),
+        ] {
+            sqlx::query(
+                r#"
+                INSERT INTO prompts (id, content, application, timestamp, starred, tags, usage_count, is_encrypted, last_used)
+                VALUES (?, 'Same prompt', 'Claude', ?, ?, ?, 0, 0, ?)
+                "#,
+            )
+            .bind(id)
+            .bind(timestamp)
+            .bind(starred)
+            .bind(tags)
+            .bind(timestamp)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        }
+
+        db.backfill_content_hashes().await.unwrap();
+
+        assert!(db.get_prompt_by_id("new").await.unwrap().is_none());
+        let survivor = db.get_prompt_by_id("old").await.unwrap().unwrap();
+        assert_eq!(survivor.usage_count, 1);
+        assert!(survivor.starred);
+        assert_eq!(survivor.tags, vec!["a", "b"]);
+        assert_eq!(survivor.last_used.unwrap().to_rfc3339(), "2024-02-01T00:00:00+00:00");
+    }
+
+    #[tokio::test]
+    async fn test_near_duplicates_cluster_and_merge() {
+        let db = PromptDatabase::new_in_memory(None).await.unwrap();
+        let mut first = sample_prompt("Explain Rust lifetimes with a short example for beginners");
+        first.usage_count = 3;
+        let mut second = sample_prompt("Explain Rust lifetimes with a simple example for beginners");
+        second.tags = vec!["learning".to_string()];
+        let unrelated = sample_prompt("Write a SQL query that lists the ten most recent orders");
+        for prompt in [&first, &second, &unrelated] {
+            db.save_prompt(prompt).await.unwrap();
+        }
+
+        let clusters = db.find_near_duplicates(similarity::DEFAULT_SIMILARITY_THRESHOLD).await.unwrap();
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].primary_id, first.id);
+        assert_eq!(clusters[0].prompts.len(), 2);
+
+        let merged = db.merge_prompts(&first.id, &[second.id.clone()]).await.unwrap();
+        assert_eq!(merged.usage_count, 4);
+        assert_eq!(merged.tags, vec!["rust", "learning"]);
+        assert!(db.get_prompt_by_id(&second.id).await.unwrap().is_none());
+        assert!(db.merge_prompts(&first.id, &[second.id.clone()]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_revisions_diff_and_restore() {
+        let crypto = CryptoManager::with_store(Box::new(MemoryKeyStore::default())).unwrap();
+        let db = PromptDatabase::new_in_memory(Some(crypto)).await.unwrap();
+        let prompt = sample_prompt("You are a reviewer.\nBe brief.");
+        db.save_prompt(&prompt).await.unwrap();
+
+        db.update_prompt(&prompt.id, Some("You are a reviewer.\nBe thorough.".to_string()), None, None).await.unwrap();
+        db.update_prompt(&prompt.id, None, Some(true), Some(vec!["review".to_string()])).await.unwrap();
+        // No-op edits don't create revisions
+        db.update_prompt(&prompt.id, None, Some(true), None).await.unwrap();
+
+        let revisions = db.get_prompt_revisions(&prompt.id).await.unwrap();
+        assert_eq!(revisions.iter().map(|r| r.revision).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(revisions[1].content, prompt.content);
+
+        let diff = db.diff_prompt_revisions(&prompt.id, 1, None).await.unwrap();
+        assert_eq!(diff.lines.iter().filter(|line| line.change == LineChange::Removed).count(), 1);
+        assert_eq!(diff.tags_added, vec!["review"]);
+        assert_eq!(diff.tags_removed, vec!["rust"]);
+        assert!(!diff.starred_before && diff.starred_after);
+
+        // Revisions must follow the prompt through a key rotation
+        db.rotate_encryption_key().await.unwrap();
+
+        let restored = db.restore_prompt_revision(&prompt.id, 1).await.unwrap();
+        assert_eq!(restored.content, prompt.content);
+        assert_eq!(restored.tags, vec!["rust"]);
+        assert_eq!(db.get_prompt_revisions(&prompt.id).await.unwrap().len(), 3);
+
+        db.delete_prompt(&prompt.id).await.unwrap();
+        assert!(db.get_prompt_revisions(&prompt.id).await.unwrap().is_empty());
+    }
+
+    fn tag_filter(tags: &[&str], tag_match: TagMatch) -> PromptFilter {
+        PromptFilter {
+            application: None,
+            starred: None,
+            tags: Some(tags.iter().map(|tag| tag.to_string()).collect()),
+            tag_match,
+            search_text: None,
+            start_date: None,
+            end_date: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tag_filtering_and_management() {
+        let crypto = CryptoManager::with_store(Box::new(MemoryKeyStore::default())).unwrap();
+        let db = PromptDatabase::new_in_memory(Some(crypto)).await.unwrap();
+        let mut both = sample_prompt("Tagged with rust and sql");
+        both.tags = vec!["rust".to_string(), " sql ".to_string()];
+        let rust_only = sample_prompt("Tagged with rust only");
+        let mut untagged = sample_prompt("Tagged with nothing");
+        untagged.tags = vec![];
+        for prompt in [&both, &rust_only, &untagged] {
+            db.save_prompt(prompt).await.unwrap();
+        }
+
+        let names: Vec<String> = sqlx::query_scalar("SELECT name FROM tags").fetch_all(&db.pool).await.unwrap();
+        assert!(!names.iter().any(|name| name == "rust" || name == "sql"));
+
+        let any = db.get_prompts(Some(tag_filter(&["sql", "rust"], TagMatch::Any)), PromptSort::Timestamp, None, None).await.unwrap();
+        assert_eq!(any.len(), 2);
+        let all = db.get_prompts(Some(tag_filter(&["sql", "rust"], TagMatch::All)), PromptSort::Timestamp, None, None).await.unwrap();
+        assert_eq!(all.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), vec![both.id.as_str()]);
+        let missing = db.get_prompts(Some(tag_filter(&["go"], TagMatch::Any)), PromptSort::Timestamp, None, None).await.unwrap();
+        assert!(missing.is_empty());
+
+        assert_eq!(db.list_tags().await.unwrap(), vec![
+            TagCount { name: "rust".to_string(), count: 2 },
+            TagCount { name: "sql".to_string(), count: 1 },
+        ]);
+
+        db.rotate_encryption_key().await.unwrap();
+
+        assert_eq!(db.rename_tag("sql", "database").await.unwrap(), 1);
+        assert_eq!(db.merge_tags(&["database".to_string()], "rust").await.unwrap(), 1);
+        let merged = db.get_prompt_by_id(&both.id).await.unwrap().unwrap();
+        assert_eq!(merged.tags, vec!["rust"]);
+        assert_eq!(db.search_prompts("database", None).await.unwrap().len(), 0);
+
+        assert_eq!(db.delete_tag("rust").await.unwrap(), 2);
+        assert!(db.list_tags().await.unwrap().is_empty());
+        assert!(db.get_prompt_by_id(&rust_only.id).await.unwrap().unwrap().tags.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_backfill_tags_for_existing_prompts() {
+        let db = PromptDatabase::new_in_memory(None).await.unwrap();
+        sqlx::query(
+            r#"
+            INSERT INTO prompts (id, content, application, timestamp, starred, tags, usage_count, is_encrypted)
+            VALUES ('old', 'Stored before tags were normalized', 'Claude', '2024-01-01T00:00:00+00:00', 0, '["legacy"]', 0, 0)
+            "#,
+        )
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO pending_tag_sync (prompt_id) VALUES ('old')")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        db.backfill_tags().await.unwrap();
+
+        assert_eq!(db.list_tags().await.unwrap(), vec![TagCount { name: "legacy".to_string(), count: 1 }]);
+        let pending: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM pending_tag_sync").fetch_one(&db.pool).await.unwrap();
+        assert_eq!(pending, 0);
+    }
+
+    #[tokio::test]
+    async fn test_collection_tree_moves_and_ordering() {
+        let db = PromptDatabase::new_in_memory(None).await.unwrap();
+        let work = db.create_collection("Work", None).await.unwrap();
+        let backend = db.create_collection("Backend", Some(&work.id)).await.unwrap();
+        let personal = db.create_collection("Personal", None).await.unwrap();
+        assert!(db.create_collection("Backend", Some(&work.id)).await.is_err());
+
+        // A collection can't end up inside its own subtree
+        assert!(db.move_collection(&work.id, Some(&backend.id), None).await.is_err());
+        db.move_collection(&personal.id, None, Some(0)).await.unwrap();
+        db.move_collection(&backend.id, Some(&personal.id), None).await.unwrap();
+
+        let first = sample_prompt("First prompt in the collection");
+        let second = sample_prompt("Second prompt in the collection");
+        for prompt in [&first, &second] {
+            db.save_prompt(prompt).await.unwrap();
+            db.add_prompt_to_collection(&backend.id, &prompt.id, None).await.unwrap();
+        }
+        db.add_prompt_to_collection(&work.id, &first.id, None).await.unwrap();
+
+        let tree = db.get_collection_tree().await.unwrap();
+        assert_eq!(tree.iter().map(|node| node.collection.name.as_str()).collect::<Vec<_>>(), vec!["Personal", "Work"]);
+        assert_eq!(tree[0].children[0].collection.id, backend.id);
+        assert_eq!(tree[0].children[0].prompt_count, 2);
+
+        assert!(db.reorder_collection_prompts(&backend.id, std::slice::from_ref(&second.id)).await.is_err());
+        db.reorder_collection_prompts(&backend.id, &[second.id.clone(), first.id.clone()]).await.unwrap();
+        let ordered = db.get_collection_prompts(&backend.id).await.unwrap();
+        assert_eq!(ordered.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), vec![second.id.as_str(), first.id.as_str()]);
+
+        db.delete_collection(&personal.id).await.unwrap();
+        let tree = db.get_collection_tree().await.unwrap();
+        assert_eq!(tree.len(), 1);
+        assert!(db.get_prompt_by_id(&first.id).await.unwrap().is_some());
+        assert_eq!(tree[0].prompt_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_merge_keeps_revisions_and_template_link() {
+        let crypto = CryptoManager::with_store(Box::new(MemoryKeyStore::default())).unwrap();
+        let db = PromptDatabase::new_in_memory(Some(crypto)).await.unwrap();
+        let template = db.create_template("Greeting", None, "Write a polite greeting", &[]).await.unwrap();
+
+        let primary = sample_prompt("Write a polite greeting for a new customer");
+        let duplicate = PromptEntry { template_id: Some(template.id.clone()), ..sample_prompt("Write a polite greeting") };
+        db.save_prompt(&primary).await.unwrap();
+        db.save_prompt(&duplicate).await.unwrap();
+        db.update_prompt(&primary.id, Some("Write a polite greeting for new customers".to_string()), None, None).await.unwrap();
+        db.update_prompt(&duplicate.id, Some("Write a polite greeting, please".to_string()), None, None).await.unwrap();
+        db.update_prompt(&duplicate.id, Some("Write a polite greeting for a customer".to_string()), None, None).await.unwrap();
+
+        let merged = db.merge_prompts(&primary.id, std::slice::from_ref(&duplicate.id)).await.unwrap();
+        assert_eq!(merged.template_id.as_deref(), Some(template.id.as_str()));
+
+        let revisions = db.get_prompt_revisions(&primary.id).await.unwrap();
+        assert_eq!(revisions.iter().map(|r| r.revision).collect::<Vec<_>>(), vec![3, 2, 1]);
+        assert_eq!(revisions[1].content, duplicate.content);
+        assert_eq!(revisions[2].content, primary.content);
+    }
+
+    #[tokio::test]
+    async fn test_merge_keeps_collection_membership() {
+        let db = PromptDatabase::new_in_memory(None).await.unwrap();
+        let reading = db.create_collection("Reading", None).await.unwrap();
+        let writing = db.create_collection("Writing", None).await.unwrap();
+
+        let primary = sample_prompt("Summarize this article in three bullet points");
+        let duplicate = sample_prompt("Summarize this article in 3 bullet points");
+        let other = sample_prompt("Proofread my cover letter");
+        for prompt in [&primary, &duplicate, &other] {
+            db.save_prompt(prompt).await.unwrap();
+        }
+        db.add_prompt_to_collection(&reading.id, &duplicate.id, None).await.unwrap();
+        db.add_prompt_to_collection(&writing.id, &other.id, None).await.unwrap();
+        db.add_prompt_to_collection(&writing.id, &primary.id, None).await.unwrap();
+        db.add_prompt_to_collection(&writing.id, &duplicate.id, Some(0)).await.unwrap();
+
+        db.merge_prompts(&primary.id, std::slice::from_ref(&duplicate.id)).await.unwrap();
+
+        let ids = |prompts: Vec<PromptEntry>| prompts.into_iter().map(|prompt| prompt.id).collect::<Vec<_>>();
+        assert_eq!(ids(db.get_collection_prompts(&reading.id).await.unwrap()), vec![primary.id.clone()]);
+        // The survivor takes the duplicate's place at the top
+        assert_eq!(ids(db.get_collection_prompts(&writing.id).await.unwrap()), vec![primary.id.clone(), other.id.clone()]);
+    }
+
+    #[tokio::test]
+    async fn test_templates_render_and_link_prompts() {
+        let crypto = CryptoManager::with_store(Box::new(MemoryKeyStore::default())).unwrap();
+        let db = PromptDatabase::new_in_memory(Some(crypto)).await.unwrap();
+        let variables = vec![
+            TemplateVariable {
+                name: "language".to_string(),
+                variable_type: Default::default(),
+                default: None,
+                allowed_values: Some(vec!["Rust".to_string(), "Go".to_string()]),
+                description: None,
+            },
+            TemplateVariable {
+                name: "concern".to_string(),
+                variable_type: Default::default(),
+                default: Some("security".to_string()),
+                allowed_values: None,
+                description: None,
+            },
+        ];
+        let body = "Review this {{language}} code for {{concern}}";
+
+        assert!(db.create_template("Broken", None, "{{undeclared}}", &variables).await.is_err());
+        let template = db.create_template("Code review", Some("Per-language review"), body, &variables).await.unwrap();
+
+        let values = HashMap::from([("language".to_string(), "Rust".to_string())]);
+        let preview = db.render_template(&template.id, &values, false).await.unwrap();
+        assert_eq!(preview.content, "Review this Rust code for security");
+        assert!(preview.prompt_id.is_none());
+
+        let saved = db.render_template(&template.id, &values, true).await.unwrap();
+        let again = db.render_template(&template.id, &values, true).await.unwrap();
+        assert_eq!(saved.prompt_id, again.prompt_id);
+        assert_eq!(db.get_template_prompts(&template.id).await.unwrap().len(), 1);
+
+        db.rotate_encryption_key().await.unwrap();
+        let listed = db.list_templates().await.unwrap();
+        assert_eq!(listed[0].body, body);
+        assert_eq!(listed[0].description.as_deref(), Some("Per-language review"));
+
+        db.delete_template(&template.id).await.unwrap();
+        let prompt = db.get_prompt_by_id(saved.prompt_id.as_deref().unwrap()).await.unwrap().unwrap();
+        assert!(prompt.template_id.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_responses_are_encrypted_and_follow_merges() {
+        let crypto = CryptoManager::with_store(Box::new(MemoryKeyStore::default())).unwrap();
+        let db = PromptDatabase::new_in_memory(Some(crypto)).await.unwrap();
+        let primary = sample_prompt("Explain Rust lifetimes with a short example");
+        let duplicate = sample_prompt("Explain Rust lifetimes with a simple example");
+        db.save_prompt(&primary).await.unwrap();
+        db.save_prompt(&duplicate).await.unwrap();
+
+        for (prompt_id, text) in [(&primary.id, "Lifetimes name scopes"), (&duplicate.id, "A borrow must not outlive")] {
+            let response = OllamaResponse {
+                response: text.to_string(),
+                done: true,
+                model: "llama3".to_string(),
+                created_at: Utc::now().to_rfc3339(),
+                total_duration: Some(1_500_000_000),
+                prompt_eval_count: Some(12),
+                eval_count: Some(40),
+            };
+            db.save_response(prompt_id, &response, 10).await.unwrap();
+        }
+        let stored: Vec<String> = sqlx::query_scalar("SELECT response FROM responses").fetch_all(&db.pool).await.unwrap();
+        assert!(stored.iter().all(|text| !text.contains("borrow") && !text.contains("Lifetimes")));
+
+        db.merge_prompts(&primary.id, std::slice::from_ref(&duplicate.id)).await.unwrap();
+        db.rotate_encryption_key().await.unwrap();
+
+        let merged = db.get_prompt_with_responses(&primary.id).await.unwrap().unwrap();
+        assert_eq!(merged.responses.len(), 2);
+        assert!(merged.responses.iter().all(|r| r.duration_ms == Some(1500) && r.completion_tokens == Some(40)));
+
+        db.delete_prompt(&primary.id).await.unwrap();
+        assert!(db.get_prompt_responses(&primary.id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sessions_group_by_gap_and_context() {
+        let db = PromptDatabase::new_in_memory(None).await.unwrap();
+        let start = Utc::now() - chrono::Duration::hours(3);
+        let mut ids = Vec::new();
+
+        for (minutes, application, context) in [
+            (0, "Claude", None),
+            (5, "Claude", None),
+            (6, "ChatGPT", None),
+            (60, "Claude", None),
+            (70, "Claude", Some("Thread A")),
+            (75, "Claude", Some("Thread B")),
+            (80, "Claude", Some("Thread A")),
+        ] {
+            let mut prompt = sample_prompt(&format!("Prompt written at minute {}", minutes));
+            prompt.application = application.to_string();
+            prompt.timestamp = start + chrono::Duration::minutes(minutes);
+            db.save_prompt_with_context(&prompt, context).await.unwrap();
+            ids.push(prompt.id);
+        }
+
+        let session_of = |index: usize| {
+            let db = &db;
+            let id = ids[index].clone();
+            async move { db.get_prompt_by_id(&id).await.unwrap().unwrap().session_id.unwrap() }
+        };
+        assert_eq!(session_of(0).await, session_of(1).await);
+        assert_ne!(session_of(1).await, session_of(2).await);
+        assert_ne!(session_of(1).await, session_of(3).await);
+        assert_eq!(session_of(3).await, session_of(4).await);
+        assert_eq!(session_of(4).await, session_of(6).await);
+        assert_ne!(session_of(4).await, session_of(5).await);
+
+        let thread_a = db.get_session(&session_of(4).await).await.unwrap().unwrap();
+        assert_eq!(thread_a.context.as_deref(), Some("Thread A"));
+        assert_eq!(thread_a.prompt_count, 3);
+        assert_eq!(db.list_sessions(Some("Claude"), None, None).await.unwrap().len(), 3);
+
+        let split = db.split_session(&thread_a.id, &ids[4]).await.unwrap();
+        assert_eq!(split.prompt_count, 2);
+        assert!(db.split_session(&split.id, &ids[4]).await.is_err());
+        let first_session = session_of(0).await;
+        let merged = db.merge_sessions(&first_session, &[split.id.clone(), thread_a.id.clone()]).await.unwrap();
+        assert_eq!(merged.prompt_count, 5);
+        let ordered: Vec<String> = db.get_session_prompts(&merged.id).await.unwrap().into_iter().map(|p| p.id).collect();
+        assert_eq!(ordered, vec![ids[0].clone(), ids[1].clone(), ids[3].clone(), ids[4].clone(), ids[6].clone()]);
+
+        db.delete_prompt(&ids[5]).await.unwrap();
+        assert_eq!(db.list_sessions(None, None, None).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_repeats_and_merges_keep_sessions_current() {
+        let db = PromptDatabase::new_in_memory(None).await.unwrap();
+        let start = Utc::now() - chrono::Duration::hours(3);
+        let at = |minutes: i64, content: &str| PromptEntry { timestamp: start + chrono::Duration::minutes(minutes), ..sample_prompt(content) };
+        let repeated = at(0, "Explain Rust lifetimes");
+        let follow_up = at(5, "Show an example with structs");
+        let near_copy = at(10, "Show an example with structs please");
+        for prompt in [&repeated, &follow_up, &near_copy] {
+            db.save_prompt_with_context(prompt, Some("Thread A")).await.unwrap();
+        }
+        let thread_a = db.get_prompt_by_id(&follow_up.id).await.unwrap().unwrap().session_id.unwrap();
+
+        // Sent again in another conversation, the prompt moves there
+        let again = at(90, "Explain Rust lifetimes");
+        assert_eq!(db.save_prompt_with_context(&again, Some("Thread B")).await.unwrap(), repeated.id);
+        let session_id = db.get_prompt_by_id(&repeated.id).await.unwrap().unwrap().session_id.unwrap();
+        let thread_b = db.get_session(&session_id).await.unwrap().unwrap();
+        assert_eq!(thread_b.context.as_deref(), Some("Thread B"));
+        assert_eq!(thread_b.started_at, again.timestamp);
+        let session = db.get_session(&thread_a).await.unwrap().unwrap();
+        assert_eq!((session.prompt_count, session.started_at), (2, follow_up.timestamp));
+
+        // Merging the near copy away shrinks the session it leaves
+        db.merge_prompts(&follow_up.id, std::slice::from_ref(&near_copy.id)).await.unwrap();
+        let session = db.get_session(&thread_a).await.unwrap().unwrap();
+        assert_eq!((session.prompt_count, session.ended_at), (1, follow_up.timestamp));
+    }
+
+    #[tokio::test]
+    async fn test_cursor_pages_cover_every_prompt_once() {
+        let db = PromptDatabase::new_in_memory(None).await.unwrap();
+        let timestamp = Utc::now();
+        for i in 0..7 {
+            let mut prompt = sample_prompt(&format!("Paged prompt number {}", i));
+            // Shared timestamps make the id the tie-breaker
+            prompt.timestamp = timestamp - chrono::Duration::minutes(i / 3);
+            db.save_prompt(&prompt).await.unwrap();
+        }
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = db.get_prompts_page(None, PromptSort::Timestamp, 3, cursor.as_deref()).await.unwrap();
+            assert_eq!(page.total_matching, 7);
+            seen.extend(page.prompts.into_iter().map(|p| (p.timestamp, p.id)));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(seen.len(), 7);
+        assert!(seen.windows(2).all(|pair| pair[0] > pair[1]));
+        assert!(db.get_prompts_page(None, PromptSort::Timestamp, 3, Some("not a cursor")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_search_pages_merge_plaintext_and_encrypted() {
+        let pool = PromptDatabase::memory_pool().await.unwrap();
+        let plain = PromptDatabase::open(pool.clone(), None, false).await.unwrap();
+        for i in 0..3 {
+            plain.save_prompt(&sample_prompt(&format!("Plain lifetimes question {}", i))).await.unwrap();
+        }
+        let crypto = CryptoManager::with_store(Box::new(MemoryKeyStore::default())).unwrap();
+        let db = PromptDatabase::open(pool, Some(crypto), false).await.unwrap();
+        for i in 0..3 {
+            db.save_prompt(&sample_prompt(&format!("Secret lifetimes question {}", i))).await.unwrap();
+        }
+
+        let mut ids = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = db.search_prompts_page("lifetimes", 4, cursor.as_deref()).await.unwrap();
+            assert_eq!(page.total_matching, 6);
+            ids.extend(page.prompts.into_iter().map(|p| p.id));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_query_builder_applies_every_filter_field() {
+        let db = PromptDatabase::new_in_memory(None).await.unwrap();
+        let now = Utc::now();
+        for (content, application, starred, hours_ago, tags) in [
+            ("Explain Rust lifetimes", "Claude", true, 1, vec!["rust"]),
+            ("Explain Go channels", "Claude", false, 2, vec!["go"]),
+            ("Write a Rust macro for lifetimes", "ChatGPT", true, 30, vec!["rust", "macros"]),
+            ("Summarize this article", "ChatGPT", false, 3, vec![]),
+        ] {
+            let mut prompt = sample_prompt(content);
+            prompt.application = application.to_string();
+            prompt.starred = starred;
+            prompt.timestamp = now - chrono::Duration::hours(hours_ago);
+            prompt.tags = tags.into_iter().map(str::to_string).collect();
+            db.save_prompt(&prompt).await.unwrap();
+        }
+
+        let contents = |filter: PromptFilter, sort: PromptSort| {
+            let db = &db;
+            async move {
+                db.get_prompts(Some(filter), sort, None, None).await.unwrap()
+                    .into_iter()
+                    .map(|p| p.content)
+                    .collect::<Vec<_>>()
+            }
+        };
+        let empty = || tag_filter(&[], TagMatch::Any);
+
+        let starred = PromptFilter { starred: Some(true), ..empty() };
+        assert_eq!(contents(starred, PromptSort::Timestamp).await, vec!["Explain Rust lifetimes", "Write a Rust macro for lifetimes"]);
+        let unstarred = PromptFilter { starred: Some(false), application: Some("ChatGPT".to_string()), ..empty() };
+        assert_eq!(contents(unstarred, PromptSort::Timestamp).await, vec!["Summarize this article"]);
+
+        let recent = PromptFilter { start_date: Some(now - chrono::Duration::hours(24)), end_date: Some(now), ..empty() };
+        assert_eq!(contents(recent, PromptSort::Timestamp).await.len(), 3);
+
+        let search = PromptFilter { search_text: Some("lifetimes".to_string()), tags: Some(vec!["rust".to_string()]), ..empty() };
+        assert_eq!(contents(search, PromptSort::Timestamp).await, vec!["Explain Rust lifetimes", "Write a Rust macro for lifetimes"]);
+        let search = PromptFilter { search_text: Some("explain".to_string()), starred: Some(false), ..empty() };
+        assert_eq!(contents(search, PromptSort::Relevance).await, vec!["Explain Go channels"]);
+    }
+
+    #[tokio::test]
+    async fn test_sort_options() {
+        let db = PromptDatabase::new_in_memory(None).await.unwrap();
+        let mut ids = Vec::new();
+        for (i, content) in ["Rust lifetimes", "Rust lifetimes and lifetimes again in Rust", "Python decorators"].iter().enumerate() {
+            let mut prompt = sample_prompt(content);
+            prompt.timestamp = Utc::now() - chrono::Duration::minutes(i as i64);
+            db.save_prompt(&prompt).await.unwrap();
+            ids.push(prompt.id);
+        }
+        PromptDatabase::increment_usage_count(&mut db.pool.acquire().await.unwrap(), &ids[2]).await.unwrap();
+        sqlx::query("UPDATE prompts SET updated_at = '2000-01-01 00:00:00' WHERE id = ?")
+            .bind(&ids[0])
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let order = |sort: PromptSort, search: Option<&str>| {
+            let db = &db;
+            let filter = PromptFilter { search_text: search.map(str::to_string), ..tag_filter(&[], TagMatch::Any) };
+            async move {
+                db.get_prompts(Some(filter), sort, None, None).await.unwrap()
+                    .into_iter()
+                    .map(|p| p.id)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(order(PromptSort::Timestamp, None).await, ids.clone());
+        assert_eq!(order(PromptSort::UsageCount, None).await[0], ids[2]);
+        assert_eq!(order(PromptSort::UpdatedAt, None).await.last(), Some(&ids[0]));
+        assert_eq!(order(PromptSort::Relevance, Some("lifetimes")).await, vec![ids[1].clone(), ids[0].clone()]);
+        // Without search text relevance falls back to newest first
+        assert_eq!(order(PromptSort::Relevance, None).await, ids);
+
+        let page = db.get_prompts_page(None, PromptSort::UsageCount, 2, None).await.unwrap();
+        let rest = db.get_prompts_page(None, PromptSort::UsageCount, 2, page.next_cursor.as_deref()).await.unwrap();
+        assert_eq!(page.prompts.len() + rest.prompts.len(), 3);
+        assert!(rest.next_cursor.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_search_query_language() {
+        let db = PromptDatabase::new_in_memory(None).await.unwrap();
+        for (content, application, starred, tags) in [
+            ("Refactor the well-known parser module", "Claude", true, vec!["refactor"]),
+            ("Refactor the parser module, draft version", "Claude", true, vec!["refactor"]),
+            ("Refactor the parser module for speed", "ChatGPT", true, vec!["refactor"]),
+            ("Parser module tests", "Claude", false, vec!["testing"]),
+        ] {
+            let mut prompt = sample_prompt(content);
+            prompt.application = application.to_string();
+            prompt.starred = starred;
+            prompt.tags = tags.into_iter().map(str::to_string).collect();
+            db.save_prompt(&prompt).await.unwrap();
+        }
+
+        let contents = |query: &str| {
+            let db = &db;
+            let query = query.to_string();
+            async move {
+                db.search_prompts(&query, None).await.unwrap().into_iter().map(|p| p.content).collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            contents(r#"app:claude tag:refactor starred:yes "parser module" -draft"#).await,
+            vec!["Refactor the well-known parser module"]
+        );
+        assert_eq!(contents("well-known").await.len(), 1);
+        assert_eq!(contents("-refactor").await, vec!["Parser module tests"]);
+        assert_eq!(contents("before:2000-01-01 parser").await.len(), 0);
+        assert!(db.search_prompts(r#"parser "unclosed"#, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_search_hits_have_snippets_highlights_and_offsets() {
+        let pool = PromptDatabase::memory_pool().await.unwrap();
+        let plain = PromptDatabase::open(pool.clone(), None, false).await.unwrap();
+        plain.save_prompt(&sample_prompt("Explain Rust lifetimes with an example")).await.unwrap();
+        let crypto = CryptoManager::with_store(Box::new(MemoryKeyStore::default())).unwrap();
+        let db = PromptDatabase::open(pool, Some(crypto), false).await.unwrap();
+        db.save_prompt(&sample_prompt("Secret notes on lifetimes and borrowing")).await.unwrap();
+
+        let options = HighlightOptions { open: "<b>".to_string(), close: "</b>".to_string(), ..Default::default() };
+        let page = db.search_hits_page("Lifetimes", &options, 10, None).await.unwrap();
+        assert_eq!(page.total_matching, 2);
+
+        for hit in &page.hits {
+            assert!(hit.score.unwrap() < 0.0);
+            assert_eq!(hit.matches.len(), 1);
+            let matched: String = hit.prompt.content.chars()
+                .skip(hit.matches[0].start)
+                .take(hit.matches[0].end - hit.matches[0].start)
+                .collect();
+            assert_eq!(matched, "lifetimes");
+        }
+        let plain_hit = page.hits.iter().find(|hit| !hit.prompt.is_encrypted).unwrap();
+        assert_eq!(plain_hit.highlighted, "Explain Rust <b>lifetimes</b> with an example");
+        let secret_hit = page.hits.iter().find(|hit| hit.prompt.is_encrypted).unwrap();
+        assert_eq!(secret_hit.snippet, "Secret notes on <b>lifetimes</b> and borrowing");
+
+        let short = HighlightOptions { snippet_tokens: 3, ..Default::default() };
+        let page = db.search_hits_page("example", &short, 10, None).await.unwrap();
+        assert_eq!(page.hits[0].snippet, "…with an [example]");
+
+        // Filter-only searches have nothing to highlight
+        let page = db.search_hits_page("starred:no", &short, 10, None).await.unwrap();
+        assert_eq!(page.hits.len(), 2);
+        assert!(page.hits.iter().all(|hit| hit.score.is_none() && hit.matches.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_fuzzy_search_tolerates_typos() {
+        let pool = PromptDatabase::memory_pool().await.unwrap();
+        let plain = PromptDatabase::open(pool.clone(), None, false).await.unwrap();
+        plain.save_prompt(&sample_prompt("Explain Rust lifetimes with an example")).await.unwrap();
+        plain.save_prompt(&sample_prompt("Refactor the parser module")).await.unwrap();
+        let crypto = CryptoManager::with_store(Box::new(MemoryKeyStore::default())).unwrap();
+        let db = PromptDatabase::open(pool, Some(crypto), false).await.unwrap();
+        db.save_prompt(&sample_prompt("Secret notes on lifetime elision")).await.unwrap();
+
+        let contents = |matches: Vec<FuzzyMatch>| matches.into_iter().map(|m| m.prompt.content).collect::<Vec<_>>();
+
+        assert!(db.search_prompts("lifetims", None).await.unwrap().is_empty());
+        let results = db.fuzzy_search_prompts("lifetims", fuzzy::DEFAULT_FUZZY_THRESHOLD, None).await.unwrap();
+        assert!(!results.truncated);
+        assert_eq!(
+            contents(results.matches),
+            vec!["Secret notes on lifetime elision", "Explain Rust lifetimes with an example"]
+        );
+
+        // Exact matches score 1 and carry their bm25 score
+        let matches = db.fuzzy_search_prompts("lifetimes", fuzzy::DEFAULT_FUZZY_THRESHOLD, None).await.unwrap().matches;
+        assert_eq!(matches[0].similarity, 1.0);
+        assert!(matches[0].score.is_some());
+        assert!(matches[1].similarity < 1.0 && matches[1].score.is_none());
+
+        assert_eq!(
+            contents(db.fuzzy_search_prompts("refac parsr -module", 0.4, None).await.unwrap().matches),
+            Vec::<String>::new()
+        );
+        assert_eq!(contents(db.fuzzy_search_prompts("refac parsr", 0.4, None).await.unwrap().matches).len(), 1);
+        assert!(db.fuzzy_search_prompts("parser", 0.0, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fuzzy_search_reports_truncated_candidates() {
+        let db = PromptDatabase::new_in_memory(None).await.unwrap();
+        for (content, timestamp) in [
+            ("Explain Rust lifetimes", "2024-01-01T00:00:00+00:00"),
+            ("Write a haiku", "2024-02-01T00:00:00+00:00"),
+            ("Summarize the release notes", "2024-03-01T00:00:00+00:00"),
+        ] {
+            let prompt = PromptEntry { timestamp: DateTime::parse_from_rfc3339(timestamp).unwrap().with_timezone(&Utc), ..sample_prompt(content) };
+            db.save_prompt(&prompt).await.unwrap();
+        }
+
+        let results = db.fuzzy_search_within("lifetims", fuzzy::DEFAULT_FUZZY_THRESHOLD, None, 2).await.unwrap();
+        assert!(results.truncated);
+        assert!(results.matches.is_empty());
+
+        let results = db.fuzzy_search_within("lifetims", fuzzy::DEFAULT_FUZZY_THRESHOLD, None, 3).await.unwrap();
+        assert!(!results.truncated);
+        assert_eq!(results.matches.len(), 1);
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+// Same defaults as SQLite's FTS5 bm25() so encrypted and plaintext results rank alike
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+
+/// Splits text into lowercase alphanumeric tokens, approximating FTS5's unicode61 tokenizer
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_lowercase())
+        .collect()
+}
+
+/// Character ranges `[start, end)` of the tokens `tokenize` would produce
+pub fn token_spans(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = None;
+
+    for (index, c) in text.chars().enumerate() {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(index),
+            (false, Some(token_start)) => {
+                spans.push((token_start, index));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(token_start) = start {
+        spans.push((token_start, text.chars().count()));
+    }
+
+    spans
+}
+
+/// Wraps each matched range in `open` and `close`. Ranges must be sorted and not overlap.
+pub fn highlight(text: &str, matches: &[(usize, usize)], open: &str, close: &str) -> String {
+    mark(&text.chars().collect::<Vec<_>>(), 0, text.chars().count(), matches, open, close)
+}
+
+/// Up to `max_tokens` tokens around the densest cluster of matches, highlighted and
+/// with `ellipsis` where text was cut, like FTS5's snippet()
+pub fn snippet(text: &str, matches: &[(usize, usize)], open: &str, close: &str, ellipsis: &str, max_tokens: usize) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let spans = token_spans(text);
+    let max_tokens = max_tokens.max(1);
+    if spans.len() <= max_tokens {
+        return highlight(text, matches, open, close);
+    }
+
+    // First window with the most matched tokens
+    let mut best = (0, 0);
+    for first in 0..=spans.len() - max_tokens {
+        let window = &spans[first..first + max_tokens];
+        let count = window.iter().filter(|span| matches.contains(span)).count();
+        if count > best.1 {
+            best = (first, count);
+        }
+    }
+
+    let window = &spans[best.0..best.0 + max_tokens];
+    let (start, end) = (window[0].0, window[max_tokens - 1].1);
+    let mut snippet = String::new();
+    if best.0 > 0 {
+        snippet.push_str(ellipsis);
+    }
+    snippet.push_str(&mark(&chars, start, end, matches, open, close));
+    if best.0 + max_tokens < spans.len() {
+        snippet.push_str(ellipsis);
+    }
+    snippet
+}
+
+fn mark(chars: &[char], start: usize, end: usize, matches: &[(usize, usize)], open: &str, close: &str) -> String {
+    let mut marked = String::new();
+    let mut position = start;
+
+    for &(match_start, match_end) in matches.iter().filter(|(s, e)| *s >= start && *e <= end) {
+        marked.extend(&chars[position..match_start]);
+        marked.push_str(open);
+        marked.extend(&chars[match_start..match_end]);
+        marked.push_str(close);
+        position = match_end;
+    }
+    marked.extend(&chars[position..end]);
+    marked
+}
+
+/// Counts how often each token appears
+pub fn term_frequencies(tokens: &[String]) -> HashMap<String, i64> {
+    let mut frequencies = HashMap::new();
+    for token in tokens {
+        *frequencies.entry(token.clone()).or_insert(0) += 1;
+    }
+    frequencies
+}
+
+/// Corpus-wide numbers needed to score a document
+#[derive(Debug, Clone, Copy)]
+pub struct CorpusStats {
+    pub document_count: i64,
+    pub average_length: f64,
+}
+
+/// Scores a single term match the way FTS5's bm25() does.
+///
+/// Like FTS5, the result is negative so that "better" matches sort first in ascending order.
+pub fn bm25_term(term_frequency: i64, document_length: i64, document_frequency: i64, stats: CorpusStats) -> f64 {
+    let n = stats.document_count as f64;
+    let df = document_frequency as f64;
+
+    let mut idf = ((n - df + 0.5) / (df + 0.5)).ln();
+    if idf <= 0.0 {
+        idf = 1e-6;
+    }
+
+    let tf = term_frequency as f64;
+    let average_length = if stats.average_length > 0.0 { stats.average_length } else { 1.0 };
+    let length_norm = 1.0 - BM25_B + BM25_B * (document_length as f64 / average_length);
+
+    -(idf * (tf * (BM25_K1 + 1.0)) / (tf + BM25_K1 * length_norm))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("Refactor the user-service, please! (v2)"),
+            vec!["refactor", "the", "user", "service", "please", "v2"]
+        );
+        assert!(tokenize("  --  ").is_empty());
+    }
+
+    #[test]
+    fn test_snippet_and_highlight() {
+        let text = "Please refactor the parser. The parser is slow, so refactor it";
+        let spans = token_spans(text);
+        assert_eq!(spans[0], (0, 6));
+        assert_eq!(spans.len(), 11);
+
+        let matches: Vec<(usize, usize)> = spans.iter()
+            .copied()
+            .filter(|&(start, end)| text.chars().skip(start).take(end - start).collect::<String>() == "parser")
+            .collect();
+        assert_eq!(
+            highlight(text, &matches, "[", "]"),
+            "Please refactor the [parser]. The [parser] is slow, so refactor it"
+        );
+        assert_eq!(snippet(text, &matches, "[", "]", "…", 5), "…refactor the [parser]. The [parser]…");
+        assert_eq!(snippet("short", &[], "[", "]", "…", 5), "short");
+    }
+
+    #[test]
+    fn test_bm25_prefers_more_frequent_and_rarer_terms() {
+        let stats = CorpusStats { document_count: 100, average_length: 20.0 };
+
+        let once = bm25_term(1, 20, 5, stats);
+        let twice = bm25_term(2, 20, 5, stats);
+        let common = bm25_term(1, 20, 60, stats);
+
+        assert!(twice < once);
+        assert!(once < common);
+        assert!(once < 0.0);
+    }
+}
@@ -0,0 +1,194 @@
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::models::{PromptHistError, Result};
+use crate::search_index;
+
+/// A parsed search like `app:claude tag:refactor starred:yes before:2026-01-01 "exact phrase" -draft`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SearchQuery {
+    /// Words and phrases that must all appear
+    pub terms: Vec<String>,
+    /// Words and phrases that must not appear
+    pub excluded: Vec<String>,
+    pub application: Option<String>,
+    pub tags: Vec<String>,
+    pub starred: Option<bool>,
+    pub before: Option<DateTime<Utc>>,
+    pub after: Option<DateTime<Utc>>,
+}
+
+const FILTERS: &str = "app:, tag:, starred:, before: or after:";
+
+impl SearchQuery {
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut query = SearchQuery::default();
+        let chars: Vec<char> = input.chars().collect();
+        let mut position = 0;
+
+        while position < chars.len() {
+            if chars[position].is_whitespace() {
+                position += 1;
+                continue;
+            }
+
+            let start = position;
+            let negated = chars[position] == '-' && chars.get(position + 1).is_some_and(|c| !c.is_whitespace());
+            if negated {
+                position += 1;
+            }
+
+            // `key:` prefix; anything else with a colon, like 10:30, is plain text
+            let key_end = chars[position..].iter().position(|c| !c.is_ascii_alphabetic()).map(|offset| position + offset);
+            let key = match key_end {
+                Some(end) if end > position && chars[end] == ':' => {
+                    let key: String = chars[position..end].iter().collect();
+                    position = end + 1;
+                    Some(key.to_lowercase())
+                }
+                _ => None,
+            };
+
+            let (value, quoted) = read_value(&chars, &mut position)?;
+
+            match key {
+                None => {
+                    if search_index::tokenize(&value).is_empty() {
+                        continue;
+                    }
+                    if negated {
+                        query.excluded.push(value);
+                    } else {
+                        query.terms.push(value);
+                    }
+                }
+                Some(key) => {
+                    if negated {
+                        return Err(syntax_error(format!("Filters can't be negated: -{}:", key)));
+                    }
+                    if value.is_empty() && !quoted {
+                        return Err(syntax_error(format!("Missing value after {}: at column {}", key, start + 1)));
+                    }
+                    query.apply_filter(&key, value)?;
+                }
+            }
+        }
+
+        Ok(query)
+    }
+
+    fn apply_filter(&mut self, key: &str, value: String) -> Result<()> {
+        match key {
+            "app" => self.application = Some(value),
+            "tag" => self.tags.push(value),
+            "starred" => {
+                self.starred = Some(match value.to_lowercase().as_str() {
+                    "yes" | "true" => true,
+                    "no" | "false" => false,
+                    _ => return Err(syntax_error(format!("starred: takes yes or no, not \"{}\"", value))),
+                })
+            }
+            "before" => self.before = Some(parse_date("before", &value)?),
+            "after" => self.after = Some(parse_date("after", &value)?),
+            _ => {
+                return Err(syntax_error(format!(
+                    "Unknown filter \"{}:\"; use {}, or put the text in quotes to search for it",
+                    key, FILTERS
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// FTS5 expression requiring every term, or None when there are none
+    pub fn fts_expression(&self) -> Option<String> {
+        if self.terms.is_empty() {
+            return None;
+        }
+        Some(self.terms.iter().map(|term| fts_phrase(term)).collect::<Vec<_>>().join(" AND "))
+    }
+
+    /// FTS5 expression matching any excluded term, or None when there are none
+    pub fn exclusion_expression(&self) -> Option<String> {
+        if self.excluded.is_empty() {
+            return None;
+        }
+        Some(self.excluded.iter().map(|term| fts_phrase(term)).collect::<Vec<_>>().join(" OR "))
+    }
+}
+
+/// Reads a bare word or a quoted string starting at `position`
+fn read_value(chars: &[char], position: &mut usize) -> Result<(String, bool)> {
+    if chars.get(*position) == Some(&'"') {
+        let open = *position;
+        let close = chars[open + 1..].iter().position(|c| *c == '"')
+            .ok_or_else(|| syntax_error(format!("Unclosed quote starting at column {}", open + 1)))?;
+        *position = open + 1 + close + 1;
+        return Ok((chars[open + 1..open + 1 + close].iter().collect(), true));
+    }
+
+    let start = *position;
+    while *position < chars.len() && !chars[*position].is_whitespace() {
+        *position += 1;
+    }
+    Ok((chars[start..*position].iter().collect(), false))
+}
+
+fn parse_date(key: &str, value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|date| date.with_timezone(&Utc))
+        .map_err(|_| syntax_error(format!("{}: needs a date like 2026-01-01, not \"{}\"", key, value)))
+}
+
+/// Quotes text as an FTS5 phrase so operators and punctuation in it are not parsed
+fn fts_phrase(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+fn syntax_error(message: String) -> PromptHistError {
+    PromptHistError::InvalidInput(format!("Search syntax error: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filters_phrases_and_exclusions() {
+        let query = SearchQuery::parse(r#"app:claude tag:refactor Tag:"code review" starred:yes before:2026-01-01 "exact phrase" well-known -draft -"old idea" 10:30"#).unwrap();
+
+        assert_eq!(query.application.as_deref(), Some("claude"));
+        assert_eq!(query.tags, vec!["refactor", "code review"]);
+        assert_eq!(query.starred, Some(true));
+        assert_eq!(query.before.unwrap().to_rfc3339(), "2026-01-01T00:00:00+00:00");
+        assert_eq!(query.terms, vec!["exact phrase", "well-known", "10:30"]);
+        assert_eq!(query.excluded, vec!["draft", "old idea"]);
+        assert_eq!(
+            query.fts_expression().unwrap(),
+            r#""exact phrase" AND "well-known" AND "10:30""#
+        );
+        assert_eq!(query.exclusion_expression().unwrap(), r#""draft" OR "old idea""#);
+    }
+
+    #[test]
+    fn test_punctuation_is_quoted_or_skipped() {
+        let query = SearchQuery::parse(r#"c++ - -- NEAR(a b) "x AND y""#).unwrap();
+        assert_eq!(query.terms, vec!["c++", "NEAR(a", "b)", "x AND y"]);
+        assert_eq!(fts_phrase(r#"a "b""#), "\"a \"\"b\"\"\"");
+        assert_eq!(SearchQuery::parse("   ").unwrap(), SearchQuery::default());
+    }
+
+    #[test]
+    fn test_helpful_errors() {
+        let error = |input: &str| SearchQuery::parse(input).unwrap_err().to_string();
+
+        assert!(error(r#"fix "unclosed"#).contains("column 5"));
+        assert!(error("http://example.com").contains("Unknown filter \"http:\""));
+        assert!(error("starred:maybe").contains("yes or no"));
+        assert!(error("before:tomorrow").contains("2026-01-01"));
+        assert!(error("app:").contains("Missing value"));
+        assert!(error("-tag:old").contains("negated"));
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::search_index;
+
+/// Jaccard similarity above which two prompts are treated as near-duplicates
+pub const DEFAULT_SIMILARITY_THRESHOLD: f64 = 0.7;
+
+// Character shingles survive single-word edits far better than word shingles on short prompts
+const SHINGLE_SIZE: usize = 4;
+// 32 bands of 4 rows: pairs around 0.5 similarity and up almost always share a bucket
+const SIGNATURE_BANDS: usize = 32;
+const SIGNATURE_ROWS: usize = 4;
+
+/// Hashed character shingles of the text, ignoring case, punctuation and spacing
+pub fn shingles(text: &str) -> HashSet<u64> {
+    let normalized: Vec<char> = search_index::tokenize(text).join(" ").chars().collect();
+
+    if normalized.is_empty() {
+        return HashSet::new();
+    }
+    if normalized.len() <= SHINGLE_SIZE {
+        return HashSet::from([fnv1a(&normalized)]);
+    }
+
+    normalized.windows(SHINGLE_SIZE).map(fnv1a).collect()
+}
+
+/// Exact Jaccard similarity of two shingle sets
+pub fn jaccard(a: &HashSet<u64>, b: &HashSet<u64>) -> f64 {
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    let intersection = a.intersection(b).count();
+    intersection as f64 / (a.len() + b.len() - intersection) as f64
+}
+
+/// MinHash signature; matching positions estimate the Jaccard similarity
+pub fn signature(shingles: &HashSet<u64>) -> Vec<u64> {
+    (0..SIGNATURE_BANDS * SIGNATURE_ROWS)
+        .map(|seed| {
+            shingles.iter()
+                .map(|shingle| splitmix64(shingle ^ splitmix64(seed as u64)))
+                .min()
+                .unwrap_or(u64::MAX)
+        })
+        .collect()
+}
+
+/// Groups documents whose shingle sets are at least `threshold` similar.
+///
+/// MinHash banding picks candidate pairs, which are then checked exactly. Groups are
+/// transitive, so A~B and B~C put all three together. Only groups of two or more are returned.
+pub fn clusters(documents: &[HashSet<u64>], threshold: f64) -> Vec<Vec<usize>> {
+    let mut buckets: HashMap<(usize, &[u64]), Vec<usize>> = HashMap::new();
+    let signatures: Vec<Vec<u64>> = documents.iter().map(signature).collect();
+
+    for (index, signature) in signatures.iter().enumerate() {
+        if documents[index].is_empty() {
+            continue;
+        }
+        for (band, rows) in signature.chunks(SIGNATURE_ROWS).enumerate() {
+            buckets.entry((band, rows)).or_default().push(index);
+        }
+    }
+
+    let mut parents: Vec<usize> = (0..documents.len()).collect();
+    let mut checked = HashSet::new();
+    for members in buckets.values() {
+        for (position, &a) in members.iter().enumerate() {
+            for &b in &members[position + 1..] {
+                if checked.insert((a, b)) && jaccard(&documents[a], &documents[b]) >= threshold {
+                    let (root_a, root_b) = (find_root(&mut parents, a), find_root(&mut parents, b));
+                    parents[root_a.max(root_b)] = root_a.min(root_b);
+                }
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for index in 0..documents.len() {
+        let root = find_root(&mut parents, index);
+        groups.entry(root).or_default().push(index);
+    }
+
+    let mut groups: Vec<Vec<usize>> = groups.into_values().filter(|group| group.len() > 1).collect();
+    groups.sort_by_key(|group| group[0]);
+    groups
+}
+
+fn find_root(parents: &mut [usize], mut index: usize) -> usize {
+    while parents[index] != index {
+        parents[index] = parents[parents[index]];
+        index = parents[index];
+    }
+    index
+}
+
+fn fnv1a(chars: &[char]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for c in chars {
+        for byte in (*c as u32).to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shingles_ignore_case_and_spacing() {
+        assert_eq!(shingles("Explain  Rust lifetimes!"), shingles("explain rust lifetimes"));
+        assert!(shingles("   ").is_empty());
+    }
+
+    #[test]
+    fn test_clusters_group_prompts_differing_by_a_word() {
+        let documents: Vec<HashSet<u64>> = [
+            "Explain Rust lifetimes with a short example for beginners",
+            "Write a SQL query that lists the ten most recent orders",
+            "Explain Rust lifetimes with a simple example for beginners",
+            "Explain Rust lifetimes with a short example for beginner",
+        ]
+        .iter()
+        .map(|text| shingles(text))
+        .collect();
+
+        assert!(jaccard(&documents[0], &documents[2]) >= DEFAULT_SIMILARITY_THRESHOLD);
+        assert!(jaccard(&documents[0], &documents[1]) < 0.2);
+        assert_eq!(clusters(&documents, DEFAULT_SIMILARITY_THRESHOLD), vec![vec![0, 2, 3]]);
+    }
+}
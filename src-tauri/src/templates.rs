@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use crate::models::{PromptHistError, Result, TemplateVariable, VariableType};
+
+/// Piece of a template body
+#[derive(Debug, PartialEq)]
+enum Segment<'a> {
+    Text(&'a str),
+    Variable(&'a str),
+}
+
+/// Splits a body into text and `{{ name }}` placeholders. `\{{` is a literal `{{`.
+fn segments(body: &str) -> Result<Vec<Segment<'_>>> {
+    let mut segments = Vec::new();
+    let mut rest = body;
+
+    while let Some(start) = rest.find("{{") {
+        if rest[..start].ends_with('\\') {
+            segments.push(Segment::Text(&rest[..start - 1]));
+            segments.push(Segment::Text("{{"));
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        segments.push(Segment::Text(&rest[..start]));
+        let end = rest[start + 2..].find("}}").ok_or_else(|| {
+            PromptHistError::InvalidInput(format!("Unclosed placeholder at \"{}\"", preview(&rest[start..])))
+        })?;
+
+        let name = rest[start + 2..start + 2 + end].trim();
+        if !is_identifier(name) {
+            return Err(PromptHistError::InvalidInput(format!("Invalid placeholder name: \"{}\"", name)));
+        }
+        segments.push(Segment::Variable(name));
+        rest = &rest[start + 2 + end + 2..];
+    }
+
+    segments.push(Segment::Text(rest));
+    Ok(segments)
+}
+
+/// Letters, digits and underscores, not starting with a digit
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn preview(text: &str) -> String {
+    text.chars().take(20).collect()
+}
+
+/// Variable names used in the body, in order of first use
+pub fn placeholders(body: &str) -> Result<Vec<String>> {
+    let mut names: Vec<String> = Vec::new();
+    for segment in segments(body)? {
+        if let Segment::Variable(name) = segment {
+            if !names.iter().any(|existing| existing == name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Checks that every placeholder is declared once and every default is a valid value
+pub fn validate(body: &str, variables: &[TemplateVariable]) -> Result<()> {
+    for (index, variable) in variables.iter().enumerate() {
+        if !is_identifier(&variable.name) {
+            return Err(PromptHistError::InvalidInput(format!("Invalid variable name: \"{}\"", variable.name)));
+        }
+        if variables[..index].iter().any(|other| other.name == variable.name) {
+            return Err(PromptHistError::InvalidInput(format!("Variable declared twice: {}", variable.name)));
+        }
+        if let Some(default) = &variable.default {
+            check_value(variable, default)?;
+        }
+    }
+
+    for name in placeholders(body)? {
+        if !variables.iter().any(|variable| variable.name == name) {
+            return Err(PromptHistError::InvalidInput(format!("Placeholder {{{{{}}}}} has no variable declared", name)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Fills in the placeholders, falling back to defaults for missing values
+pub fn render(body: &str, variables: &[TemplateVariable], values: &HashMap<String, String>) -> Result<String> {
+    if let Some(unknown) = values.keys().find(|name| !variables.iter().any(|variable| &variable.name == *name)) {
+        return Err(PromptHistError::InvalidInput(format!("Unknown template variable: {}", unknown)));
+    }
+
+    let mut rendered = String::with_capacity(body.len());
+    for segment in segments(body)? {
+        match segment {
+            Segment::Text(text) => rendered.push_str(text),
+            Segment::Variable(name) => {
+                let variable = variables.iter()
+                    .find(|variable| variable.name == name)
+                    .ok_or_else(|| PromptHistError::InvalidInput(format!("Undeclared template variable: {}", name)))?;
+                let value = values.get(name)
+                    .or(variable.default.as_ref())
+                    .ok_or_else(|| PromptHistError::InvalidInput(format!("Missing value for template variable: {}", name)))?;
+
+                check_value(variable, value)?;
+                rendered.push_str(value);
+            }
+        }
+    }
+
+    Ok(rendered)
+}
+
+/// Replaces `{{name}}` wherever `name` is in `values`; everything else is copied as is.
+///
+/// Unlike `render` this accepts any text, so it works on stored prompts that were never templates.
+pub fn substitute(text: &str, values: &HashMap<String, String>) -> String {
+    let mut substituted = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("{{") {
+        substituted.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+
+        match after.find("}}").and_then(|end| values.get(after[..end].trim()).map(|value| (end, value))) {
+            Some((end, value)) => {
+                substituted.push_str(value);
+                rest = &after[end + 2..];
+            }
+            None => {
+                substituted.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+
+    substituted.push_str(rest);
+    substituted
+}
+
+fn check_value(variable: &TemplateVariable, value: &str) -> Result<()> {
+    let valid_type = match variable.variable_type {
+        VariableType::Text => true,
+        VariableType::Integer => value.trim().parse::<i64>().is_ok(),
+        VariableType::Number => value.trim().parse::<f64>().map(f64::is_finite).unwrap_or(false),
+        VariableType::Boolean => value == "true" || value == "false",
+    };
+    if !valid_type {
+        return Err(PromptHistError::InvalidInput(format!(
+            "Value \"{}\" is not a valid {:?} for {}",
+            value, variable.variable_type, variable.name
+        )));
+    }
+
+    if let Some(allowed) = &variable.allowed_values {
+        if !allowed.iter().any(|allowed| allowed == value) {
+            return Err(PromptHistError::InvalidInput(format!(
+                "Value \"{}\" is not allowed for {}; expected one of {:?}",
+                value, variable.name, allowed
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variable(name: &str, variable_type: VariableType, default: Option<&str>, allowed: Option<&[&str]>) -> TemplateVariable {
+        TemplateVariable {
+            name: name.to_string(),
+            variable_type,
+            default: default.map(str::to_string),
+            allowed_values: allowed.map(|values| values.iter().map(|v| v.to_string()).collect()),
+            description: None,
+        }
+    }
+
+    #[test]
+    fn test_placeholders_and_validation() {
+        let body = "Review this {{ language }} code for {{concern}}. Keep {{language}} idioms. \\{{literal}}";
+        assert_eq!(placeholders(body).unwrap(), vec!["language", "concern"]);
+
+        assert!(placeholders("Broken {{language").is_err());
+        assert!(placeholders("Bad {{two words}}").is_err());
+
+        let language = variable("language", VariableType::Text, None, Some(&["Rust", "Go"]));
+        assert!(validate(body, &[language]).is_err());
+        assert!(validate("{{n}}", &[variable("n", VariableType::Integer, Some("many"), None)]).is_err());
+    }
+
+    #[test]
+    fn test_render_uses_values_defaults_and_types() {
+        let body = "Review this {{language}} code for {{concern}} in {{max_items}} points. \\{{raw}}";
+        let variables = vec![
+            variable("language", VariableType::Text, None, Some(&["Rust", "Go"])),
+            variable("concern", VariableType::Text, Some("security"), None),
+            variable("max_items", VariableType::Integer, Some("5"), None),
+        ];
+        validate(body, &variables).unwrap();
+
+        let values = HashMap::from([("language".to_string(), "Rust".to_string())]);
+        assert_eq!(
+            render(body, &variables, &values).unwrap(),
+            "Review this Rust code for security in 5 points. {{raw}}"
+        );
+
+        let wrong_choice = HashMap::from([("language".to_string(), "COBOL".to_string())]);
+        assert!(render(body, &variables, &wrong_choice).is_err());
+        let wrong_type = HashMap::from([
+            ("language".to_string(), "Go".to_string()),
+            ("max_items".to_string(), "a few".to_string()),
+        ]);
+        assert!(render(body, &variables, &wrong_type).is_err());
+        assert!(render(body, &variables, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_substitute_leaves_unknown_placeholders() {
+        let values = HashMap::from([("summary".to_string(), "three bullets".to_string())]);
+        assert_eq!(
+            substitute("Expand {{ summary }} for {{audience}} using {{ user.name }}", &values),
+            "Expand three bullets for {{audience}} using {{ user.name }}"
+        );
+    }
+}
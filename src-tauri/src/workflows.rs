@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use chrono::Utc;
+
+use crate::models::{
+    OllamaRequest, PromptEntry, PromptHistError, Result, StepSource, Workflow, WorkflowRun, WorkflowRunStep,
+};
+use crate::ollama::OllamaClient;
+use crate::prompt_storage::PromptDatabase;
+use crate::templates;
+
+/// Runs every step of a workflow in order against Ollama.
+///
+/// Each prompt sent is saved to history and linked to the run. A failing step stops the
+/// run and marks it failed; the returned run then carries the error and the steps that finished.
+pub async fn run_workflow(db: &PromptDatabase, client: &OllamaClient, workflow_id: &str, model: &str) -> Result<WorkflowRun> {
+    let workflow = db.get_workflow(workflow_id).await?
+        .ok_or_else(|| PromptHistError::InvalidInput(format!("Workflow not found: {}", workflow_id)))?;
+
+    let run_id = db.start_workflow_run(&workflow.id, model).await?;
+    println!("[WORKFLOW] Started run {} of \"{}\" with {} steps", run_id, workflow.name, workflow.steps.len());
+
+    let error = execute_steps(db, client, &workflow, &run_id, model).await.err().map(|e| e.to_string());
+    match &error {
+        Some(error) => println!("[WORKFLOW] Run {} failed: {}", run_id, error),
+        None => println!("[WORKFLOW] Run {} completed", run_id),
+    }
+    db.finish_workflow_run(&run_id, error.as_deref()).await?;
+
+    db.get_workflow_run(&run_id).await?
+        .ok_or_else(|| PromptHistError::InvalidInput(format!("Workflow run not found: {}", run_id)))
+}
+
+async fn execute_steps(db: &PromptDatabase, client: &OllamaClient, workflow: &Workflow, run_id: &str, model: &str) -> Result<()> {
+    // Outputs of earlier steps by their output variable
+    let mut outputs: HashMap<String, String> = HashMap::new();
+
+    for (index, step) in workflow.steps.iter().enumerate() {
+        let (content, template_id) = match &step.source {
+            StepSource::Prompt { prompt_id } => {
+                let prompt = db.get_prompt_by_id(prompt_id).await?
+                    .ok_or_else(|| PromptHistError::InvalidInput(format!("Prompt not found: {}", prompt_id)))?;
+                (templates::substitute(&prompt.content, &outputs), None)
+            }
+            StepSource::Template { template_id, values } => {
+                let template = db.get_template(template_id).await?
+                    .ok_or_else(|| PromptHistError::InvalidInput(format!("Template not found: {}", template_id)))?;
+
+                // Outputs fill variables of the same name; explicit values win and may reference outputs
+                let mut inputs: HashMap<String, String> = outputs.iter()
+                    .filter(|(name, _)| template.variables.iter().any(|variable| &variable.name == *name))
+                    .map(|(name, output)| (name.clone(), output.clone()))
+                    .collect();
+                for (name, value) in values {
+                    inputs.insert(name.clone(), templates::substitute(value, &outputs));
+                }
+
+                (templates::render(&template.body, &template.variables, &inputs)?, Some(template.id))
+            }
+        };
+
+        let step_model = step.model.clone().unwrap_or_else(|| model.to_string());
+        let request = OllamaRequest { model: step_model.clone(), prompt: content.clone(), stream: false };
+        let started = Instant::now();
+        let response = client.generate(&request).await?;
+        let duration_ms = started.elapsed().as_millis() as i64;
+
+        let entry = PromptEntry {
+            id: uuid::Uuid::new_v4().to_string(),
+            content,
+            application: "workflow".to_string(),
+            timestamp: Utc::now(),
+            starred: false,
+            tags: vec![],
+            usage_count: 0,
+            is_encrypted: false,
+            last_used: None,
+            template_id,
+            session_id: None,
+            window_title: None,
+        };
+        let prompt_id = db.save_prompt(&entry).await?;
+        db.save_response(&prompt_id, &response, duration_ms).await?;
+
+        db.record_workflow_step(run_id, &WorkflowRunStep {
+            step_index: index as i64,
+            prompt_id: Some(prompt_id),
+            model: step_model,
+            output: response.response.clone(),
+            duration_ms,
+        })
+        .await?;
+        println!("[WORKFLOW] Step {} of run {} finished in {}ms", index + 1, run_id, duration_ms);
+
+        if let Some(variable) = &step.output_variable {
+            outputs.insert(variable.clone(), response.response.trim().to_string());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{RunStatus, TemplateVariable, VariableType, WorkflowStep};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Fake Ollama server answering every generate request with "echo: <prompt>"
+    async fn echo_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 4096];
+                let body = loop {
+                    let read = socket.read(&mut buffer).await.unwrap();
+                    if read == 0 {
+                        return;
+                    }
+                    request.extend_from_slice(&buffer[..read]);
+
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some(header_end) = text.find("\r\n\r\n") {
+                        let length: usize = text[..header_end].lines()
+                            .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap()))
+                            .unwrap_or(0);
+                        if request.len() >= header_end + 4 + length {
+                            break request[header_end + 4..header_end + 4 + length].to_vec();
+                        }
+                    }
+                };
+
+                let request: OllamaRequest = serde_json::from_slice(&body).unwrap();
+                let response = serde_json::json!({
+                    "response": format!("echo: {}", request.prompt),
+                    "done": true,
+                    "model": request.model,
+                    "created_at": "2024-01-01T00:00:00Z",
+                })
+                .to_string();
+                let reply = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    response.len(),
+                    response
+                );
+                socket.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn test_run_passes_outputs_between_steps() {
+        let db = PromptDatabase::new_in_memory(None).await.unwrap();
+        let client = OllamaClient::new(&echo_server().await);
+
+        let topic = TemplateVariable {
+            name: "topic".to_string(),
+            variable_type: VariableType::Text,
+            default: None,
+            allowed_values: None,
+            description: None,
+        };
+        let template = db.create_template("Summary", None, "Summarize {{topic}}", &[topic]).await.unwrap();
+        let prompt = PromptEntry {
+            id: "translate".to_string(),
+            content: "Translate: {{summary}}".to_string(),
+            application: "test".to_string(),
+            timestamp: Utc::now(),
+            starred: false,
+            tags: vec![],
+            usage_count: 0,
+            is_encrypted: false,
+            last_used: None,
+            template_id: None,
+            session_id: None,
+            window_title: None,
+        };
+        db.save_prompt(&prompt).await.unwrap();
+
+        let steps = vec![
+            WorkflowStep {
+                source: StepSource::Template {
+                    template_id: template.id.clone(),
+                    values: HashMap::from([("topic".to_string(), "Rust".to_string())]),
+                },
+                model: Some("small".to_string()),
+                output_variable: Some("summary".to_string()),
+            },
+            WorkflowStep {
+                source: StepSource::Prompt { prompt_id: "translate".to_string() },
+                model: None,
+                output_variable: None,
+            },
+        ];
+        assert!(db.create_workflow("Empty", None, &[]).await.is_err());
+        let workflow = db.create_workflow("Summarize and translate", None, &steps).await.unwrap();
+
+        let run = run_workflow(&db, &client, &workflow.id, "large").await.unwrap();
+        assert_eq!(run.status, RunStatus::Completed);
+        assert_eq!(run.steps.len(), 2);
+        assert_eq!(run.steps[0].model, "small");
+        assert_eq!(run.steps[1].model, "large");
+        assert_eq!(run.steps[1].output, "echo: Translate: echo: Summarize Rust");
+
+        let sent = db.get_prompt_by_id(run.steps[1].prompt_id.as_ref().unwrap()).await.unwrap().unwrap();
+        assert_eq!(sent.application, "workflow");
+        assert_eq!(sent.content, "Translate: echo: Summarize Rust");
+        assert_eq!(db.get_template_prompts(&template.id).await.unwrap().len(), 1);
+
+        let sent = db.get_prompt_with_responses(&sent.id).await.unwrap().unwrap();
+        assert_eq!(sent.responses.len(), 1);
+        assert_eq!(sent.responses[0].response, run.steps[1].output);
+
+        // A missing prompt fails the run after the steps before it were stored
+        let broken = vec![
+            steps[0].clone(),
+            WorkflowStep { source: StepSource::Prompt { prompt_id: "gone".to_string() }, model: None, output_variable: None },
+        ];
+        let workflow = db.update_workflow(&workflow.id, "Broken", None, &broken).await.unwrap();
+        let run = run_workflow(&db, &client, &workflow.id, "large").await.unwrap();
+        assert_eq!(run.status, RunStatus::Failed);
+        assert_eq!(run.steps.len(), 1);
+        assert!(run.error.unwrap().contains("gone"));
+        assert_eq!(db.list_workflow_runs(&workflow.id).await.unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use crate::capture::{ActiveWindow, BrowserTab, WindowSource};
+use crate::clipboard_linux::{on_path, run};
+use crate::models::Result;
+
+/// Focused window on X11, read through `xprop` from the window manager's `_NET_ACTIVE_WINDOW`.
+///
+/// Under XWayland only X11 clients are visible, so native Wayland windows are not reported.
+pub struct X11Windows;
+
+impl X11Windows {
+    /// None when there is no X display or xprop isn't installed
+    pub fn detect() -> Option<Self> {
+        (std::env::var_os("DISPLAY").is_some() && on_path("xprop")).then_some(Self)
+    }
+}
+
+impl WindowSource for X11Windows {
+    fn browser_tabs(&mut self) -> Result<Vec<BrowserTab>> {
+        // Browsers on Linux don't expose their tabs to other processes
+        Ok(vec![])
+    }
+
+    fn active_window(&mut self) -> Result<Option<ActiveWindow>> {
+        let Some(root) = run("xprop", &["-root", "_NET_ACTIVE_WINDOW"])? else {
+            return Ok(None);
+        };
+        let Some(window_id) = active_window_id(&String::from_utf8_lossy(&root)) else {
+            return Ok(None);
+        };
+
+        let properties = run("xprop", &["-id", &window_id, "_NET_WM_NAME", "WM_NAME", "WM_CLASS", "_NET_WM_PID"])?;
+        Ok(properties.and_then(|output| parse_window(&String::from_utf8_lossy(&output))))
+    }
+}
+
+/// Window id from `xprop -root _NET_ACTIVE_WINDOW`; None when no window has focus
+fn active_window_id(output: &str) -> Option<String> {
+    let id = output.split('#').nth(1)?.split(|c: char| c == ',' || c.is_whitespace()).find(|part| !part.is_empty())?;
+    let value = u64::from_str_radix(id.strip_prefix("0x")?, 16).ok()?;
+    (value != 0).then(|| id.to_string())
+}
+
+/// Title, class and pid from `xprop -id <window>` output
+fn parse_window(output: &str) -> Option<ActiveWindow> {
+    let properties: HashMap<&str, &str> = output
+        .lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once(" = ")?;
+            Some((name.split('(').next()?.trim(), value.trim()))
+        })
+        .collect();
+
+    let title = ["_NET_WM_NAME", "WM_NAME"]
+        .iter()
+        .find_map(|name| properties.get(name).and_then(|value| quoted_strings(value).into_iter().next()))
+        .unwrap_or_default();
+    // WM_CLASS holds the instance name and then the class, like "Navigator", "firefox"
+    let class = properties.get("WM_CLASS").and_then(|value| quoted_strings(value).pop());
+    let pid = properties.get("_NET_WM_PID").and_then(|value| value.parse().ok());
+
+    if class.is_none() && title.is_empty() {
+        return None;
+    }
+    Some(ActiveWindow { process_name: class.unwrap_or_default(), title, pid })
+}
+
+/// The double-quoted strings of an xprop value, with backslash escapes undone
+fn quoted_strings(value: &str) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut current: Option<String> = None;
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        match (&mut current, c) {
+            (None, '"') => current = Some(String::new()),
+            (None, _) => {}
+            (Some(_), '"') => strings.extend(current.take()),
+            (Some(text), '\\') => text.extend(chars.next()),
+            (Some(text), c) => text.push(c),
+        }
+    }
+
+    strings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_active_window_id_skips_unfocused_roots() {
+        assert_eq!(active_window_id("_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007\n"), Some("0x3a00007".to_string()));
+        assert_eq!(active_window_id("_NET_ACTIVE_WINDOW(WINDOW): window id # 0x0\n"), None);
+        assert_eq!(active_window_id("_NET_ACTIVE_WINDOW:  not found.\n"), None);
+    }
+
+    #[test]
+    fn test_parse_window_reads_title_class_and_pid() {
+        let window = parse_window(concat!(
+            "_NET_WM_NAME(UTF8_STRING) = \"Rust help - Claude \\\"beta\\\" — Mozilla Firefox\"\n",
+            "WM_NAME(STRING) = \"Rust help\"\n",
+            "WM_CLASS(STRING) = \"Navigator\", \"firefox\"\n",
+            "_NET_WM_PID(CARDINAL) = 4242\n",
+        ))
+        .unwrap();
+        assert_eq!(window.process_name, "firefox");
+        assert_eq!(window.title, "Rust help - Claude \"beta\" — Mozilla Firefox");
+        assert_eq!(window.pid, Some(4242));
+
+        let window = parse_window("_NET_WM_NAME:  not found.\nWM_NAME(STRING) = \"xterm\"\nWM_CLASS(STRING) = \"xterm\", \"XTerm\"\n_NET_WM_PID:  not found.\n").unwrap();
+        assert_eq!((window.title.as_str(), window.pid), ("xterm", None));
+        assert_eq!(parse_window("WM_CLASS:  not found.\n"), None);
+    }
+}
@@ -61,6 +61,9 @@ export default function DashboardPage() {
     type: 'success' | 'error';
     message: string;
   } | null>(null);
+  const [encryptionWarning, setEncryptionWarning] = useState<string | null>(
+    null
+  );
 
   useEffect(() => {
     loadPrompts();
@@ -68,6 +71,10 @@ export default function DashboardPage() {
     checkMonitoringStatus();
   }, [filter]);
 
+  useEffect(() => {
+    checkEncryptionWarning();
+  }, []);
+
   const loadPrompts = async () => {
     try {
       setLoading(true);
@@ -102,6 +109,15 @@ export default function DashboardPage() {
     }
   };
 
+  const checkEncryptionWarning = async () => {
+    try {
+      const result = await invoke<string | null>('get_encryption_warning');
+      setEncryptionWarning(result);
+    } catch (error) {
+      console.error('Failed to check encryption status:', error);
+    }
+  };
+
   const toggleMonitoring = async () => {
     try {
       if (isMonitoring) {
@@ -277,6 +293,13 @@ export default function DashboardPage() {
       )}
 
       <div className='mx-auto max-w-7xl px-4 py-6 sm:px-6 lg:px-8'>
+        {encryptionWarning && (
+          <div className='mb-6 flex items-center space-x-2 rounded-lg border border-red-500/40 bg-red-600/20 px-4 py-3 text-red-200'>
+            <AlertCircle className='h-5 w-5 flex-shrink-0' />
+            <span>{encryptionWarning}</span>
+          </div>
+        )}
+
         {/* Stats Cards */}
         {stats && (
           <div className='mb-6 grid grid-cols-1 gap-6 sm:grid-cols-2 lg:grid-cols-4 animate-slide-in-left'>
@@ -44,6 +44,8 @@ export default function SettingsPage() {
     'Claude',
     'Cursor',
     'Ollama',
+    'LM Studio',
+    'VS Code',
     'Perplexity',
     'Gemini',
     'Copilot',